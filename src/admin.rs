@@ -0,0 +1,137 @@
+//! Admin endpoints
+//! # Note
+//! Enabled only when `admin.token` is set. Requests send it as `Authorization: Bearer <token>`.
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, post, web, web::Data, HttpResponse};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use crate::config::AdminConfig;
+use crate::dates::parse_date;
+use crate::state::AppState;
+use crate::support::admin_token::AdminToken;
+use crate::usage::{to_csv, LedgerQuery, UsageReport};
+
+/// Summary of the configuration after a reload.
+#[derive(Serialize, Debug)]
+struct ReloadResponse {
+    personas: Vec<String>,
+    allowed_users: usize,
+    allowed_groups: usize,
+}
+
+/// Reloads the non-secret configuration. An invalid configuration is rejected with
+/// `400 Bad Request` and the running one is kept.
+#[post("/admin/reload")]
+pub async fn reload(token: AdminToken, state: Data<AppState>) -> HttpResponse {
+    if let Some(res) = reject(&state.admin, &token) {
+        return res;
+    }
+    match web::block(move || state.live.reload()).await {
+        Ok(Ok(runtime)) => {
+            info!("Reloaded configuration");
+            HttpResponse::Ok().json(ReloadResponse {
+                personas: runtime
+                    .personas
+                    .as_ref()
+                    .map(|p| p.names())
+                    .unwrap_or_default(),
+                allowed_users: runtime.access.allowed_users.len(),
+                allowed_groups: runtime.access.allowed_groups.len(),
+            })
+        }
+        Ok(Err(e)) => {
+            error!("Rejected configuration change: {}", e);
+            HttpResponse::BadRequest().json(json!({ "error": e.to_string() }))
+        }
+        Err(e) => {
+            error!("Error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Filters of `GET /admin/usage`.
+#[derive(Deserialize, Debug)]
+pub struct UsageParams {
+    /// First day included, `YYYY-MM-DD` in UTC.
+    from: Option<String>,
+    /// Last day included, `YYYY-MM-DD` in UTC.
+    to: Option<String>,
+    /// User, group or room id.
+    chat: Option<String>,
+    /// `json` (the default) for totals, `csv` for one row per completion.
+    format: Option<String>,
+}
+
+/// Usage recorded in the ledger, e.g.
+/// `GET /admin/usage?from=2024-01-01&to=2024-01-31&format=csv` for a monthly bill.
+#[get("/admin/usage")]
+pub async fn usage(
+    token: AdminToken,
+    state: Data<AppState>,
+    params: web::Query<UsageParams>,
+) -> HttpResponse {
+    if let Some(res) = reject(&state.admin, &token) {
+        return res;
+    }
+    let day = |date: &Option<String>, key: &str| match date {
+        Some(date) => match parse_date(date) {
+            Some(secs) => Ok(Some(secs as i64 * 1000)),
+            None => Err(format!("`{key}` must be a YYYY-MM-DD date")),
+        },
+        None => Ok(None),
+    };
+    let (from, to) = match (day(&params.from, "from"), day(&params.to, "to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    let query = LedgerQuery {
+        from,
+        // The whole last day
+        to: to.map(|to| to + 24 * 60 * 60 * 1000),
+        chat_id: params.chat.clone(),
+        ..Default::default()
+    };
+    let entries = match state.usage.query(&query).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed reading usage: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    match params.format.as_deref().unwrap_or("json") {
+        "json" => HttpResponse::Ok().json(UsageReport::new(&entries)),
+        "csv" => {
+            let name = format!(
+                "usage-{}-{}.csv",
+                params.from.as_deref().unwrap_or("start"),
+                params.to.as_deref().unwrap_or("now")
+            );
+            HttpResponse::Ok()
+                .content_type("text/csv; charset=utf-8")
+                .insert_header(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename(name)],
+                })
+                .body(to_csv(&entries))
+        }
+        other => HttpResponse::BadRequest()
+            .json(json!({ "error": format!("unknown format `{other}`, use json or csv") })),
+    }
+}
+
+/// The response to send when the token is not the admin token, `None` if it is.
+pub fn reject(admin: &AdminConfig, token: &AdminToken) -> Option<HttpResponse> {
+    let Some(expected) = &admin.token else {
+        return Some(HttpResponse::NotFound().finish());
+    };
+    // Comparing digests keeps the comparison time independent of the token
+    if Sha256::digest(expected.as_bytes()) == Sha256::digest(token.token.as_bytes()) {
+        None
+    } else {
+        Some(HttpResponse::Unauthorized().finish())
+    }
+}
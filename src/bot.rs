@@ -3,13 +3,18 @@ use reqwest::{Error, Response};
 use serde_json::{json, Value};
 
 use crate::client::HttpClient;
+use crate::content::{ContentError, MessageContent, MAX_CONTENT_SIZE};
 use crate::messages::SendMessageType;
+use crate::metrics::BotMetrics;
+use crate::objects::rich_menu::{RichMenuAliasList, RichMenuId, RichMenuList};
+use crate::objects::{
+    BotInfo, Profile, RichMenu, RichMenuAlias, RichMenuResponse, TranscodingStatus,
+};
 
 /// LineBot Client
 #[derive(Debug)]
 pub struct LineBot {
     pub channel_secret: String,
-    pub channel_token: String,
     pub http_client: HttpClient,
 }
 
@@ -22,20 +27,48 @@ impl LineBot {
     pub fn new(channel_secret: &str, channel_token: &str) -> LineBot {
         LineBot {
             channel_secret: String::from(channel_secret),
-            channel_token: String::from(channel_token),
             http_client: HttpClient::new(channel_token),
         }
     }
 
     /// # Note
-    /// Send reply message. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#send-reply-message)
+    /// Instantiate a LineBot that talks to other API hosts, e.g. a local mock.
     /// ```
-    /// let res: Result<Response, Error> = bot.reply_message("xxxxxxxxx", vec![...]);
+    /// let bot = LineBot::with_base_urls("<channel secret>", "<channel access token>", "http://localhost:8081/v2/bot", "http://localhost:8081/v2/bot");
     /// ```
-    pub async fn reply_message(
+    #[cfg(test)]
+    pub fn with_base_urls(
+        channel_secret: &str,
+        channel_token: &str,
+        base_url: &str,
+        data_base_url: &str,
+    ) -> LineBot {
+        LineBot {
+            channel_secret: String::from(channel_secret),
+            http_client: HttpClient::with_base_urls(channel_token, base_url, data_base_url),
+        }
+    }
+
+    /// # Note
+    /// Count the failed LINE API calls in `metrics`.
+    /// ```
+    /// let bot = LineBot::new("<channel secret>", "<channel access token>").with_metrics(BotMetrics::global());
+    /// ```
+    pub fn with_metrics(mut self, metrics: BotMetrics) -> LineBot {
+        self.http_client = self.http_client.with_metrics(metrics);
+        self
+    }
+
+    /// # Note
+    /// Send reply message, carrying the given trace context. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#send-reply-message)
+    /// ```
+    /// let res: Result<Response, Error> = bot.reply_message_with_context("xxxxxxxxx", vec![...], Context::current());
+    /// ```
+    pub async fn reply_message_with_context(
         &self,
         reply_token: &str,
         msgs: Vec<SendMessageType>,
+        context: Context,
     ) -> Result<Response, Error> {
         let data: Value = json!(
                 {
@@ -43,27 +76,426 @@ impl LineBot {
                 "messages": msgs,
                 }
         );
-        self.http_client.post("/message/reply", data).await
+        self.http_client
+            .post_with_context("/message/reply", data, context)
+            .await
     }
+
     /// # Note
-    /// Send reply message. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#send-reply-message)
+    /// Send push message. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#send-push-message)
     /// ```
-    /// let res: Result<Response, Error> = bot.reply_message("xxxxxxxxx", vec![...]);
+    /// let res: Result<Response, Error> = bot.push_message("Uxxx", vec![...]);
     /// ```
-    pub async fn reply_message_with_context(
+    pub async fn push_message(
         &self,
-        reply_token: &str,
+        to: &str,
         msgs: Vec<SendMessageType>,
-        context: Context,
     ) -> Result<Response, Error> {
         let data: Value = json!(
                 {
-                "replyToken": reply_token,
+                "to": to,
                 "messages": msgs,
                 }
         );
+        self.http_client.post("/message/push", data).await
+    }
+
+    /// # Note
+    /// Get bot info. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-bot-info)
+    /// ```
+    /// let res: Result<BotInfo, Error> = bot.get_bot_info().await;
+    /// ```
+    pub async fn get_bot_info(&self) -> Result<BotInfo, Error> {
+        self.http_client
+            .get("/info", vec![], json!({}))
+            .await?
+            .error_for_status()?
+            .json::<BotInfo>()
+            .await
+    }
+
+    /// # Note
+    /// Get profile. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-profile)
+    /// ```
+    /// let res: Result<Profile, Error> = bot.get_profile("Uxxx").await;
+    /// ```
+    pub async fn get_profile(&self, user_id: &str) -> Result<Profile, Error> {
+        self.http_client
+            .get(&format!("/profile/{user_id}"), vec![], json!({}))
+            .await?
+            .error_for_status()?
+            .json::<Profile>()
+            .await
+    }
+
+    /// # Note
+    /// Get group chat member profile. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-group-member-profile)
+    /// ```
+    /// let res: Result<Profile, Error> = bot.get_group_member_profile("Cxxx", "Uxxx").await;
+    /// ```
+    pub async fn get_group_member_profile(
+        &self,
+        group_id: &str,
+        user_id: &str,
+    ) -> Result<Profile, Error> {
         self.http_client
-            .post_with_context("/message/reply", data, context.to_owned())
+            .get(
+                &format!("/group/{group_id}/member/{user_id}"),
+                vec![],
+                json!({}),
+            )
+            .await?
+            .error_for_status()?
+            .json::<Profile>()
             .await
     }
+
+    /// # Note
+    /// Get multi-person chat member profile. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-room-member-profile)
+    /// ```
+    /// let res: Result<Profile, Error> = bot.get_room_member_profile("Rxxx", "Uxxx").await;
+    /// ```
+    pub async fn get_room_member_profile(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Profile, Error> {
+        self.http_client
+            .get(
+                &format!("/room/{room_id}/member/{user_id}"),
+                vec![],
+                json!({}),
+            )
+            .await?
+            .error_for_status()?
+            .json::<Profile>()
+            .await
+    }
+
+    /// # Note
+    /// Display a loading animation in a one-on-one chat. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#display-a-loading-indicator)
+    /// `loading_seconds` must be a multiple of 5 between 5 and 60.
+    /// ```
+    /// let res: Result<Response, Error> = bot.show_loading_animation("Uxxx", 20).await;
+    /// ```
+    pub async fn show_loading_animation(
+        &self,
+        chat_id: &str,
+        loading_seconds: u64,
+    ) -> Result<Response, Error> {
+        let data: Value = json!(
+                {
+                "chatId": chat_id,
+                "loadingSeconds": loading_seconds,
+                }
+        );
+        self.http_client.post("/chat/loading/start", data).await
+    }
+
+    /// # Note
+    /// Get content sent by a user as a stream. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-content)
+    /// ```
+    /// let content: MessageContent = bot.get_message_content("xxxxxxxxx").await?;
+    /// content.save_to("/tmp/xxxxxxxxx.pdf").await?;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn get_message_content(
+        &self,
+        message_id: &str,
+    ) -> Result<MessageContent, ContentError> {
+        self.get_message_content_with_limit(message_id, MAX_CONTENT_SIZE)
+            .await
+    }
+
+    /// # Note
+    /// Same as `get_message_content`, rejecting content larger than `limit` bytes.
+    /// ```
+    /// let content = bot.get_message_content_with_limit("xxxxxxxxx", 10 * 1024 * 1024).await?;
+    /// ```
+    pub async fn get_message_content_with_limit(
+        &self,
+        message_id: &str,
+        limit: u64,
+    ) -> Result<MessageContent, ContentError> {
+        let res = self
+            .http_client
+            .get_data(&format!("/message/{message_id}/content"), vec![], json!({}))
+            .await?;
+        MessageContent::new(res, limit)
+    }
+
+    /// # Note
+    /// Verify the preparation status of a video or audio for getting. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#verify-video-or-audio-preparation-status)
+    /// ```
+    /// let res: Result<TranscodingStatus, Error> = bot.get_content_transcoding_status("xxxxxxxxx").await;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn get_content_transcoding_status(
+        &self,
+        message_id: &str,
+    ) -> Result<TranscodingStatus, Error> {
+        self.http_client
+            .get_data(
+                &format!("/message/{message_id}/content/transcoding"),
+                vec![],
+                json!({}),
+            )
+            .await?
+            .error_for_status()?
+            .json::<TranscodingStatus>()
+            .await
+    }
+
+    /// # Note
+    /// Create rich menu. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#create-rich-menu)
+    /// ```
+    /// let rich_menu_id: String = bot.create_rich_menu(&rich_menu).await?;
+    /// ```
+    pub async fn create_rich_menu(&self, rich_menu: &RichMenu) -> Result<String, Error> {
+        let res = self
+            .http_client
+            .post("/richmenu", json!(rich_menu))
+            .await?
+            .error_for_status()?
+            .json::<RichMenuId>()
+            .await?;
+        Ok(res.rich_menu_id)
+    }
+
+    /// # Note
+    /// Get rich menu. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-rich-menu)
+    /// ```
+    /// let res: Result<RichMenuResponse, Error> = bot.get_rich_menu("richmenu-xxx").await;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn get_rich_menu(&self, rich_menu_id: &str) -> Result<RichMenuResponse, Error> {
+        self.http_client
+            .get(&format!("/richmenu/{rich_menu_id}"), vec![], json!({}))
+            .await?
+            .error_for_status()?
+            .json::<RichMenuResponse>()
+            .await
+    }
+
+    /// # Note
+    /// Get rich menu list. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-rich-menu-list)
+    /// ```
+    /// let res: Result<Vec<RichMenuResponse>, Error> = bot.get_rich_menu_list().await;
+    /// ```
+    pub async fn get_rich_menu_list(&self) -> Result<Vec<RichMenuResponse>, Error> {
+        let res = self
+            .http_client
+            .get("/richmenu/list", vec![], json!({}))
+            .await?
+            .error_for_status()?
+            .json::<RichMenuList>()
+            .await?;
+        Ok(res.richmenus)
+    }
+
+    /// # Note
+    /// Delete rich menu. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#delete-rich-menu)
+    /// ```
+    /// let res: Result<Response, Error> = bot.delete_rich_menu("richmenu-xxx").await;
+    /// ```
+    pub async fn delete_rich_menu(&self, rich_menu_id: &str) -> Result<Response, Error> {
+        self.http_client
+            .delete(&format!("/richmenu/{rich_menu_id}"), json!({}))
+            .await
+    }
+
+    /// # Note
+    /// Upload rich menu image (`image/png` or `image/jpeg`). [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#upload-rich-menu-image)
+    /// ```
+    /// let res: Result<Response, Error> = bot.upload_rich_menu_image("richmenu-xxx", "image/png", bytes).await;
+    /// ```
+    pub async fn upload_rich_menu_image(
+        &self,
+        rich_menu_id: &str,
+        content_type: &str,
+        image: Vec<u8>,
+    ) -> Result<Response, Error> {
+        self.http_client
+            .post_data(
+                &format!("/richmenu/{rich_menu_id}/content"),
+                content_type,
+                image,
+            )
+            .await
+    }
+
+    /// # Note
+    /// Set default rich menu. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#set-default-rich-menu)
+    /// ```
+    /// let res: Result<Response, Error> = bot.set_default_rich_menu("richmenu-xxx").await;
+    /// ```
+    pub async fn set_default_rich_menu(&self, rich_menu_id: &str) -> Result<Response, Error> {
+        self.http_client
+            .post(&format!("/user/all/richmenu/{rich_menu_id}"), json!({}))
+            .await
+    }
+
+    /// # Note
+    /// Get default rich menu ID. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-default-rich-menu-id)
+    /// ```
+    /// let res: Result<String, Error> = bot.get_default_rich_menu_id().await;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn get_default_rich_menu_id(&self) -> Result<String, Error> {
+        let res = self
+            .http_client
+            .get("/user/all/richmenu", vec![], json!({}))
+            .await?
+            .error_for_status()?
+            .json::<RichMenuId>()
+            .await?;
+        Ok(res.rich_menu_id)
+    }
+
+    /// # Note
+    /// Clear default rich menu. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#clear-default-rich-menu)
+    /// ```
+    /// let res: Result<Response, Error> = bot.cancel_default_rich_menu().await;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn cancel_default_rich_menu(&self) -> Result<Response, Error> {
+        self.http_client
+            .delete("/user/all/richmenu", json!({}))
+            .await
+    }
+
+    /// # Note
+    /// Link rich menu to user. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#link-rich-menu-to-user)
+    /// ```
+    /// let res: Result<Response, Error> = bot.link_rich_menu_to_user("Uxxx", "richmenu-xxx").await;
+    /// ```
+    pub async fn link_rich_menu_to_user(
+        &self,
+        user_id: &str,
+        rich_menu_id: &str,
+    ) -> Result<Response, Error> {
+        self.http_client
+            .post(
+                &format!("/user/{user_id}/richmenu/{rich_menu_id}"),
+                json!({}),
+            )
+            .await
+    }
+
+    /// # Note
+    /// Unlink rich menu from user. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#unlink-rich-menu-from-user)
+    /// ```
+    /// let res: Result<Response, Error> = bot.unlink_rich_menu_from_user("Uxxx").await;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn unlink_rich_menu_from_user(&self, user_id: &str) -> Result<Response, Error> {
+        self.http_client
+            .delete(&format!("/user/{user_id}/richmenu"), json!({}))
+            .await
+    }
+
+    /// # Note
+    /// Get rich menu ID of user. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-rich-menu-id-of-user)
+    /// ```
+    /// let res: Result<String, Error> = bot.get_rich_menu_id_of_user("Uxxx").await;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn get_rich_menu_id_of_user(&self, user_id: &str) -> Result<String, Error> {
+        let res = self
+            .http_client
+            .get(&format!("/user/{user_id}/richmenu"), vec![], json!({}))
+            .await?
+            .error_for_status()?
+            .json::<RichMenuId>()
+            .await?;
+        Ok(res.rich_menu_id)
+    }
+
+    /// # Note
+    /// Create rich menu alias. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#create-rich-menu-alias)
+    /// ```
+    /// let res: Result<Response, Error> = bot.create_rich_menu_alias("translate", "richmenu-xxx").await;
+    /// ```
+    pub async fn create_rich_menu_alias(
+        &self,
+        rich_menu_alias_id: &str,
+        rich_menu_id: &str,
+    ) -> Result<Response, Error> {
+        let data: Value = json!(RichMenuAlias {
+            rich_menu_alias_id: rich_menu_alias_id.to_string(),
+            rich_menu_id: rich_menu_id.to_string(),
+        });
+        self.http_client.post("/richmenu/alias", data).await
+    }
+
+    /// # Note
+    /// Update rich menu alias. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#update-rich-menu-alias)
+    /// ```
+    /// let res: Result<Response, Error> = bot.update_rich_menu_alias("translate", "richmenu-xxx").await;
+    /// ```
+    pub async fn update_rich_menu_alias(
+        &self,
+        rich_menu_alias_id: &str,
+        rich_menu_id: &str,
+    ) -> Result<Response, Error> {
+        self.http_client
+            .post(
+                &format!("/richmenu/alias/{rich_menu_alias_id}"),
+                json!({ "richMenuId": rich_menu_id }),
+            )
+            .await
+    }
+
+    /// # Note
+    /// Delete rich menu alias. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#delete-rich-menu-alias)
+    /// ```
+    /// let res: Result<Response, Error> = bot.delete_rich_menu_alias("translate").await;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn delete_rich_menu_alias(
+        &self,
+        rich_menu_alias_id: &str,
+    ) -> Result<Response, Error> {
+        self.http_client
+            .delete(&format!("/richmenu/alias/{rich_menu_alias_id}"), json!({}))
+            .await
+    }
+
+    /// # Note
+    /// Get rich menu alias information. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-rich-menu-alias-by-id)
+    /// ```
+    /// let res: Result<RichMenuAlias, Error> = bot.get_rich_menu_alias("translate").await;
+    /// ```
+    #[allow(dead_code)]
+    pub async fn get_rich_menu_alias(
+        &self,
+        rich_menu_alias_id: &str,
+    ) -> Result<RichMenuAlias, Error> {
+        self.http_client
+            .get(
+                &format!("/richmenu/alias/{rich_menu_alias_id}"),
+                vec![],
+                json!({}),
+            )
+            .await?
+            .error_for_status()?
+            .json::<RichMenuAlias>()
+            .await
+    }
+
+    /// # Note
+    /// Get list of rich menu alias. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-rich-menu-alias-list)
+    /// ```
+    /// let res: Result<Vec<RichMenuAlias>, Error> = bot.get_rich_menu_alias_list().await;
+    /// ```
+    pub async fn get_rich_menu_alias_list(&self) -> Result<Vec<RichMenuAlias>, Error> {
+        let res = self
+            .http_client
+            .get("/richmenu/alias/list", vec![], json!({}))
+            .await?
+            .error_for_status()?
+            .json::<RichMenuAliasList>()
+            .await?;
+        Ok(res.aliases)
+    }
 }
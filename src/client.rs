@@ -1,11 +1,14 @@
 use opentelemetry::trace::FutureExt;
-use opentelemetry::Context;
-use reqwest::{Client, Error, Response};
+use opentelemetry::{Context, KeyValue};
+use reqwest::{Client, Error, RequestBuilder, Response};
 //use reqwest::blocking::{Client, Response};
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 use reqwest::Url;
 use serde_json::Value;
 
+use crate::metrics::{BotMetrics, HTTP_METHOD};
+use crate::traces;
+
 static BASE_URL: &str = "https://api.line.me/v2/bot";
 static BASEDATA_URL: &str = "https://api-data.line.me/v2/bot";
 
@@ -15,6 +18,7 @@ pub struct HttpClient {
     headers: HeaderMap,
     endpoint_base: String,
     endpoint_base_data: String,
+    metrics: Option<BotMetrics>,
 }
 
 impl HttpClient {
@@ -24,21 +28,76 @@ impl HttpClient {
     /// let http_client = HttpClient::new("<channel secret>");
     /// ```
     pub fn new(channel_token: &str) -> HttpClient {
+        HttpClient::with_base_urls(channel_token, BASE_URL, BASEDATA_URL)
+    }
+
+    /// # Note
+    /// Instantiate a HttpClient for other API hosts, e.g. a local mock.
+    /// ```
+    /// let http_client = HttpClient::with_base_urls("<channel token>", "http://localhost:8081/v2/bot", "http://localhost:8081/v2/bot");
+    /// ```
+    pub fn with_base_urls(
+        channel_token: &str,
+        endpoint_base: &str,
+        endpoint_base_data: &str,
+    ) -> HttpClient {
         let mut headers = HeaderMap::new();
-        if let Ok(v) = format!("Bearer {}", channel_token).parse::<String>() {
-            if let Ok(header_value) = HeaderValue::from_str(&v) {
-                headers.insert(AUTHORIZATION, header_value);
-            }
+        if let Ok(header_value) = HeaderValue::from_str(&format!("Bearer {}", channel_token)) {
+            headers.insert(AUTHORIZATION, header_value);
         }
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         HttpClient {
             client: Client::new(),
             headers,
-            endpoint_base: String::from(BASE_URL),
-            endpoint_base_data: String::from(BASEDATA_URL),
+            endpoint_base: String::from(endpoint_base),
+            endpoint_base_data: String::from(endpoint_base_data),
+            metrics: None,
         }
     }
 
+    /// # Note
+    /// Count the failed calls in `metrics`.
+    /// ```
+    /// let http_client = HttpClient::new("<channel token>").with_metrics(BotMetrics::global());
+    /// ```
+    pub fn with_metrics(mut self, metrics: BotMetrics) -> HttpClient {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sends `request` in a client span, child of the span of `parent`, whose trace
+    /// context goes to LINE with the request.
+    async fn send(&self, request: RequestBuilder, parent: Context) -> Result<Response, Error> {
+        let mut request = request.build()?;
+        let method = request.method().to_string();
+        let cx = traces::client_context(
+            &parent,
+            format!("LINE {method}"),
+            vec![
+                KeyValue::new(HTTP_METHOD, method.clone()),
+                KeyValue::new(traces::URL, request.url().to_string()),
+            ],
+        );
+        traces::inject(&cx, request.headers_mut());
+        let res = self.client.execute(request).with_context(cx.clone()).await;
+        traces::end_http(&cx, &res);
+        self.observe(&method, res)
+    }
+
+    /// Counts a failed call, returning its result unchanged.
+    fn observe(&self, method: &str, res: Result<Response, Error>) -> Result<Response, Error> {
+        if let Some(metrics) = &self.metrics {
+            match &res {
+                Ok(response) if !response.status().is_success() => {
+                    metrics.line_error(method, Some(response.status().as_u16()))
+                }
+                Err(_) => metrics.line_error(method, None),
+                Ok(_) => {}
+            }
+        }
+        res
+    }
+
     /// # Note
     /// `GET` request
     /// ```
@@ -51,13 +110,13 @@ impl HttpClient {
         data: Value,
     ) -> Result<Response, Error> {
         let uri = Url::parse(&format!("{}{}", self.endpoint_base, endpoint)).unwrap();
-        self.client
+        let request = self
+            .client
             .get(uri)
             .query(&query)
             .headers(self.headers.clone())
-            .json(&data)
-            .send()
-            .await
+            .json(&data);
+        self.send(request, Context::current()).await
     }
 
     /// # Note
@@ -72,13 +131,13 @@ impl HttpClient {
         data: Value,
     ) -> Result<Response, Error> {
         let uri = Url::parse(&format!("{}{}", self.endpoint_base_data, endpoint)).unwrap();
-        self.client
+        let request = self
+            .client
             .get(uri)
             .query(&query)
             .headers(self.headers.clone())
-            .json(&data)
-            .send()
-            .await
+            .json(&data);
+        self.send(request, Context::current()).await
     }
 
     /// # Note
@@ -87,18 +146,13 @@ impl HttpClient {
     /// let res: Result<Response, Error> = http_client.post("https://example.com");
     /// ```
     pub async fn post(&self, endpoint: &str, data: Value) -> Result<Response, Error> {
-        let uri = Url::parse(&format!("{}{}", self.endpoint_base, endpoint)).unwrap();
-        self.client
-            .post(uri)
-            .headers(self.headers.clone())
-            .json(&data)
-            .send()
+        self.post_with_context(endpoint, data, Context::current())
             .await
     }
     /// # Note
-    /// `POST` request
+    /// `POST` request, carrying the given trace context
     /// ```
-    /// let res: Result<Response, Error> = http_client.post("https://example.com");
+    /// let res: Result<Response, Error> = http_client.post_with_context("https://example.com", data, Context::current());
     /// ```
     pub async fn post_with_context(
         &self,
@@ -107,13 +161,32 @@ impl HttpClient {
         context: Context,
     ) -> Result<Response, Error> {
         let uri = Url::parse(&format!("{}{}", self.endpoint_base, endpoint)).unwrap();
-        self.client
+        let request = self
+            .client
             .post(uri)
             .headers(self.headers.clone())
-            .json(&data)
-            .send()
-            .with_context(context.to_owned())
-            .await
+            .json(&data);
+        self.send(request, context).await
+    }
+
+    /// # Note
+    /// `POST` request with a binary body to the data API
+    /// ```
+    /// let res: Result<Response, Error> = http_client.post_data("/richmenu/xxx/content", "image/png", bytes);
+    /// ```
+    pub async fn post_data(
+        &self,
+        endpoint: &str,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<Response, Error> {
+        let uri = Url::parse(&format!("{}{}", self.endpoint_base_data, endpoint)).unwrap();
+        let mut headers = self.headers.clone();
+        if let Ok(header_value) = HeaderValue::from_str(content_type) {
+            headers.insert(CONTENT_TYPE, header_value);
+        }
+        let request = self.client.post(uri).headers(headers).body(body);
+        self.send(request, Context::current()).await
     }
 
     /// # Note
@@ -121,14 +194,15 @@ impl HttpClient {
     /// ```
     /// let res: Result<Response, Error> = http_client.put("https://example.com");
     /// ```
+    #[allow(dead_code)]
     pub async fn put(&self, endpoint: &str, data: Value) -> Result<Response, Error> {
         let uri = Url::parse(&format!("{}{}", self.endpoint_base, endpoint)).unwrap();
-        self.client
+        let request = self
+            .client
             .put(uri)
             .headers(self.headers.clone())
-            .json(&data)
-            .send()
-            .await
+            .json(&data);
+        self.send(request, Context::current()).await
     }
 
     /// # Note
@@ -136,13 +210,14 @@ impl HttpClient {
     /// ```
     /// let res: Result<Response, Error> = http_client.delete("https://example.com");
     /// ```
+    #[allow(dead_code)]
     pub async fn delete(&self, endpoint: &str, data: Value) -> Result<Response, Error> {
         let uri = Url::parse(&format!("{}{}", self.endpoint_base, endpoint)).unwrap();
-        self.client
+        let request = self
+            .client
             .delete(uri)
             .headers(self.headers.clone())
-            .json(&data)
-            .send()
-            .await
+            .json(&data);
+        self.send(request, Context::current()).await
     }
 }
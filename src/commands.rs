@@ -0,0 +1,341 @@
+//! Chat commands
+//! # Note
+//! Text starting with `/` controls the bot instead of being sent to the model.
+//! Commands act on the chat they are sent from, so the members of a group or room
+//! share its model, temperature, persona and memory.
+use std::str::FromStr;
+
+use crate::conversation::ConversationStore;
+use crate::messages::{SendMessageType, TextMessage};
+use crate::objects::QuickReply;
+use crate::openai::models::Model;
+use crate::settings::{SettingsStore, DEFAULT_MODEL};
+use crate::usage::UsageStore;
+
+/// Highest temperature OpenAI accepts.
+const MAX_TEMPERATURE: f32 = 2.0;
+
+/// Argument that puts a setting back to its default.
+const DEFAULT_ARG: &str = "default";
+
+/// A command as listed by `/help`.
+pub struct CommandSpec {
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+/// Every command, in the order `/help` lists them.
+pub const COMMANDS: [CommandSpec; 7] = [
+    CommandSpec {
+        usage: "/reset",
+        description: "forget this conversation",
+    },
+    CommandSpec {
+        usage: "/model <name>",
+        description: "choose the model",
+    },
+    CommandSpec {
+        usage: "/temp <0-2>",
+        description: "choose the temperature",
+    },
+    CommandSpec {
+        usage: "/persona <name>",
+        description: "choose the persona",
+    },
+    CommandSpec {
+        usage: "/usage",
+        description: "show the tokens used",
+    },
+    CommandSpec {
+        usage: "/export",
+        description: "send me everything you store about me",
+    },
+    CommandSpec {
+        usage: "/help",
+        description: "show this help",
+    },
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Reset,
+    Model(Option<String>),
+    Temperature(Option<String>),
+    Persona(Option<String>),
+    Usage,
+    /// Sent to the user by the webhook, which can push messages.
+    Export,
+    Help,
+    Unknown(String),
+}
+
+impl Command {
+    /// Parses `text` as a command, `None` if it is not one.
+    pub fn parse(text: &str) -> Option<Command> {
+        let rest = text.trim().strip_prefix('/')?;
+        let mut parts = rest.splitn(2, char::is_whitespace);
+        let name = parts.next()?.to_lowercase();
+        if name.is_empty() {
+            return None;
+        }
+        let arg = parts
+            .next()
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(str::to_string);
+        let command = match name.as_str() {
+            "reset" => Command::Reset,
+            "model" => Command::Model(arg),
+            "temp" | "temperature" => Command::Temperature(arg),
+            "persona" => Command::Persona(arg),
+            "usage" => Command::Usage,
+            "export" => Command::Export,
+            "help" => Command::Help,
+            _ => Command::Unknown(name),
+        };
+        Some(command)
+    }
+}
+
+/// Chat state the commands read and change.
+pub struct CommandContext<'a> {
+    /// Source id of the chat the command was sent from.
+    pub chat_id: &'a str,
+    pub settings: &'a SettingsStore,
+    pub conversations: &'a ConversationStore,
+    pub usage: &'a UsageStore,
+    /// Names of the personas that can be chosen.
+    pub personas: &'a [String],
+}
+
+/// Runs `command` and returns the reply.
+pub async fn execute(command: &Command, ctx: &CommandContext<'_>) -> SendMessageType {
+    match command {
+        Command::Reset => {
+            ctx.conversations.clear(ctx.chat_id).await;
+            text("Done, I've forgotten our conversation.", None)
+        }
+        Command::Model(arg) => set_model(arg.as_deref(), ctx).await,
+        Command::Temperature(arg) => set_temperature(arg.as_deref(), ctx).await,
+        Command::Persona(arg) => set_persona(arg.as_deref(), ctx).await,
+        Command::Usage => {
+            let usage = ctx.usage.get(ctx.chat_id).await;
+            text(
+                &format!(
+                    "This chat used {} tokens in {} requests ({} prompt, {} completion), about ${:.2}.",
+                    usage.total_tokens(),
+                    usage.requests,
+                    usage.prompt_tokens,
+                    usage.completion_tokens,
+                    usage.cost_usd
+                ),
+                None,
+            )
+        }
+        Command::Export => text(
+            "I've sent you everything I store about you in our private chat.",
+            None,
+        ),
+        Command::Help => help(),
+        Command::Unknown(name) => text(
+            &format!("I don't know /{name}. Send /help to see the commands."),
+            Some(QuickReply::messages(["/help"])),
+        ),
+    }
+}
+
+async fn set_model(arg: Option<&str>, ctx: &CommandContext<'_>) -> SendMessageType {
+    let choices = || {
+        let mut choices: Vec<String> = Model::ALL.iter().map(|m| format!("/model {m}")).collect();
+        choices.push(format!("/model {DEFAULT_ARG}"));
+        Some(QuickReply::messages(choices))
+    };
+    let model = match arg {
+        None => {
+            let current = ctx.settings.get(ctx.chat_id).await.model();
+            return text(&format!("This chat uses {current}."), choices());
+        }
+        Some(DEFAULT_ARG) => None,
+        Some(name) => match Model::from_str(name) {
+            Ok(model) => Some(model),
+            Err(()) => return text(&format!("There is no model called {name}."), choices()),
+        },
+    };
+    ctx.settings.update(ctx.chat_id, |s| s.model = model).await;
+    text(
+        &format!("This chat now uses {}.", model.unwrap_or(DEFAULT_MODEL)),
+        None,
+    )
+}
+
+async fn set_temperature(arg: Option<&str>, ctx: &CommandContext<'_>) -> SendMessageType {
+    let temperature = match arg {
+        None => {
+            let reply = match ctx.settings.get(ctx.chat_id).await.temperature {
+                Some(t) => format!("The temperature is {t}."),
+                None => "The temperature is the model's default.".to_string(),
+            };
+            return text(&reply, None);
+        }
+        Some(DEFAULT_ARG) => None,
+        Some(value) => match value.parse::<f32>() {
+            Ok(t) if (0.0..=MAX_TEMPERATURE).contains(&t) => Some(t),
+            _ => {
+                return text(
+                    &format!("The temperature must be a number from 0 to {MAX_TEMPERATURE}."),
+                    None,
+                )
+            }
+        },
+    };
+    ctx.settings
+        .update(ctx.chat_id, |s| s.temperature = temperature)
+        .await;
+    let reply = match temperature {
+        Some(t) => format!("The temperature is now {t}."),
+        None => "The temperature is back to the model's default.".to_string(),
+    };
+    text(&reply, None)
+}
+
+async fn set_persona(arg: Option<&str>, ctx: &CommandContext<'_>) -> SendMessageType {
+    if ctx.personas.is_empty() {
+        return text("No personas are configured.", None);
+    }
+    let choices = || {
+        let mut choices: Vec<String> = ctx
+            .personas
+            .iter()
+            .map(|p| format!("/persona {p}"))
+            .collect();
+        choices.push(format!("/persona {DEFAULT_ARG}"));
+        Some(QuickReply::messages(choices))
+    };
+    let persona = match arg {
+        None => {
+            let reply = match ctx.settings.get(ctx.chat_id).await.persona {
+                Some(persona) => format!("The persona is {persona}."),
+                None => "No persona is chosen.".to_string(),
+            };
+            return text(&reply, choices());
+        }
+        Some(DEFAULT_ARG) => None,
+        Some(name) => match ctx.personas.iter().find(|p| p.eq_ignore_ascii_case(name)) {
+            Some(persona) => Some(persona.clone()),
+            None => return text(&format!("There is no persona called {name}."), choices()),
+        },
+    };
+    let reply = match &persona {
+        Some(persona) => format!("The persona is now {persona}."),
+        None => "The persona is back to the default.".to_string(),
+    };
+    ctx.settings
+        .update(ctx.chat_id, |s| s.persona = persona)
+        .await;
+    text(&reply, None)
+}
+
+fn help() -> SendMessageType {
+    let lines: Vec<String> = COMMANDS
+        .iter()
+        .map(|c| format!("{} - {}", c.usage, c.description))
+        .collect();
+    // Commands that work without an argument
+    let buttons = ["/model", "/temp", "/persona", "/usage", "/reset"];
+    text(&lines.join("\n"), Some(QuickReply::messages(buttons)))
+}
+
+fn text(text: &str, quick_reply: Option<QuickReply>) -> SendMessageType {
+    SendMessageType::TextMessage(TextMessage {
+        text: text.to_string(),
+        emojis: None,
+        quote_token: None,
+        quick_reply,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversation::Turn;
+    use crate::openai::models::Role;
+
+    fn reply_text(reply: &SendMessageType) -> &str {
+        match reply {
+            SendMessageType::TextMessage(message) => &message.text,
+            _ => panic!("not a text reply"),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse(" /reset "), Some(Command::Reset));
+        assert_eq!(
+            Command::parse("/MODEL  gpt-4 "),
+            Some(Command::Model(Some("gpt-4".to_string())))
+        );
+        assert_eq!(Command::parse("/temp"), Some(Command::Temperature(None)));
+        assert_eq!(Command::parse("/Export"), Some(Command::Export));
+        assert_eq!(
+            Command::parse("/foo bar"),
+            Some(Command::Unknown("foo".to_string()))
+        );
+        assert_eq!(Command::parse("/"), None);
+        assert_eq!(Command::parse("what is 1/2?"), None);
+    }
+
+    #[tokio::test]
+    async fn test_settings_are_scoped_per_chat() {
+        let settings = SettingsStore::new();
+        let conversations = ConversationStore::default();
+        let usage = UsageStore::new();
+        let ctx = CommandContext {
+            chat_id: "C1",
+            settings: &settings,
+            conversations: &conversations,
+            usage: &usage,
+            personas: &[],
+        };
+
+        let reply = execute(&Command::Model(Some("gpt-4".to_string())), &ctx).await;
+        assert_eq!(reply_text(&reply), "This chat now uses gpt-4.");
+        assert_eq!(settings.get("C1").await.model, Some(Model::Gpt_4));
+        assert_eq!(settings.get("U1").await.model, None);
+
+        execute(&Command::Model(Some("gpt-9".to_string())), &ctx).await;
+        assert_eq!(settings.get("C1").await.model, Some(Model::Gpt_4));
+
+        execute(&Command::Temperature(Some("0.5".to_string())), &ctx).await;
+        assert_eq!(settings.get("C1").await.temperature, Some(0.5));
+        execute(&Command::Temperature(Some("3".to_string())), &ctx).await;
+        assert_eq!(settings.get("C1").await.temperature, Some(0.5));
+        execute(&Command::Temperature(Some("default".to_string())), &ctx).await;
+        assert_eq!(settings.get("C1").await.temperature, None);
+    }
+
+    #[tokio::test]
+    async fn test_reset_and_persona() {
+        let settings = SettingsStore::new();
+        let conversations = ConversationStore::default();
+        let usage = UsageStore::new();
+        let personas = vec!["Teacher".to_string()];
+        let ctx = CommandContext {
+            chat_id: "U1",
+            settings: &settings,
+            conversations: &conversations,
+            usage: &usage,
+            personas: &personas,
+        };
+
+        conversations
+            .push("U1", Turn::new(Role::User, "hi", None))
+            .await;
+        execute(&Command::Reset, &ctx).await;
+        assert!(conversations.turns("U1").await.is_empty());
+
+        execute(&Command::Persona(Some("teacher".to_string())), &ctx).await;
+        assert_eq!(settings.get("U1").await.persona.as_deref(), Some("Teacher"));
+        execute(&Command::Persona(Some("pirate".to_string())), &ctx).await;
+        assert_eq!(settings.get("U1").await.persona.as_deref(), Some("Teacher"));
+    }
+}
@@ -0,0 +1,1309 @@
+//! Configuration
+//! # Note
+//! Settings are read from three layers, each one overriding the previous:
+//! 1. a TOML file, given with `--config <path>` or `LINEBOT_CONFIG`,
+//! 2. environment variables: the historical names such as `LINE_CHANNEL_SECRET`, or
+//!    `LINEBOT__<SECTION>__<KEY>` for any key, e.g. `LINEBOT__SERVER__PORT=9000`,
+//! 3. command line flags `--<section>.<key> <value>`, e.g. `--server.port 9000`.
+//!
+//! Secrets can be read from a file instead: `<key>_file` in every layer, or `<VAR>_FILE`
+//! for the historical variables, e.g. `LINE_CHANNEL_SECRET_FILE=/run/secrets/line`.
+//! ```toml
+//! [server]
+//! port = 8080
+//! workers = 20
+//!
+//! [line]
+//! channel_secret_file = "/run/secrets/line_channel_secret"
+//! channel_access_token_file = "/run/secrets/line_access_token"
+//! chat_prompt = "Nick:>"
+//!
+//! [openai]
+//! api_key_file = "/run/secrets/openai"
+//!
+//! [generation]
+//! temperature = 0.7
+//! max_tokens = 800
+//! ```
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+
+use opentelemetry_otlp::Protocol;
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::events::source::SouceType;
+use crate::events::Source;
+use crate::generation::GenerationParams;
+use crate::moderation::Moderator;
+use crate::openai::embeddings::DEFAULT_EMBEDDING_MODEL;
+use crate::openai::moderations::DEFAULT_MODERATION_MODEL;
+use crate::redaction::detectors::{CREDIT_CARD, EMAIL, PHONE, THAI_ID};
+use crate::redaction::Redactor;
+
+/// Variable naming the configuration file.
+const CONFIG_FILE_VAR: &str = "LINEBOT_CONFIG";
+
+/// Prefix of the variables that set any key.
+const ENV_PREFIX: &str = "LINEBOT__";
+
+/// Suffix of the keys that read a secret from a file.
+const FILE_SUFFIX: &str = "_file";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Str,
+    /// A string that may also be read from a file.
+    Secret,
+    Int,
+    Port,
+    Float,
+    Bool,
+    /// Strings, `|`-separated in variables and flags.
+    List,
+}
+
+/// Every key, with the type of its value.
+const KEYS: &[(&str, Kind)] = &[
+    ("server.host", Kind::Str),
+    ("server.port", Kind::Port),
+    ("server.workers", Kind::Int),
+    ("line.channel_secret", Kind::Secret),
+    ("line.channel_access_token", Kind::Secret),
+    ("line.chat_prompt", Kind::Str),
+    ("openai.api_key", Kind::Secret),
+    ("openai.base_url", Kind::Str),
+    ("openai.embedding_model", Kind::Str),
+    ("openai.moderation_model", Kind::Str),
+    ("generation.temperature", Kind::Float),
+    ("generation.top_p", Kind::Float),
+    ("generation.max_tokens", Kind::Int),
+    ("generation.presence_penalty", Kind::Float),
+    ("generation.frequency_penalty", Kind::Float),
+    ("generation.stop", Kind::List),
+    ("generation.send_user", Kind::Bool),
+    ("documents.ttl_minutes", Kind::Int),
+    ("knowledge.index", Kind::Str),
+    ("personas.file", Kind::Str),
+    ("welcome.enabled", Kind::Bool),
+    ("welcome.file", Kind::Str),
+    ("rich_menus.dir", Kind::Str),
+    (
+        "telemetry.application_insights_connection_string",
+        Kind::Secret,
+    ),
+    ("telemetry.exporter", Kind::Str),
+    ("telemetry.otlp_endpoint", Kind::Str),
+    ("telemetry.otlp_protocol", Kind::Str),
+    ("telemetry.service_name", Kind::Str),
+    ("telemetry.metrics_interval_seconds", Kind::Int),
+    ("access.allowed_users", Kind::List),
+    ("access.allowed_groups", Kind::List),
+    ("admin.token", Kind::Secret),
+    ("admin.users", Kind::List),
+    ("limits.requests_per_minute", Kind::Int),
+    ("limits.burst", Kind::Int),
+    ("limits.daily_tokens", Kind::Int),
+    ("limits.daily_cost_usd", Kind::Float),
+    ("limits.state_file", Kind::Str),
+    ("reload.interval_seconds", Kind::Int),
+    ("storage.backend", Kind::Str),
+    ("storage.sqlite_path", Kind::Str),
+    ("storage.turn_retention_days", Kind::Int),
+    ("storage.usage_retention_days", Kind::Int),
+    ("storage.prune_interval_minutes", Kind::Int),
+    ("usage.ledger_file", Kind::Str),
+    ("usage.prices.gpt-3.5-turbo.prompt", Kind::Float),
+    ("usage.prices.gpt-3.5-turbo.completion", Kind::Float),
+    ("usage.prices.gpt-4.prompt", Kind::Float),
+    ("usage.prices.gpt-4.completion", Kind::Float),
+    ("usage.prices.gpt-4-32k.prompt", Kind::Float),
+    ("usage.prices.gpt-4-32k.completion", Kind::Float),
+    ("usage.prices.gpt-4-1106-preview.prompt", Kind::Float),
+    ("usage.prices.gpt-4-1106-preview.completion", Kind::Float),
+    ("usage.prices.gpt-4-vision-preview.prompt", Kind::Float),
+    ("usage.prices.gpt-4-vision-preview.completion", Kind::Float),
+    ("privacy.retention_days", Kind::Int),
+    ("redis.url", Kind::Secret),
+    ("redis.key_prefix", Kind::Str),
+    ("redis.timeout_ms", Kind::Int),
+    ("redaction.enabled", Kind::Bool),
+    ("redaction.detectors", Kind::List),
+    ("redaction.custom", Kind::List),
+    ("redaction.restore", Kind::Bool),
+    ("moderation.enabled", Kind::Bool),
+    ("moderation.api", Kind::Bool),
+    ("moderation.blocklist", Kind::List),
+    ("moderation.blocklist_patterns", Kind::List),
+    ("moderation.thresholds", Kind::List),
+    ("moderation.input_response", Kind::Str),
+    ("moderation.output_response", Kind::Str),
+    ("moderation.review_file", Kind::Str),
+];
+
+/// Historical environment variables and the key each one sets.
+const ENV_VARS: &[(&str, &str)] = &[
+    ("LINE_CHANNEL_SECRET", "line.channel_secret"),
+    ("LINE_CHANNEL_ACCESS_TOKEN", "line.channel_access_token"),
+    ("LINE_CHAT_PROMPT", "line.chat_prompt"),
+    ("CHATGPT_API_KEY", "openai.api_key"),
+    ("EMBEDDING_MODEL", "openai.embedding_model"),
+    ("CHATGPT_TEMPERATURE", "generation.temperature"),
+    ("CHATGPT_TOP_P", "generation.top_p"),
+    ("CHATGPT_MAX_TOKENS", "generation.max_tokens"),
+    ("CHATGPT_PRESENCE_PENALTY", "generation.presence_penalty"),
+    ("CHATGPT_FREQUENCY_PENALTY", "generation.frequency_penalty"),
+    ("CHATGPT_STOP", "generation.stop"),
+    ("CHATGPT_SEND_USER", "generation.send_user"),
+    ("DOCUMENT_TTL_MINUTES", "documents.ttl_minutes"),
+    ("KNOWLEDGE_INDEX", "knowledge.index"),
+    ("PERSONAS_FILE", "personas.file"),
+    ("RICH_MENU_DIR", "rich_menus.dir"),
+    (
+        "APPLICATIONINSIGHTS_CON_STRING",
+        "telemetry.application_insights_connection_string",
+    ),
+    ("OTEL_EXPORTER_OTLP_ENDPOINT", "telemetry.otlp_endpoint"),
+    ("OTEL_EXPORTER_OTLP_PROTOCOL", "telemetry.otlp_protocol"),
+    ("OTEL_SERVICE_NAME", "telemetry.service_name"),
+    ("ADMIN_TOKEN", "admin.token"),
+    ("ADMIN_USERS", "admin.users"),
+    ("REDIS_URL", "redis.url"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub line: LineConfig,
+    pub openai: OpenAIConfig,
+    pub generation: GenerationParams,
+    pub documents: DocumentsConfig,
+    pub knowledge: KnowledgeConfig,
+    pub personas: PersonasConfig,
+    pub welcome: WelcomeConfig,
+    pub rich_menus: RichMenusConfig,
+    pub telemetry: TelemetryConfig,
+    pub access: AccessConfig,
+    pub admin: AdminConfig,
+    pub limits: LimitsConfig,
+    pub reload: ReloadConfig,
+    pub storage: StorageConfig,
+    pub usage: UsageConfig,
+    pub privacy: PrivacyConfig,
+    pub redis: RedisConfig,
+    pub redaction: RedactionConfig,
+    pub moderation: ModerationConfig,
+    /// Configuration file the values were read from, if any.
+    #[serde(skip)]
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub workers: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LineConfig {
+    pub channel_secret: String,
+    pub channel_access_token: String,
+    /// Text that marks a message as a question for the bot.
+    pub chat_prompt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenAIConfig {
+    pub api_key: String,
+    pub base_url: String,
+    pub embedding_model: String,
+    pub moderation_model: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentsConfig {
+    /// How long an uploaded document is kept.
+    pub ttl_minutes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KnowledgeConfig {
+    /// Vector index built by `line_botx ingest`.
+    pub index: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersonasConfig {
+    pub file: Option<String>,
+}
+
+/// Greetings of new friends and groups, see [`crate::welcome`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WelcomeConfig {
+    pub enabled: bool,
+    /// Greetings replacing the built-in English and Thai ones.
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RichMenusConfig {
+    pub dir: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub application_insights_connection_string: Option<String>,
+    /// `application_insights`, `otlp`, `stdout` or `none`. Application Insights when
+    /// its connection string is set, none otherwise.
+    pub exporter: Option<String>,
+    /// Base URL of the OTLP collector, e.g. `http://localhost:4317` for gRPC. Over HTTP,
+    /// `/v1/traces` and `/v1/metrics` are added to it.
+    pub otlp_endpoint: String,
+    /// `grpc` or `http/protobuf`.
+    pub otlp_protocol: String,
+    pub service_name: String,
+    /// How often metrics are exported.
+    pub metrics_interval_seconds: u64,
+}
+
+/// Where spans and metrics are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryExporter {
+    ApplicationInsights,
+    Otlp,
+    /// OTLP JSON documents on the standard output, one per line.
+    Stdout,
+    None,
+}
+
+/// Who may talk to the bot. An empty list allows everyone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// User ids allowed in 1:1 chats.
+    pub allowed_users: Vec<String>,
+    /// Group and room ids the bot answers in.
+    pub allowed_groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Bearer token of the admin endpoints, which are disabled without one.
+    pub token: Option<String>,
+    /// LINE user ids of the administrators, who are exempt from limits.
+    pub users: Vec<String>,
+}
+
+/// Rate limits and daily quotas of every user, group and room. Unset means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Questions per minute, refilled continuously.
+    pub requests_per_minute: Option<u64>,
+    /// Questions that can be asked in a row, `requests_per_minute` if unset.
+    pub burst: Option<u64>,
+    /// Tokens per UTC day, as reported by OpenAI.
+    pub daily_tokens: Option<u64>,
+    /// Cost per UTC day in USD, at the list price of the models.
+    pub daily_cost_usd: Option<f64>,
+    /// File the daily counters are kept in across restarts. Only read at startup.
+    pub state_file: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReloadConfig {
+    /// How often the configuration files are checked for changes, 0 to never check.
+    pub interval_seconds: u64,
+}
+
+/// Where conversations, settings and usage are kept, and for how long.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// `memory` or `sqlite`.
+    pub backend: String,
+    /// Database file of the `sqlite` backend.
+    pub sqlite_path: String,
+    /// Days conversation turns are kept, 0 to keep them forever.
+    pub turn_retention_days: u64,
+    /// Days ledger entries are kept, 0 to keep them forever.
+    pub usage_retention_days: u64,
+    /// How often expired turns and entries are deleted.
+    pub prune_interval_minutes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    Memory,
+    Sqlite,
+}
+
+/// Usage ledger of the `memory` storage, and the prices usage is charged at.
+/// ```toml
+/// [usage.prices."gpt-4"]
+/// prompt = 0.03
+/// completion = 0.06
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageConfig {
+    /// JSON lines file every completion is appended to. It is read back at startup into
+    /// a storage that has no ledger yet, and pruned after `storage.usage_retention_days`.
+    pub ledger_file: Option<String>,
+    /// Prices by model name. A model or price left out keeps the list price.
+    pub prices: BTreeMap<String, PriceConfig>,
+}
+
+/// Price in USD of 1,000 tokens of a model.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PriceConfig {
+    pub prompt: Option<f64>,
+    pub completion: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    /// Days anything about a user is kept at most, whatever the other retentions say.
+    /// 0 leaves them alone.
+    pub retention_days: u64,
+}
+
+/// State shared by the replicas behind a load balancer: delivered webhook events,
+/// rate-limit buckets and conversation history. Kept in process without a `url`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedisConfig {
+    /// `redis://[:password@]host[:port][/db]`, `rediss://` for TLS, or
+    /// `redis+unix:///path/to/socket`.
+    pub url: Option<String>,
+    /// Prepended to every key, so that several bots can share a server.
+    pub key_prefix: String,
+    /// How long a command may take before the in-process store is used instead.
+    pub timeout_ms: u64,
+}
+
+/// Personal data replaced with placeholders before user text is sent to OpenAI.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    /// Built-in detectors, by priority: `THAI_ID`, `CREDIT_CARD`, `EMAIL`, `PHONE`.
+    pub detectors: Vec<String>,
+    /// More detectors as `NAME=regex`, after the built-in ones.
+    pub custom: Vec<String>,
+    /// Whether placeholders in answers are replaced back with the original values.
+    pub restore: bool,
+}
+
+/// Checks of questions and answers, see [`crate::moderation`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModerationConfig {
+    pub enabled: bool,
+    /// Whether `/v1/moderations` is called, after the blocklist.
+    pub api: bool,
+    /// Keywords, matched ignoring case.
+    pub blocklist: Vec<String>,
+    /// Regular expressions.
+    pub blocklist_patterns: Vec<String>,
+    /// `category=score`, from 0 to 1, e.g. `violence=0.5`.
+    pub thresholds: Vec<String>,
+    /// Reply to a blocked question.
+    pub input_response: String,
+    /// Sent instead of a blocked answer.
+    pub output_response: String,
+    /// JSON lines file every flagged event is appended to, and pruned after
+    /// `storage.turn_retention_days`.
+    pub review_file: Option<String>,
+}
+
+impl AccessConfig {
+    pub fn allows(&self, source: &Source) -> bool {
+        match &source.r#type {
+            SouceType::User(user) => {
+                self.allowed_users.is_empty() || self.allowed_users.contains(&user.user_id)
+            }
+            SouceType::Group(_) | SouceType::Room(_) => {
+                self.allowed_groups.is_empty()
+                    || self.allowed_groups.iter().any(|id| id == source.id())
+            }
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn exporter(&self) -> Result<TelemetryExporter, String> {
+        match self.exporter.as_deref() {
+            None if self.application_insights_connection_string.is_some() => {
+                Ok(TelemetryExporter::ApplicationInsights)
+            }
+            None | Some("none") => Ok(TelemetryExporter::None),
+            Some("application_insights") => Ok(TelemetryExporter::ApplicationInsights),
+            Some("otlp") => Ok(TelemetryExporter::Otlp),
+            Some("stdout") => Ok(TelemetryExporter::Stdout),
+            Some(other) => Err(format!(
+                "unknown exporter {other:?}, expected application_insights, otlp, stdout or none"
+            )),
+        }
+    }
+
+    pub fn otlp_protocol(&self) -> Result<Protocol, String> {
+        match self.otlp_protocol.as_str() {
+            "grpc" => Ok(Protocol::Grpc),
+            "http/protobuf" => Ok(Protocol::HttpBinary),
+            other => Err(format!(
+                "unknown protocol {other:?}, expected grpc or http/protobuf"
+            )),
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            application_insights_connection_string: None,
+            exporter: None,
+            otlp_endpoint: "http://localhost:4318".to_string(),
+            otlp_protocol: "http/protobuf".to_string(),
+            service_name: "LineChatBot".to_string(),
+            metrics_interval_seconds: 60,
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn backend(&self) -> Result<StorageBackend, String> {
+        match self.backend.as_str() {
+            "memory" => Ok(StorageBackend::Memory),
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            other => Err(format!(
+                "unknown backend {other:?}, expected memory or sqlite"
+            )),
+        }
+    }
+}
+
+impl PrivacyConfig {
+    /// `storage` with its retentions capped by `retention_days`.
+    pub fn cap(&self, storage: &StorageConfig) -> StorageConfig {
+        let cap = |days: u64| match (self.retention_days, days) {
+            (0, days) => days,
+            (limit, 0) => limit,
+            (limit, days) => days.min(limit),
+        };
+        StorageConfig {
+            turn_retention_days: cap(storage.turn_retention_days),
+            usage_retention_days: cap(storage.usage_retention_days),
+            ..storage.clone()
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: "memory".to_string(),
+            sqlite_path: "line_botx.db".to_string(),
+            turn_retention_days: 0,
+            usage_retention_days: 0,
+            prune_interval_minutes: 60,
+        }
+    }
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            key_prefix: "line_botx:".to_string(),
+            timeout_ms: 500,
+        }
+    }
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            detectors: [THAI_ID, CREDIT_CARD, EMAIL, PHONE]
+                .map(String::from)
+                .to_vec(),
+            custom: Vec::new(),
+            restore: true,
+        }
+    }
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            api: true,
+            blocklist: Vec::new(),
+            blocklist_patterns: Vec::new(),
+            thresholds: Vec::new(),
+            input_response: "Sorry, I can't help with that.".to_string(),
+            output_response: "Sorry, I can't share that answer.".to_string(),
+            review_file: None,
+        }
+    }
+}
+
+impl Default for WelcomeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: None,
+        }
+    }
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 10,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+            workers: 20,
+        }
+    }
+}
+
+impl Default for OpenAIConfig {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            base_url: "https://api.openai.com".to_string(),
+            embedding_model: DEFAULT_EMBEDDING_MODEL.to_string(),
+            moderation_model: DEFAULT_MODERATION_MODEL.to_string(),
+        }
+    }
+}
+
+impl Default for DocumentsConfig {
+    fn default() -> Self {
+        Self { ttl_minutes: 30 }
+    }
+}
+
+/// Enum representing possible errors while loading the configuration.
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    /// The configuration file could not be read or parsed.
+    File {
+        path: String,
+        message: String,
+    },
+    UnknownKey(String),
+    InvalidValue {
+        key: String,
+        message: String,
+    },
+    Missing(String),
+    /// A command line argument that is not a flag.
+    Usage(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::File { path, message } => write!(f, "{path}: {message}"),
+            ConfigError::UnknownKey(key) => write!(f, "unknown config key `{key}`"),
+            ConfigError::InvalidValue { key, message } => {
+                write!(f, "invalid value for `{key}`: {message}")
+            }
+            ConfigError::Missing(key) => write!(f, "missing config key `{key}`"),
+            ConfigError::Usage(arg) => write!(f, "unexpected argument `{arg}`"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads and validates the configuration of the process from its command line
+    /// flags (without the program name) and environment.
+    pub fn load(args: &[String]) -> Result<Config, ConfigError> {
+        let config = Config::resolve(args, env::vars())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Merges the layers into a configuration, without checking required keys.
+    pub fn resolve<I>(args: &[String], vars: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let vars: HashMap<String, String> = vars.into_iter().collect();
+        let flags = parse_flags(args)?;
+        let mut layers = Layers::default();
+
+        let config_file = flags
+            .config_file
+            .or_else(|| vars.get(CONFIG_FILE_VAR).cloned());
+        if let Some(path) = &config_file {
+            let file_error = |message: String| ConfigError::File {
+                path: path.clone(),
+                message,
+            };
+            let text = std::fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+            let table: Table = toml::from_str(&text).map_err(|e| file_error(e.to_string()))?;
+            let mut values = Vec::new();
+            flatten("", table, &mut values);
+            for (key, value) in values {
+                layers.set(&key, value)?;
+            }
+        }
+
+        // Sorted, so that errors do not depend on the order of the environment
+        let mut names: Vec<&String> = vars.keys().collect();
+        names.sort();
+        for name in names {
+            let value = &vars[name];
+            if let Some(key) = env_key(name) {
+                layers.set_str(&key, value)?;
+            }
+        }
+
+        for (key, value) in flags.values {
+            layers.set_str(&key, &value)?;
+        }
+
+        let mut config = layers.into_config()?;
+        config.file = config_file;
+        Ok(config)
+    }
+
+    /// Checks the keys needed to serve the webhook are set and every value is in range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let required = [
+            ("line.channel_secret", &self.line.channel_secret),
+            ("line.channel_access_token", &self.line.channel_access_token),
+            ("line.chat_prompt", &self.line.chat_prompt),
+            ("openai.api_key", &self.openai.api_key),
+        ];
+        for (key, value) in required {
+            if value.is_empty() {
+                return Err(ConfigError::Missing(key.to_string()));
+            }
+        }
+        let invalid = |key: &str, message: &str| ConfigError::InvalidValue {
+            key: key.to_string(),
+            message: message.to_string(),
+        };
+        if self.server.port == 0 {
+            return Err(invalid("server.port", "must not be 0"));
+        }
+        if self.server.workers == 0 {
+            return Err(invalid("server.workers", "must be at least 1"));
+        }
+        if !self.openai.base_url.starts_with("http://")
+            && !self.openai.base_url.starts_with("https://")
+        {
+            return Err(invalid("openai.base_url", "must be an http(s) URL"));
+        }
+        if self.documents.ttl_minutes == 0 {
+            return Err(invalid("documents.ttl_minutes", "must be at least 1"));
+        }
+        let exporter = self
+            .telemetry
+            .exporter()
+            .map_err(|e| invalid("telemetry.exporter", &e))?;
+        match exporter {
+            TelemetryExporter::ApplicationInsights
+                if self
+                    .telemetry
+                    .application_insights_connection_string
+                    .is_none() =>
+            {
+                return Err(ConfigError::Missing(
+                    "telemetry.application_insights_connection_string".to_string(),
+                ))
+            }
+            TelemetryExporter::Otlp => {
+                let endpoint = &self.telemetry.otlp_endpoint;
+                if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                    return Err(invalid("telemetry.otlp_endpoint", "must be an http(s) URL"));
+                }
+                if let Err(message) = self.telemetry.otlp_protocol() {
+                    return Err(invalid("telemetry.otlp_protocol", &message));
+                }
+            }
+            _ => {}
+        }
+        if self.telemetry.metrics_interval_seconds == 0 {
+            return Err(invalid(
+                "telemetry.metrics_interval_seconds",
+                "must be at least 1",
+            ));
+        }
+        self.storage
+            .backend()
+            .map_err(|e| invalid("storage.backend", &e))?;
+        if self.storage.prune_interval_minutes == 0 {
+            return Err(invalid(
+                "storage.prune_interval_minutes",
+                "must be at least 1",
+            ));
+        }
+        if let Some(url) = &self.redis.url {
+            let schemes = ["redis://", "rediss://", "redis+unix://", "unix://"];
+            if !schemes.iter().any(|scheme| url.starts_with(scheme)) {
+                return Err(invalid(
+                    "redis.url",
+                    "must be a redis://, rediss:// or redis+unix:// URL",
+                ));
+            }
+        }
+        if self.redis.timeout_ms == 0 {
+            return Err(invalid("redis.timeout_ms", "must be at least 1"));
+        }
+        if self.limits.requests_per_minute == Some(0) {
+            return Err(invalid("limits.requests_per_minute", "must be at least 1"));
+        }
+        match self.limits.burst {
+            Some(0) => return Err(invalid("limits.burst", "must be at least 1")),
+            Some(_) if self.limits.requests_per_minute.is_none() => {
+                return Err(invalid("limits.burst", "needs limits.requests_per_minute"))
+            }
+            _ => {}
+        }
+        if self.limits.daily_cost_usd.is_some_and(|cost| cost <= 0.0) {
+            return Err(invalid("limits.daily_cost_usd", "must be positive"));
+        }
+        for (model, price) in &self.usage.prices {
+            for (name, price) in [("prompt", price.prompt), ("completion", price.completion)] {
+                if price.is_some_and(|price| !(price.is_finite() && price >= 0.0)) {
+                    let key = format!("usage.prices.{model}.{name}");
+                    return Err(invalid(&key, "must be zero or more"));
+                }
+            }
+        }
+        Redactor::from_config(&self.redaction)
+            .map_err(|e| invalid(&format!("redaction.{}", e.key), &e.message))?;
+        Moderator::from_config(&self.moderation)
+            .map_err(|e| invalid(&format!("moderation.{}", e.key), &e.message))?;
+        self.generation
+            .validate()
+            .map_err(|e| invalid(&format!("generation.{}", e.key), &e.message))
+    }
+
+    /// `host:port` to listen on.
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.server.host, self.server.port)
+    }
+}
+
+/// Values set so far, by dotted key. A secret and its `_file` replace each other.
+#[derive(Debug, Default)]
+struct Layers {
+    values: BTreeMap<String, Value>,
+}
+
+impl Layers {
+    fn set(&mut self, key: &str, value: Value) -> Result<(), ConfigError> {
+        let invalid = |message: String| ConfigError::InvalidValue {
+            key: key.to_string(),
+            message,
+        };
+        let (target, kind) = lookup_key(key)?;
+        let value = if target != key {
+            // `<secret>_file`
+            match value {
+                Value::String(path) => {
+                    let secret = std::fs::read_to_string(&path)
+                        .map_err(|e| invalid(format!("cannot read {path}: {e}")))?;
+                    Value::String(secret.trim().to_string())
+                }
+                other => return Err(invalid(format!("expected a path, got {other}"))),
+            }
+        } else {
+            check_kind(kind, value).map_err(invalid)?
+        };
+        self.values.insert(target.to_string(), value);
+        Ok(())
+    }
+
+    fn set_str(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let (target, kind) = lookup_key(key)?;
+        if target != key {
+            return self.set(key, Value::String(value.to_string()));
+        }
+        let invalid = || ConfigError::InvalidValue {
+            key: key.to_string(),
+            message: format!("cannot parse {value:?}"),
+        };
+        let value = match kind {
+            Kind::Str | Kind::Secret => Value::String(value.to_string()),
+            Kind::Int | Kind::Port => Value::Integer(value.trim().parse().map_err(|_| invalid())?),
+            Kind::Float => Value::Float(value.trim().parse().map_err(|_| invalid())?),
+            Kind::Bool => Value::Boolean(value.trim().parse().map_err(|_| invalid())?),
+            Kind::List => Value::Array(
+                value
+                    .split('|')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| Value::String(s.replace("\\n", "\n")))
+                    .collect(),
+            ),
+        };
+        self.set(key, value)
+    }
+
+    fn into_config(self) -> Result<Config, ConfigError> {
+        let mut root = Table::new();
+        for (key, value) in self.values {
+            let (section, name) = key.split_once('.').unwrap_or(("", &key));
+            let table = subtable(&mut root, section);
+            // `<map>.<entry>.<field>`, the entry being a model name that may hold dots
+            let nested = name
+                .split_once('.')
+                .and_then(|(map, rest)| Some((map, rest.rsplit_once('.')?)));
+            match nested {
+                Some((map, (entry, field))) => {
+                    let entry = subtable(subtable(table, map), entry);
+                    entry.insert(field.to_string(), value);
+                }
+                None => {
+                    table.insert(name.to_string(), value);
+                }
+            }
+        }
+        // Every value has been checked against its kind, so this only fails on a bug
+        Config::deserialize(Value::Table(root)).map_err(|e| ConfigError::InvalidValue {
+            key: "config".to_string(),
+            message: e.to_string().trim().to_string(),
+        })
+    }
+}
+
+/// The table at `name` in `table`, created if missing.
+fn subtable<'a>(table: &'a mut Table, name: &str) -> &'a mut Table {
+    let Value::Table(table) = table
+        .entry(name.to_string())
+        .or_insert_with(|| Value::Table(Table::new()))
+    else {
+        unreachable!("only tables are nested");
+    };
+    table
+}
+
+/// The key a dotted key sets, which differs for `<secret>_file`, and its kind.
+fn lookup_key(key: &str) -> Result<(&'static str, Kind), ConfigError> {
+    if let Some(&(target, kind)) = KEYS.iter().find(|(k, _)| *k == key) {
+        return Ok((target, kind));
+    }
+    if let Some(secret) = key.strip_suffix(FILE_SUFFIX) {
+        if let Some(&(target, kind)) = KEYS
+            .iter()
+            .find(|(k, kind)| *k == secret && *kind == Kind::Secret)
+        {
+            return Ok((target, kind));
+        }
+    }
+    Err(ConfigError::UnknownKey(key.to_string()))
+}
+
+fn check_kind(kind: Kind, value: Value) -> Result<Value, String> {
+    match (kind, value) {
+        (Kind::Str | Kind::Secret, value @ Value::String(_)) => Ok(value),
+        (Kind::Int, Value::Integer(i)) if i < 0 => Err(format!("{i} is negative")),
+        (Kind::Int, value @ Value::Integer(_)) => Ok(value),
+        (Kind::Port, Value::Integer(i)) if u16::try_from(i).is_err() => {
+            Err(format!("{i} is not a port"))
+        }
+        (Kind::Port, value @ Value::Integer(_)) => Ok(value),
+        (Kind::Float, value @ Value::Float(_)) => Ok(value),
+        (Kind::Float, Value::Integer(i)) => Ok(Value::Float(i as f64)),
+        (Kind::Bool, value @ Value::Boolean(_)) => Ok(value),
+        (Kind::List, Value::Array(items)) if items.iter().all(Value::is_str) => {
+            Ok(Value::Array(items))
+        }
+        (kind, value) => Err(format!("expected {kind:?}, got {value}")),
+    }
+}
+
+/// Dotted keys of the leaves of a TOML table.
+fn flatten(prefix: &str, table: Table, values: &mut Vec<(String, Value)>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}.{name}")
+        };
+        match value {
+            Value::Table(table) => flatten(&key, table, values),
+            value => values.push((key, value)),
+        }
+    }
+}
+
+/// The dotted key an environment variable sets, if it is a config variable.
+fn env_key(name: &str) -> Option<String> {
+    if let Some(path) = name.strip_prefix(ENV_PREFIX) {
+        return Some(path.to_lowercase().replace("__", "."));
+    }
+    if let Some(&(_, key)) = ENV_VARS.iter().find(|(var, _)| *var == name) {
+        return Some(key.to_string());
+    }
+    let var = name.strip_suffix("_FILE")?;
+    let &(_, key) = ENV_VARS.iter().find(|(v, _)| *v == var)?;
+    Some(format!("{key}{FILE_SUFFIX}"))
+}
+
+/// Flags of the command line.
+#[derive(Debug, Default)]
+struct Flags {
+    /// `--config <path>`
+    config_file: Option<String>,
+    /// `--<key> <value>`, in order.
+    values: Vec<(String, String)>,
+}
+
+fn parse_flags(args: &[String]) -> Result<Flags, ConfigError> {
+    let mut flags = Flags::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(ConfigError::Usage(arg.clone()));
+        };
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => match args.next() {
+                Some(value) => (flag.to_string(), value.clone()),
+                None => {
+                    return Err(ConfigError::InvalidValue {
+                        key: flag.to_string(),
+                        message: "missing value".to_string(),
+                    })
+                }
+            },
+        };
+        if key == "config" {
+            flags.config_file = Some(value);
+        } else {
+            flags.values.push((key, value));
+        }
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("line_botx_config_{}_{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// A config with the required secrets, which passes validation.
+    fn valid_config() -> Config {
+        let config = Config::resolve(
+            &[],
+            vars(&[
+                ("LINE_CHANNEL_SECRET", "a"),
+                ("LINE_CHANNEL_ACCESS_TOKEN", "b"),
+                ("LINE_CHAT_PROMPT", "c"),
+                ("CHATGPT_API_KEY", "d"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        config
+    }
+
+    #[test]
+    fn test_defaults_keep_previous_behavior() {
+        let config = Config::resolve(&[], Vec::new()).unwrap();
+        assert_eq!(config.bind_address(), "0.0.0.0:8080");
+        assert_eq!(config.server.workers, 20);
+        assert_eq!(config.openai.base_url, "https://api.openai.com");
+        assert_eq!(config.documents.ttl_minutes, 30);
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::Missing("line.channel_secret".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let file = temp_file(
+            "layers.toml",
+            "[server]\nport = 9000\nworkers = 4\n\n[line]\nchat_prompt = \"Bot:\"\n\n[generation]\ntemperature = 1\n",
+        );
+        let config = Config::resolve(
+            &args(&["--config", file.to_str().unwrap(), "--server.workers=8"]),
+            vars(&[
+                ("LINEBOT__SERVER__PORT", "9100"),
+                ("LINE_CHAT_PROMPT", "Nick:>"),
+                ("CHATGPT_STOP", "END|\\n\\n"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 9100);
+        assert_eq!(config.server.workers, 8);
+        assert_eq!(config.line.chat_prompt, "Nick:>");
+        assert_eq!(config.generation.temperature, Some(1.0));
+        assert_eq!(
+            config.generation.stop,
+            Some(vec!["END".to_string(), "\n\n".to_string()])
+        );
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_usage_prices_by_model() {
+        let file = temp_file(
+            "prices.toml",
+            "[usage.prices]\n\"gpt-3.5-turbo\" = { prompt = 0.001, completion = 0.002 }\n",
+        );
+        let config = Config::resolve(
+            &args(&["--config", file.to_str().unwrap()]),
+            vars(&[("LINEBOT__USAGE__PRICES__GPT-4__COMPLETION", "0.05")]),
+        )
+        .unwrap();
+        let gpt_3_5 = PriceConfig {
+            prompt: Some(0.001),
+            completion: Some(0.002),
+        };
+        assert_eq!(config.usage.prices["gpt-3.5-turbo"], gpt_3_5);
+        assert_eq!(config.usage.prices["gpt-4"].prompt, None);
+        assert_eq!(config.usage.prices["gpt-4"].completion, Some(0.05));
+        std::fs::remove_file(file).unwrap();
+
+        let err = Config::resolve(&args(&["--usage.prices.gpt-5.prompt", "1"]), Vec::new());
+        assert_eq!(
+            err.unwrap_err(),
+            ConfigError::UnknownKey("usage.prices.gpt-5.prompt".to_string())
+        );
+    }
+
+    #[test]
+    fn test_secrets_from_files() {
+        let secret = temp_file("secret", "s3cret\n");
+        let file = temp_file(
+            "secrets.toml",
+            &format!("[openai]\napi_key_file = {:?}\n", secret.to_str().unwrap()),
+        );
+        let config = Config::resolve(
+            &args(&["--config", file.to_str().unwrap()]),
+            vars(&[("LINE_CHANNEL_SECRET_FILE", secret.to_str().unwrap())]),
+        )
+        .unwrap();
+        assert_eq!(config.openai.api_key, "s3cret");
+        assert_eq!(config.line.channel_secret, "s3cret");
+
+        // A later plain value replaces the file
+        let config = Config::resolve(
+            &args(&["--openai.api_key", "inline"]),
+            vars(&[("CHATGPT_API_KEY_FILE", secret.to_str().unwrap())]),
+        )
+        .unwrap();
+        assert_eq!(config.openai.api_key, "inline");
+
+        let err = Config::resolve(
+            &args(&["--line.channel_secret_file", "/nonexistent"]),
+            Vec::new(),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, ConfigError::InvalidValue { key, .. } if key == "line.channel_secret_file")
+        );
+        std::fs::remove_file(secret).unwrap();
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_errors_name_the_key() {
+        let err = Config::resolve(&args(&["--server.prot", "1"]), Vec::new()).unwrap_err();
+        assert_eq!(err, ConfigError::UnknownKey("server.prot".to_string()));
+
+        let err = Config::resolve(&[], vars(&[("DOCUMENT_TTL_MINUTES", "soon")])).unwrap_err();
+        assert!(
+            matches!(err, ConfigError::InvalidValue { key, .. } if key == "documents.ttl_minutes")
+        );
+
+        let file = temp_file("bad.toml", "[server]\nport = \"eighty\"\n");
+        let err =
+            Config::resolve(&args(&["--config", file.to_str().unwrap()]), Vec::new()).unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidValue { key, .. } if key == "server.port"));
+        std::fs::remove_file(file).unwrap();
+
+        let err = Config::resolve(&args(&["--server.port", "70000"]), Vec::new()).unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidValue { key, .. } if key == "server.port"));
+
+        let err = Config::resolve(&args(&["serve"]), Vec::new()).unwrap_err();
+        assert_eq!(err, ConfigError::Usage("serve".to_string()));
+
+        let config = Config::resolve(
+            &[],
+            vars(&[
+                ("LINE_CHANNEL_SECRET", "a"),
+                ("LINE_CHANNEL_ACCESS_TOKEN", "b"),
+                ("LINE_CHAT_PROMPT", "c"),
+                ("CHATGPT_API_KEY", "d"),
+                ("CHATGPT_TOP_P", "3"),
+            ]),
+        )
+        .unwrap();
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "generation.top_p")
+        );
+    }
+
+    #[test]
+    fn test_limits_are_validated() {
+        let mut config = valid_config();
+        config.limits.burst = Some(5);
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "limits.burst")
+        );
+        config.limits.requests_per_minute = Some(5);
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_telemetry_is_validated() {
+        let mut config = valid_config();
+        config.telemetry.exporter = Some("jaeger".to_string());
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "telemetry.exporter")
+        );
+        config.telemetry.exporter = Some("otlp".to_string());
+        config.telemetry.otlp_protocol = "http/json".to_string();
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "telemetry.otlp_protocol")
+        );
+        config.telemetry.otlp_protocol = "grpc".to_string();
+        assert_eq!(config.validate(), Ok(()));
+        config.telemetry.exporter = Some("application_insights".to_string());
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::Missing(
+                "telemetry.application_insights_connection_string".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_storage_is_validated() {
+        let mut config = valid_config();
+        config.storage.backend = "postgres".to_string();
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "storage.backend")
+        );
+        config.storage.backend = "sqlite".to_string();
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_redis_is_validated() {
+        let mut config = valid_config();
+        config.redis.url = Some("http://cache:6379".to_string());
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "redis.url")
+        );
+        config.redis.url = Some("redis://:secret@cache:6379/1".to_string());
+        assert_eq!(config.validate(), Ok(()));
+        // TLS, as managed Redis services require
+        config.redis.url = Some("rediss://:secret@cache:6380".to_string());
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_redaction_is_validated() {
+        let mut config = valid_config();
+        config.redaction.detectors.push("IBAN".to_string());
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "redaction.detectors")
+        );
+    }
+
+    #[test]
+    fn test_usage_is_validated() {
+        let mut config = valid_config();
+        let price = PriceConfig {
+            prompt: Some(-0.01),
+            completion: None,
+        };
+        config.usage.prices.insert("gpt-4".to_string(), price);
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "usage.prices.gpt-4.prompt")
+        );
+        config.usage.prices.get_mut("gpt-4").unwrap().prompt = Some(0.0);
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_moderation_is_validated() {
+        let mut config = valid_config();
+        config.moderation.blocklist_patterns.push("(".to_string());
+        assert!(
+            matches!(config.validate(), Err(ConfigError::InvalidValue { key, .. }) if key == "moderation.blocklist_patterns")
+        );
+    }
+
+    #[test]
+    fn test_privacy_retention_caps_storage() {
+        let storage = StorageConfig {
+            turn_retention_days: 90,
+            ..Default::default()
+        };
+        assert_eq!(PrivacyConfig::default().cap(&storage), storage);
+        let privacy = PrivacyConfig { retention_days: 30 };
+        let capped = privacy.cap(&storage);
+        assert_eq!(capped.turn_retention_days, 30);
+        assert_eq!(capped.usage_retention_days, 30);
+        assert_eq!(capped.backend, storage.backend);
+    }
+
+    #[test]
+    fn test_telemetry_exporter_defaults_to_application_insights() {
+        let mut telemetry = TelemetryConfig::default();
+        assert_eq!(telemetry.exporter(), Ok(TelemetryExporter::None));
+        telemetry.application_insights_connection_string = Some("InstrumentationKey=k".into());
+        assert_eq!(
+            telemetry.exporter(),
+            Ok(TelemetryExporter::ApplicationInsights)
+        );
+        telemetry.exporter = Some("stdout".to_string());
+        assert_eq!(telemetry.exporter(), Ok(TelemetryExporter::Stdout));
+    }
+}
@@ -0,0 +1,276 @@
+//! Message content download
+//! # Note
+//! Images, videos, audio and files sent by users are fetched from the data API. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-content)
+//! The body is streamed chunk by chunk, so large files never have to be held in memory.
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Response;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+/// Largest content accepted by default (LINE accepts files up to 300MB).
+pub const MAX_CONTENT_SIZE: u64 = 300 * 1024 * 1024;
+
+/// Enum representing possible errors while downloading message content.
+#[derive(Debug)]
+pub enum ContentError {
+    RequestFailed(String),
+    TooLarge { limit: u64 },
+    Reqwest(reqwest::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for ContentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentError::RequestFailed(message) => write!(f, "{message}"),
+            ContentError::TooLarge { limit } => {
+                write!(f, "Content exceeds the size limit of {limit} bytes")
+            }
+            ContentError::Reqwest(error) => write!(f, "Reqwest error: {error}"),
+            ContentError::Io(error) => write!(f, "IO error: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ContentError {}
+
+impl From<reqwest::Error> for ContentError {
+    fn from(error: reqwest::Error) -> Self {
+        ContentError::Reqwest(error)
+    }
+}
+
+impl From<io::Error> for ContentError {
+    fn from(error: io::Error) -> Self {
+        ContentError::Io(error)
+    }
+}
+
+/// Streaming body of a message content response.
+#[derive(Debug)]
+pub struct MessageContent {
+    response: Response,
+    limit: u64,
+    received: u64,
+}
+
+impl MessageContent {
+    /// # Note
+    /// Wrap a content response. Fails early when the status is not successful
+    /// or the announced `Content-Length` is already above `limit`.
+    pub fn new(response: Response, limit: u64) -> Result<MessageContent, ContentError> {
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ContentError::RequestFailed(format!(
+                "Request failed with status code: {status}"
+            )));
+        }
+        if let Some(length) = response.content_length() {
+            if length > limit {
+                return Err(ContentError::TooLarge { limit });
+            }
+        }
+        Ok(MessageContent {
+            response,
+            limit,
+            received: 0,
+        })
+    }
+
+    /// Size announced by the data API, if any.
+    #[allow(dead_code)]
+    pub fn content_length(&self) -> Option<u64> {
+        self.response.content_length()
+    }
+
+    /// Mime type announced by the data API, if any.
+    #[allow(dead_code)]
+    pub fn content_type(&self) -> Option<&str> {
+        self.response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+    }
+
+    /// # Note
+    /// Read the next chunk of the body. Returns `None` at the end of the content.
+    /// ```
+    /// while let Some(chunk) = content.chunk().await? { ... }
+    /// ```
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, ContentError> {
+        match self.response.chunk().await? {
+            Some(chunk) => {
+                self.received += chunk.len() as u64;
+                if self.received > self.limit {
+                    return Err(ContentError::TooLarge { limit: self.limit });
+                }
+                Ok(Some(chunk))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// # Note
+    /// Copy the whole body into `writer` and return the number of bytes written.
+    /// ```
+    /// let written = content.write_to(&mut writer).await?;
+    /// ```
+    pub async fn write_to<W>(mut self, writer: &mut W) -> Result<u64, ContentError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        while let Some(chunk) = self.chunk().await? {
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        Ok(self.received)
+    }
+
+    /// # Note
+    /// Save the body to `path`, which must not exist yet. A partially written file is
+    /// removed on failure.
+    /// ```
+    /// let written = content.save_to("/tmp/report.pdf").await?;
+    /// ```
+    pub async fn save_to<P: AsRef<Path>>(self, path: P) -> Result<u64, ContentError> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await?;
+        match self.write_to(&mut file).await {
+            Ok(written) => Ok(written),
+            Err(e) => {
+                drop(file);
+                let _ = tokio::fs::remove_file(path).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// # Note
+    /// Turn the body into an `AsyncRead`. Exceeding the size limit surfaces as an `io::Error`.
+    #[allow(dead_code)]
+    pub fn into_async_read(self) -> impl AsyncRead + Unpin {
+        let limit = self.limit;
+        let mut received = self.received;
+        let stream = self
+            .response
+            .bytes_stream()
+            .map_err(io::Error::other)
+            .and_then(move |chunk: Bytes| {
+                received += chunk.len() as u64;
+                let res = if received > limit {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        ContentError::TooLarge { limit },
+                    ))
+                } else {
+                    Ok(chunk)
+                };
+                futures_util::future::ready(res)
+            });
+        StreamReader::new(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // Serve `body` once over HTTP/1.1 on a random local port and return the URL.
+    async fn serve_once(body: Vec<u8>, content_length: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+            let head = if content_length {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n",
+                    body.len()
+                )
+            } else {
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(&body).await.unwrap();
+            socket.shutdown().await.unwrap();
+        });
+        format!("http://{addr}/")
+    }
+
+    async fn fetch(
+        body: Vec<u8>,
+        content_length: bool,
+        limit: u64,
+    ) -> Result<MessageContent, ContentError> {
+        let url = serve_once(body, content_length).await;
+        let response = reqwest::get(url).await.unwrap();
+        MessageContent::new(response, limit)
+    }
+
+    #[tokio::test]
+    async fn test_write_to_copies_body() {
+        let content = fetch(b"hello world".to_vec(), true, 1024).await.unwrap();
+        assert_eq!(content.content_type(), Some("text/plain"));
+        let mut out = Vec::new();
+        let written = content.write_to(&mut out).await.unwrap();
+        assert_eq!(written, 11);
+        assert_eq!(out, b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_content_length_over_limit_is_rejected() {
+        let result = fetch(vec![b'x'; 64], true, 10).await;
+        assert!(matches!(result, Err(ContentError::TooLarge { limit: 10 })));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_over_limit_is_rejected() {
+        let content = fetch(vec![b'x'; 64], false, 10).await.unwrap();
+        let mut out = Vec::new();
+        let result = content.write_to(&mut out).await;
+        assert!(matches!(result, Err(ContentError::TooLarge { limit: 10 })));
+    }
+
+    #[tokio::test]
+    async fn test_save_to_removes_partial_file() {
+        let path = std::env::temp_dir().join(format!("content-{}.bin", rand::random::<u32>()));
+        let content = fetch(vec![b'x'; 64], false, 10).await.unwrap();
+        assert!(content.save_to(&path).await.is_err());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_save_to_keeps_existing_file() {
+        let path = std::env::temp_dir().join(format!("content-{}.bin", rand::random::<u32>()));
+        std::fs::write(&path, "mine").unwrap();
+        let content = fetch(b"theirs".to_vec(), true, 1024).await.unwrap();
+        assert!(content.save_to(&path).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "mine");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_into_async_read() {
+        let content = fetch(b"streamed".to_vec(), true, 1024).await.unwrap();
+        let mut reader = content.into_async_read();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "streamed");
+    }
+}
@@ -0,0 +1,178 @@
+//! Conversation history
+//! # Note
+//! Recent messages of every chat, given to the model before each question, as many of
+//! the latest as fit in its context window. The LINE message id of each one is kept so
+//! that a quoted message can be found again. `/reset` forgets them.
+//!
+//! A storage failure is logged and loses the turn, or reads as an empty conversation,
+//! rather than failing the reply.
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::openai::client::Message;
+use crate::openai::models::Role;
+use crate::openai::tokenizer::count_tokens;
+use crate::storage::{MemoryStorage, Storage};
+
+/// Turns kept per chat by default.
+pub const DEFAULT_MAX_TURNS: usize = 50;
+
+/// One message of a conversation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
+    /// LINE message id, for both user and bot messages.
+    pub message_id: Option<String>,
+    /// Milliseconds since the epoch.
+    pub timestamp: i64,
+}
+
+impl Turn {
+    pub fn new(role: Role, content: &str, message_id: Option<&str>) -> Turn {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        Turn {
+            role,
+            content: content.to_string(),
+            message_id: message_id.map(str::to_string),
+            timestamp,
+        }
+    }
+
+    pub fn to_message(&self) -> Message {
+        Message {
+            role: self.role.clone(),
+            content: self.content.clone(),
+        }
+    }
+}
+
+/// Bounded history of every chat, oldest turns dropped first.
+pub struct ConversationStore {
+    max_turns: usize,
+    storage: Arc<dyn Storage>,
+}
+
+impl ConversationStore {
+    /// A store kept in memory.
+    pub fn new(max_turns: usize) -> ConversationStore {
+        ConversationStore::with_storage(Arc::new(MemoryStorage::new()), max_turns)
+    }
+
+    pub fn with_storage(storage: Arc<dyn Storage>, max_turns: usize) -> ConversationStore {
+        ConversationStore { max_turns, storage }
+    }
+
+    pub async fn push(&self, chat_id: &str, turn: Turn) {
+        if let Err(e) = self.storage.push_turn(chat_id, &turn, self.max_turns).await {
+            error!("Failed storing turn of {}: {}", chat_id, e);
+        }
+    }
+
+    /// The turn of the chat with the LINE message id `message_id`.
+    pub async fn find(&self, chat_id: &str, message_id: &str) -> Option<Turn> {
+        self.storage
+            .find_turn(chat_id, message_id)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed reading turn of {}: {}", chat_id, e);
+                None
+            })
+    }
+
+    /// Every stored turn of the chat, oldest first.
+    pub async fn turns(&self, chat_id: &str) -> Vec<Turn> {
+        self.storage.turns(chat_id).await.unwrap_or_else(|e| {
+            error!("Failed reading turns of {}: {}", chat_id, e);
+            Vec::new()
+        })
+    }
+
+    /// The latest turns of the chat that fit in `max_tokens`, oldest first.
+    pub async fn recent(&self, chat_id: &str, max_tokens: usize) -> Vec<Turn> {
+        let mut used_tokens = 0;
+        let mut recent: Vec<Turn> = self
+            .turns(chat_id)
+            .await
+            .into_iter()
+            .rev()
+            .take_while(|turn| {
+                used_tokens += count_tokens(&turn.content);
+                used_tokens <= max_tokens
+            })
+            .collect();
+        recent.reverse();
+        recent
+    }
+
+    pub async fn clear(&self, chat_id: &str) {
+        if let Err(e) = self.storage.clear_turns(chat_id).await {
+            error!("Failed clearing turns of {}: {}", chat_id, e);
+        }
+    }
+}
+
+impl Default for ConversationStore {
+    fn default() -> Self {
+        ConversationStore::new(DEFAULT_MAX_TURNS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_find_quoted_turn() {
+        let store = ConversationStore::default();
+        store
+            .push("U1", Turn::new(Role::User, "hi", Some("100")))
+            .await;
+        store
+            .push("U1", Turn::new(Role::Assistant, "hello!", Some("101")))
+            .await;
+
+        let turn = store.find("U1", "101").await.unwrap();
+        assert_eq!(turn.role, Role::Assistant);
+        assert_eq!(turn.content, "hello!");
+        assert!(store.find("U1", "999").await.is_none());
+        assert!(store.find("U2", "101").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oldest_turns_are_dropped() {
+        let store = ConversationStore::new(2);
+        for i in 0..3 {
+            store
+                .push("U1", Turn::new(Role::User, &i.to_string(), None))
+                .await;
+        }
+        let turns = store.turns("U1").await;
+        let contents: Vec<String> = turns.into_iter().map(|t| t.content).collect();
+        assert_eq!(contents, vec!["1", "2"]);
+
+        store.clear("U1").await;
+        assert!(store.turns("U1").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recent_turns_fit_the_budget() {
+        let store = ConversationStore::default();
+        for content in ["a".repeat(40), "b".repeat(40), "c".repeat(40)] {
+            store
+                .push("U1", Turn::new(Role::User, &content, None))
+                .await;
+        }
+        // 10 tokens each
+        let recent = store.recent("U1", 25).await;
+        let contents: Vec<String> = recent.into_iter().map(|t| t.content).collect();
+        assert_eq!(contents, vec!["b".repeat(40), "c".repeat(40)]);
+        assert!(store.recent("U1", 5).await.is_empty());
+    }
+}
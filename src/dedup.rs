@@ -0,0 +1,92 @@
+//! Webhook deduplication
+//! # Note
+//! LINE delivers an event again, with the same `webhookEventId`, when the webhook did
+//! not answer in time. An event id seen within `DEDUP_TTL` is skipped, so that a
+//! question is answered once. With Redis configured, a redelivery reaching another
+//! replica is skipped too; the ids are kept in process otherwise, and while Redis cannot
+//! be reached.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+use crate::shared::SharedState;
+
+/// How long the id of a handled event is remembered.
+pub const DEDUP_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+pub struct Deduplicator {
+    /// Event ids and when they were first seen.
+    seen: Mutex<HashMap<String, Instant>>,
+    shared: Option<Arc<SharedState>>,
+}
+
+impl Deduplicator {
+    pub fn new(shared: Option<Arc<SharedState>>) -> Deduplicator {
+        Deduplicator {
+            shared,
+            ..Default::default()
+        }
+    }
+
+    /// `true` unless the event was already seen within `DEDUP_TTL`.
+    pub async fn first_delivery(&self, event_id: &str) -> bool {
+        if let Some(shared) = &self.shared {
+            match shared.first_delivery(event_id, DEDUP_TTL).await {
+                Ok(first) => return first,
+                Err(e) => error!("Failed checking event {} in Redis: {}", event_id, e),
+            }
+        }
+        self.first_delivery_at(event_id, Instant::now())
+    }
+
+    fn first_delivery_at(&self, event_id: &str, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| now.saturating_duration_since(*at) < DEDUP_TTL);
+        if seen.contains_key(event_id) {
+            return false;
+        }
+        seen.insert(event_id.to_string(), now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::Value;
+
+    use super::*;
+    use crate::config::RedisConfig;
+    use crate::shared::testing::{first_delivery_cmd, mock, MockCmd};
+
+    #[test]
+    fn test_redelivered_event_is_skipped() {
+        let dedup = Deduplicator::new(None);
+        let now = Instant::now();
+        assert!(dedup.first_delivery_at("01H", now));
+        assert!(!dedup.first_delivery_at("01H", now + Duration::from_secs(1)));
+        assert!(dedup.first_delivery_at("01J", now));
+        // Forgotten after a while
+        assert!(dedup.first_delivery_at("01H", now + DEDUP_TTL));
+    }
+
+    #[tokio::test]
+    async fn test_replicas_skip_each_other_events() {
+        // Another replica handled the event already
+        let handled = MockCmd::new(first_delivery_cmd("01H", DEDUP_TTL), Ok(Value::Nil));
+        let replica = Deduplicator::new(Some(Arc::new(mock(vec![handled]))));
+        assert!(!replica.first_delivery("01H").await);
+
+        // Without Redis, each replica remembers its own
+        let unreachable = RedisConfig {
+            url: Some("redis://127.0.0.1:1".to_string()),
+            ..Default::default()
+        };
+        let shared = SharedState::open(&unreachable).unwrap().map(Arc::new);
+        let replica = Deduplicator::new(shared);
+        assert!(replica.first_delivery("01H").await);
+        assert!(!replica.first_delivery("01H").await);
+    }
+}
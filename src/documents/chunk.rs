@@ -0,0 +1,88 @@
+use crate::openai::tokenizer::count_tokens;
+
+/// Splits `text` into chunks of at most `max_tokens` tokens.
+///
+/// Paragraphs are kept together when they fit, otherwise they are split by
+/// line and, as a last resort, by character count.
+///
+/// # Arguments
+///
+/// * `text` - The extracted document text.
+/// * `max_tokens` - The token budget of a single chunk.
+pub fn chunk_text(text: &str, max_tokens: usize) -> Vec<String> {
+    let max_chars = max_tokens.max(1) * 4;
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for piece in pieces(text, max_tokens, max_chars) {
+        let joined = if current.is_empty() {
+            piece.clone()
+        } else {
+            format!("{current}\n\n{piece}")
+        };
+        if count_tokens(&joined) <= max_tokens {
+            current = joined;
+        } else {
+            if !current.is_empty() {
+                chunks.push(current);
+            }
+            current = piece;
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// Paragraphs, or smaller pieces of paragraphs that are over the budget on their own.
+fn pieces(text: &str, max_tokens: usize, max_chars: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        if count_tokens(paragraph) <= max_tokens {
+            pieces.push(paragraph.to_string());
+            continue;
+        }
+        for line in paragraph.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if count_tokens(line) <= max_tokens {
+                pieces.push(line.to_string());
+                continue;
+            }
+            let chars: Vec<char> = line.chars().collect();
+            for window in chars.chunks(max_chars) {
+                pieces.push(window.iter().collect());
+            }
+        }
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_text_is_one_chunk() {
+        let chunks = chunk_text("First paragraph.\n\nSecond paragraph.", 100);
+        assert_eq!(chunks, vec!["First paragraph.\n\nSecond paragraph."]);
+    }
+
+    #[test]
+    fn test_paragraphs_are_split_at_budget() {
+        let text = format!("{}\n\n{}", "a".repeat(40), "b".repeat(40));
+        let chunks = chunk_text(&text, 10);
+        assert_eq!(chunks, vec!["a".repeat(40), "b".repeat(40)]);
+    }
+
+    #[test]
+    fn test_long_line_is_split_by_characters() {
+        let chunks = chunk_text(&"x".repeat(100), 10);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| count_tokens(c) <= 10));
+    }
+
+    #[test]
+    fn test_empty_text() {
+        assert!(chunk_text("  \n\n ", 10).is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use crate::documents::DocumentError;
+
+/// File types the bot can read.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DocumentKind {
+    Text,
+    Markdown,
+    Csv,
+    Pdf,
+}
+
+impl DocumentKind {
+    /// Detects the kind from the extension of `file_name`, as sent in `FileMessage`.
+    pub fn from_file_name(file_name: &str) -> Option<DocumentKind> {
+        let extension = Path::new(file_name)
+            .extension()?
+            .to_str()?
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "txt" => Some(DocumentKind::Text),
+            "md" | "markdown" => Some(DocumentKind::Markdown),
+            "csv" => Some(DocumentKind::Csv),
+            "pdf" => Some(DocumentKind::Pdf),
+            _ => None,
+        }
+    }
+}
+
+/// Extracts the plain text of the downloaded file at `path`.
+///
+/// PDF parsing is CPU bound and runs on the blocking thread pool.
+pub async fn extract_text(kind: DocumentKind, path: &Path) -> Result<String, DocumentError> {
+    let text = match kind {
+        DocumentKind::Text | DocumentKind::Markdown | DocumentKind::Csv => {
+            let bytes = tokio::fs::read(path).await?;
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        DocumentKind::Pdf => {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || pdf_extract::extract_text(path))
+                .await
+                .map_err(|e| DocumentError::Extract(e.to_string()))?
+                .map_err(|e| DocumentError::Extract(e.to_string()))?
+        }
+    };
+    let text = text.replace("\r\n", "\n");
+    if text.trim().is_empty() {
+        return Err(DocumentError::Empty);
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_from_file_name() {
+        assert_eq!(
+            DocumentKind::from_file_name("notes.TXT"),
+            Some(DocumentKind::Text)
+        );
+        assert_eq!(
+            DocumentKind::from_file_name("README.md"),
+            Some(DocumentKind::Markdown)
+        );
+        assert_eq!(
+            DocumentKind::from_file_name("sales.csv"),
+            Some(DocumentKind::Csv)
+        );
+        assert_eq!(
+            DocumentKind::from_file_name("report.pdf"),
+            Some(DocumentKind::Pdf)
+        );
+        assert_eq!(DocumentKind::from_file_name("photo.jpg"), None);
+        assert_eq!(DocumentKind::from_file_name("Makefile"), None);
+    }
+
+    #[tokio::test]
+    async fn test_extract_plain_text() {
+        let path = std::env::temp_dir().join(format!("extract-{}.txt", rand::random::<u32>()));
+        tokio::fs::write(&path, "line one\r\nline two")
+            .await
+            .unwrap();
+        let text = extract_text(DocumentKind::Text, &path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(text, "line one\nline two");
+    }
+
+    #[tokio::test]
+    async fn test_extract_empty_file() {
+        let path = std::env::temp_dir().join(format!("extract-{}.md", rand::random::<u32>()));
+        tokio::fs::write(&path, "  \n").await.unwrap();
+        let result = extract_text(DocumentKind::Markdown, &path).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(matches!(result, Err(DocumentError::Empty)));
+    }
+}
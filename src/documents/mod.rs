@@ -0,0 +1,242 @@
+//! Document Q&A
+//! # Note
+//! Files uploaded to a chat are downloaded, converted to text and chunked.
+//! For a while afterwards, questions in that chat are answered from the document.
+pub mod chunk;
+pub mod extract;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::bot::LineBot;
+use crate::content::ContentError;
+use crate::events::messages::FileMessage;
+use crate::openai::tokenizer::count_tokens;
+
+pub use chunk::chunk_text;
+pub use extract::{extract_text, DocumentKind};
+
+/// Largest file the bot downloads for Q&A.
+pub const MAX_DOCUMENT_SIZE: u64 = 20 * 1024 * 1024;
+
+/// Token budget of one chunk.
+pub const CHUNK_TOKENS: usize = 500;
+
+/// Enum representing possible errors while loading a document.
+#[derive(Debug)]
+pub enum DocumentError {
+    Unsupported(String),
+    TooLarge { size: i64 },
+    Empty,
+    Extract(String),
+    Content(ContentError),
+    Io(io::Error),
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::Unsupported(file_name) => write!(f, "Unsupported file: {file_name}"),
+            DocumentError::TooLarge { size } => write!(f, "File is too large: {size} bytes"),
+            DocumentError::Empty => write!(f, "Document has no text"),
+            DocumentError::Extract(message) => write!(f, "Text extraction failed: {message}"),
+            DocumentError::Content(error) => write!(f, "Download failed: {error}"),
+            DocumentError::Io(error) => write!(f, "IO error: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+impl From<ContentError> for DocumentError {
+    fn from(error: ContentError) -> Self {
+        DocumentError::Content(error)
+    }
+}
+
+impl From<io::Error> for DocumentError {
+    fn from(error: io::Error) -> Self {
+        DocumentError::Io(error)
+    }
+}
+
+/// A chunked document attached to a chat.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub file_name: String,
+    pub chunks: Vec<String>,
+}
+
+impl Document {
+    /// Picks the chunks most relevant to `question` that fit into `budget` tokens.
+    ///
+    /// Chunks are ranked by how many words of the question they contain and
+    /// returned in document order, so the excerpt still reads naturally.
+    pub fn excerpt(&self, question: &str, budget: usize) -> String {
+        let terms: Vec<String> = question
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| t.chars().count() >= 3)
+            .map(str::to_lowercase)
+            .collect();
+
+        let mut ranked: Vec<(usize, usize)> = self
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let chunk = chunk.to_lowercase();
+                let score = terms.iter().filter(|t| chunk.contains(t.as_str())).count();
+                (i, score)
+            })
+            .collect();
+        // Stable sort: ties keep the document order.
+        ranked.sort_by_key(|(_, score)| Reverse(*score));
+
+        let mut used = 0;
+        let mut picked = Vec::new();
+        for (i, _) in ranked {
+            let tokens = count_tokens(&self.chunks[i]);
+            if used + tokens > budget {
+                continue;
+            }
+            used += tokens;
+            picked.push(i);
+        }
+        picked.sort_unstable();
+        picked
+            .iter()
+            .map(|i| self.chunks[*i].as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n---\n\n")
+    }
+
+    /// System prompt that grounds the answer in the excerpt.
+    pub fn system_prompt(&self, excerpt: &str) -> String {
+        format!(
+            "Answer the user's question using the document \"{}\" below. \
+             If the document does not contain the answer, say so.\n\n{}",
+            self.file_name, excerpt
+        )
+    }
+}
+
+/// Active documents keyed by chat id, each expiring after `ttl`.
+#[derive(Debug)]
+pub struct DocumentStore {
+    ttl: Duration,
+    documents: Mutex<HashMap<String, (Document, Instant)>>,
+}
+
+impl DocumentStore {
+    pub fn new(ttl: Duration) -> DocumentStore {
+        DocumentStore {
+            ttl,
+            documents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Attaches `document` to the chat, replacing the previous one.
+    pub fn insert(&self, chat_id: &str, document: Document) {
+        let expires_at = Instant::now() + self.ttl;
+        let mut documents = self.documents.lock().unwrap();
+        documents.retain(|_, (_, expires)| *expires > Instant::now());
+        documents.insert(chat_id.to_string(), (document, expires_at));
+    }
+
+    /// The document attached to the chat, unless it has expired.
+    pub fn get(&self, chat_id: &str) -> Option<Document> {
+        let mut documents = self.documents.lock().unwrap();
+        match documents.get(chat_id) {
+            Some((document, expires)) if *expires > Instant::now() => Some(document.clone()),
+            Some(_) => {
+                documents.remove(chat_id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn remove(&self, chat_id: &str) {
+        self.documents.lock().unwrap().remove(chat_id);
+    }
+}
+
+/// Downloads the file of `message`, extracts its text and splits it into chunks.
+pub async fn load_document(
+    bot: &LineBot,
+    message: &FileMessage,
+) -> Result<Document, DocumentError> {
+    let kind = DocumentKind::from_file_name(&message.file_name)
+        .ok_or_else(|| DocumentError::Unsupported(message.file_name.clone()))?;
+    if message.file_size as u64 > MAX_DOCUMENT_SIZE {
+        return Err(DocumentError::TooLarge {
+            size: message.file_size,
+        });
+    }
+
+    let content = bot
+        .get_message_content_with_limit(&message.id, MAX_DOCUMENT_SIZE)
+        .await?;
+    // A random name, and `save_to` never writes through an existing file or link
+    let path = std::env::temp_dir().join(format!("line-document-{}", rand::random::<u64>()));
+    content.save_to(&path).await?;
+    let text = extract_text(kind, &path).await;
+    let _ = tokio::fs::remove_file(&path).await;
+
+    let chunks = chunk_text(&text?, CHUNK_TOKENS);
+    Ok(Document {
+        file_name: message.file_name.clone(),
+        chunks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> Document {
+        Document {
+            file_name: "handbook.md".to_string(),
+            chunks: vec![
+                "Office hours are 9 to 5.".to_string(),
+                "Annual leave is 15 days per year.".to_string(),
+                "Parking is free for staff.".to_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_excerpt_prefers_matching_chunks() {
+        let excerpt = document().excerpt("How many days of annual leave?", 10);
+        assert_eq!(excerpt, "Annual leave is 15 days per year.");
+    }
+
+    #[test]
+    fn test_excerpt_keeps_document_order() {
+        let excerpt = document().excerpt("parking and office", 100);
+        assert_eq!(
+            excerpt,
+            "Office hours are 9 to 5.\n\n---\n\nAnnual leave is 15 days per year.\n\n---\n\nParking is free for staff."
+        );
+    }
+
+    #[test]
+    fn test_store_expires_documents() {
+        let store = DocumentStore::new(Duration::from_secs(60));
+        store.insert("U1", document());
+        assert!(store.get("U1").is_some());
+        assert!(store.get("U2").is_none());
+
+        let expired = DocumentStore::new(Duration::ZERO);
+        expired.insert("U1", document());
+        assert!(expired.get("U1").is_none());
+    }
+}
@@ -11,4 +11,6 @@ pub struct Mentionee {
     pub length: i64,
     #[serde(rename = "userId")]
     pub user_id: Option<String>,
+    #[serde(rename = "isSelf")]
+    pub is_self: Option<bool>,
 }
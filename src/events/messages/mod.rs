@@ -19,10 +19,6 @@ pub use sticker_message::StickerMessage;
 pub use text_message::TextMessage;
 pub use video_message::VideoMessage;
 
-pub use content_provider::ContentProvider;
-pub use emoji::Emoji;
-pub use mention::{Mention, Mentionee};
-
 use serde_derive::Deserialize;
 
 #[derive(Deserialize, Debug)]
@@ -43,6 +39,7 @@ pub struct Message {
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
 pub enum MessageType {
     #[serde(rename = "text")]
     TextMessage(TextMessage),
@@ -1,5 +1,5 @@
 use crate::events::messages::emoji::Emoji;
-use crate::events::messages::mention::Mention;
+use crate::events::messages::mention::{Mention, Mentionee};
 
 use serde_derive::Deserialize;
 
@@ -9,4 +9,108 @@ pub struct TextMessage {
     pub text: String,
     pub emojis: Option<Vec<Emoji>>,
     pub mention: Option<Mention>,
+    /// Token for quoting this message in a reply.
+    #[serde(rename = "quoteToken")]
+    pub quote_token: Option<String>,
+    /// Id of the message this message quotes, if any.
+    #[serde(rename = "quotedMessageId")]
+    pub quoted_message_id: Option<String>,
+}
+
+impl TextMessage {
+    /// Whether the user `user_id` is @mentioned in the message.
+    pub fn mentions(&self, user_id: &str) -> bool {
+        self.mentionees_of(user_id).next().is_some()
+    }
+
+    /// The text with every @mention of `user_id` removed.
+    ///
+    /// Mention positions are given by LINE in UTF-16 code units.
+    pub fn text_without_mentions_of(&self, user_id: &str) -> String {
+        let mut spans: Vec<(usize, usize)> = self
+            .mentionees_of(user_id)
+            .map(|m| (m.index.max(0) as usize, m.length.max(0) as usize))
+            .collect();
+        spans.sort_unstable();
+
+        let units: Vec<u16> = self.text.encode_utf16().collect();
+        let mut kept = Vec::with_capacity(units.len());
+        let mut position = 0;
+        for (index, length) in spans {
+            let start = index.clamp(position, units.len());
+            kept.extend_from_slice(&units[position..start]);
+            position = (index + length).clamp(start, units.len());
+        }
+        kept.extend_from_slice(&units[position..]);
+
+        let text = String::from_utf16_lossy(&kept);
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    fn mentionees_of<'a>(&'a self, user_id: &'a str) -> impl Iterator<Item = &'a Mentionee> {
+        self.mention
+            .iter()
+            .flat_map(|m| m.mentionees.iter())
+            .filter(move |m| m.user_id.as_deref() == Some(user_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(json: &str) -> TextMessage {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_mentions_and_strip() {
+        let message = message(
+            r#"{
+                "id": "1",
+                "text": "@GPT Bot what is the weather @Alice ?",
+                "mention": {"mentionees": [
+                    {"index": 0, "length": 8, "userId": "Ubot", "type": "user", "isSelf": true},
+                    {"index": 29, "length": 6, "userId": "Ualice", "type": "user"}
+                ]}
+            }"#,
+        );
+        assert!(message.mentions("Ubot"));
+        assert!(!message.mentions("Ucarol"));
+        assert_eq!(
+            message.text_without_mentions_of("Ubot"),
+            "what is the weather @Alice ?"
+        );
+    }
+
+    #[test]
+    fn test_strip_uses_utf16_positions() {
+        // "🤖" is two UTF-16 code units
+        let message = message(
+            r#"{
+                "id": "1",
+                "text": "สวัสดี 🤖 @Bot ช่วยด้วย",
+                "mention": {"mentionees": [
+                    {"index": 10, "length": 4, "userId": "Ubot"}
+                ]}
+            }"#,
+        );
+        assert_eq!(message.text_without_mentions_of("Ubot"), "สวัสดี 🤖 ช่วยด้วย");
+    }
+
+    #[test]
+    fn test_no_mention() {
+        let message = message(r#"{"id": "1", "text": "hello"}"#);
+        assert!(!message.mentions("Ubot"));
+        assert_eq!(message.text_without_mentions_of("Ubot"), "hello");
+        assert!(message.quote_token.is_none());
+    }
+
+    #[test]
+    fn test_quote_fields() {
+        let message =
+            message(r#"{"id": "2", "text": "why?", "quoteToken": "q2", "quotedMessageId": "1"}"#);
+        assert_eq!(message.quote_token.as_deref(), Some("q2"));
+        assert_eq!(message.quoted_message_id.as_deref(), Some("1"));
+    }
 }
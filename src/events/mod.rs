@@ -44,6 +44,17 @@ pub struct Events {
 pub struct Event {
     #[serde(flatten)]
     pub r#type: EventType,
+    /// Unique id of the event, the same when it is delivered again.
+    #[serde(rename = "webhookEventId")]
+    pub webhook_event_id: Option<String>,
+    #[serde(rename = "deliveryContext")]
+    pub delivery_context: Option<DeliveryContext>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeliveryContext {
+    #[serde(rename = "isRedelivery")]
+    pub is_redelivery: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -78,3 +89,25 @@ pub enum EventType {
     #[serde(other)]
     Other,
 }
+
+impl EventType {
+    /// The `type` of the event, as LINE names it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventType::UnsendEvent(_) => "unsend",
+            EventType::FollowEvent(_) => "follow",
+            EventType::UnFollowEvent(_) => "unfollow",
+            EventType::JoinEvent(_) => "join",
+            EventType::LeaveEvent(_) => "leave",
+            EventType::MemberJoinEvent(_) => "memberJoined",
+            EventType::MemberLeaveEvent(_) => "memberLeft",
+            EventType::PostBackEvent(_) => "postback",
+            EventType::VideoPlayCompleteEvent(_) => "videoPlayComplete",
+            EventType::BeaconEvent(_) => "beacon",
+            EventType::AccountLinkEvent(_) => "accountLink",
+            EventType::ThingsEvent(_) => "things",
+            EventType::MessageEvent(_) => "message",
+            EventType::Other => "other",
+        }
+    }
+}
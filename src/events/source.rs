@@ -38,3 +38,23 @@ pub struct Room {
     #[serde(rename = "userId")]
     pub user_id: Option<String>,
 }
+
+impl Source {
+    /// Id of the chat the event came from: the user, group or room id.
+    pub fn id(&self) -> &str {
+        match &self.r#type {
+            SouceType::User(user) => &user.user_id,
+            SouceType::Group(group) => &group.group_id,
+            SouceType::Room(room) => &room.room_id,
+        }
+    }
+
+    /// Id of the user who triggered the event, when LINE provides it.
+    pub fn user_id(&self) -> Option<&str> {
+        match &self.r#type {
+            SouceType::User(user) => Some(&user.user_id),
+            SouceType::Group(group) => group.user_id.as_deref(),
+            SouceType::Room(room) => room.user_id.as_deref(),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Generation parameters
+//! # Note
+//! Sampling settings sent with every completion. The global values are the
+//! `[generation]` section of the configuration; a persona can override any of them.
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::openai::client::{ChatInput, Message};
+use crate::openai::models::Model;
+
+/// Most stop sequences OpenAI accepts.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Sampling settings of a completion, unset values left to OpenAI's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default)]
+    pub stop: Option<Vec<String>>,
+    /// Send a hash of the LINE user id as `user`, which lets OpenAI tell abusive users apart.
+    #[serde(default)]
+    pub send_user: Option<bool>,
+}
+
+/// A generation parameter with an invalid value.
+#[derive(Debug, PartialEq)]
+pub struct GenerationError {
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for GenerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+impl std::error::Error for GenerationError {}
+
+impl GenerationParams {
+    /// Checks every set value is in the range OpenAI accepts.
+    pub fn validate(&self) -> Result<(), GenerationError> {
+        check_range("temperature", self.temperature, 0.0, 2.0)?;
+        check_range("top_p", self.top_p, 0.0, 1.0)?;
+        check_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        check_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        if self.max_tokens == Some(0) {
+            return Err(error("max_tokens", "must be at least 1"));
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > MAX_STOP_SEQUENCES {
+                return Err(error(
+                    "stop",
+                    &format!("at most {MAX_STOP_SEQUENCES} sequences allowed"),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// These parameters with the values set in `overrides` replacing them.
+    pub fn merge(&self, overrides: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+            send_user: overrides.send_user.or(self.send_user),
+        }
+    }
+
+    /// The chat request for `messages` with these parameters.
+    pub fn chat_input(
+        &self,
+        model: Model,
+        messages: Vec<Message>,
+        user_id: Option<&str>,
+    ) -> ChatInput {
+        let user = match user_id {
+            Some(user_id) if self.send_user == Some(true) => Some(hash_user_id(user_id)),
+            _ => None,
+        };
+        ChatInput {
+            model,
+            messages,
+            temperature: self.temperature.map(f64::from),
+            top_p: self.top_p.map(f64::from),
+            max_tokens: self.max_tokens,
+            presence_penalty: self.presence_penalty.map(f64::from),
+            frequency_penalty: self.frequency_penalty.map(f64::from),
+            stop: self.stop.clone(),
+            user,
+            ..Default::default()
+        }
+    }
+}
+
+fn check_range(key: &str, value: Option<f32>, min: f32, max: f32) -> Result<(), GenerationError> {
+    match value {
+        Some(v) if !(min..=max).contains(&v) => {
+            Err(error(key, &format!("{v} is not from {min} to {max}")))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn error(key: &str, message: &str) -> GenerationError {
+    GenerationError {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Stable pseudonym of a LINE user id, so OpenAI never sees the id itself.
+fn hash_user_id(user_id: &str) -> String {
+    Sha256::digest(user_id.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_names_the_parameter() {
+        let params = GenerationParams {
+            top_p: Some(1.5),
+            ..Default::default()
+        };
+        assert_eq!(params.validate().unwrap_err().key, "top_p");
+        let params = GenerationParams {
+            stop: Some(vec!["a".to_string(); 5]),
+            ..Default::default()
+        };
+        assert_eq!(params.validate().unwrap_err().key, "stop");
+        assert_eq!(GenerationParams::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_merge_and_chat_input() {
+        let global = GenerationParams {
+            temperature: Some(1.0),
+            max_tokens: Some(1000),
+            send_user: Some(true),
+            ..Default::default()
+        };
+        let persona = GenerationParams {
+            temperature: Some(0.2),
+            ..Default::default()
+        };
+        let params = global.merge(&persona);
+        assert_eq!(params.temperature, Some(0.2));
+        assert_eq!(params.max_tokens, Some(1000));
+
+        let input = params.chat_input(Model::Gpt_4, Vec::new(), Some("U1"));
+        assert_eq!(input.max_tokens, Some(1000));
+        let user = input.user.unwrap();
+        assert_eq!(user.len(), 64);
+        assert!(!user.contains("U1"));
+    }
+}
@@ -0,0 +1,129 @@
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// One embedded passage of the knowledge base.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Path of the Markdown file, relative to the ingested folder.
+    pub source: String,
+    /// Closest heading above the passage.
+    pub heading: Option<String>,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+impl IndexEntry {
+    /// Human readable reference, e.g. `faq/leave.md § Annual leave`.
+    pub fn citation(&self) -> String {
+        match &self.heading {
+            Some(heading) => format!("{} § {}", self.source, heading),
+            None => self.source.clone(),
+        }
+    }
+}
+
+/// On-disk vector index searched by cosine similarity.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct VectorIndex {
+    /// Embedding model the vectors were produced with.
+    pub model: String,
+    pub entries: Vec<IndexEntry>,
+}
+
+impl VectorIndex {
+    #[cfg(test)]
+    pub fn new(model: &str) -> VectorIndex {
+        VectorIndex {
+            model: model.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<VectorIndex> {
+        let file = std::fs::File::open(path)?;
+        serde_json::from_reader(io::BufReader::new(file)).map_err(io::Error::from)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer(io::BufWriter::new(file), self).map_err(io::Error::from)
+    }
+
+    /// The `k` entries most similar to `query`, best first.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(f32, &IndexEntry)> {
+        let mut scored: Vec<(f32, &IndexEntry)> = self
+            .entries
+            .iter()
+            .map(|entry| (cosine_similarity(query, &entry.embedding), entry))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(k);
+        scored
+    }
+}
+
+/// Cosine similarity of two vectors, 0 when either is empty or zero.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str, embedding: Vec<f32>) -> IndexEntry {
+        IndexEntry {
+            source: source.to_string(),
+            heading: None,
+            text: source.to_string(),
+            embedding,
+        }
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[1.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 2.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 2.0]), 0.0);
+    }
+
+    #[test]
+    fn test_search_top_k() {
+        let mut index = VectorIndex::new("test");
+        index.entries.push(entry("a.md", vec![1.0, 0.0]));
+        index.entries.push(entry("b.md", vec![0.0, 1.0]));
+        index.entries.push(entry("c.md", vec![0.7, 0.7]));
+
+        let results = index.search(&[0.0, 1.0], 2);
+        let sources: Vec<&str> = results.iter().map(|(_, e)| e.source.as_str()).collect();
+        assert_eq!(sources, vec!["b.md", "c.md"]);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("index-{}.json", rand::random::<u32>()));
+        let mut index = VectorIndex::new("test");
+        index.entries.push(IndexEntry {
+            heading: Some("Leave".to_string()),
+            ..entry("hr.md", vec![0.5, 0.5])
+        });
+        index.save(&path).unwrap();
+        let loaded = VectorIndex::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.model, "test");
+        assert_eq!(loaded.entries[0].citation(), "hr.md § Leave");
+        assert_eq!(loaded.entries[0].embedding, vec![0.5, 0.5]);
+    }
+}
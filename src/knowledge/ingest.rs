@@ -0,0 +1,204 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::documents::chunk_text;
+use crate::knowledge::index::{IndexEntry, VectorIndex};
+use crate::knowledge::KnowledgeError;
+use crate::openai::embeddings::EmbeddingsClient;
+
+/// Token budget of one indexed passage.
+const PASSAGE_TOKENS: usize = 300;
+
+/// Inputs sent per embeddings request.
+const EMBEDDING_BATCH: usize = 64;
+
+/// A Markdown section: the text under one heading.
+#[derive(Debug, PartialEq)]
+pub struct Section {
+    pub heading: Option<String>,
+    pub text: String,
+}
+
+/// Splits Markdown into sections at every ATX heading (`#`, `##`, ...), except inside
+/// fenced code blocks.
+pub fn split_markdown(markdown: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut heading = None;
+    let mut text = String::new();
+    // The run of backticks or tildes that opened the code block we are in
+    let mut fence: Option<&str> = None;
+    for line in markdown.lines() {
+        let marker = code_fence(line);
+        match (fence, marker) {
+            (None, Some(marker)) => fence = Some(marker),
+            (Some(open), Some(marker)) if marker.starts_with(open) => fence = None,
+            (None, None) => {
+                if let Some(title) = atx_heading(line) {
+                    push_section(&mut sections, heading.take(), &text);
+                    text.clear();
+                    heading = Some(title.to_string());
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    push_section(&mut sections, heading, &text);
+    sections
+}
+
+/// The title of an ATX heading: up to 3 spaces, 1 to 6 `#`, then a space or nothing.
+fn atx_heading(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let title = trimmed.trim_start_matches('#');
+    let level = trimmed.len() - title.len();
+    if !(1..=6).contains(&level) || !(title.is_empty() || title.starts_with([' ', '\t'])) {
+        return None;
+    }
+    // An optional closing sequence of `#`
+    let title = title.trim();
+    let unclosed = title.trim_end_matches('#');
+    if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) {
+        return Some(unclosed.trim_end());
+    }
+    Some(title)
+}
+
+/// The backticks or tildes opening or closing a fenced code block on `line`.
+fn code_fence(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let marker = &trimmed[..trimmed.len() - trimmed.trim_start_matches(fence_char).len()];
+    (marker.len() >= 3).then_some(marker)
+}
+
+fn push_section(sections: &mut Vec<Section>, heading: Option<String>, text: &str) {
+    if !text.trim().is_empty() {
+        sections.push(Section {
+            heading,
+            text: text.trim().to_string(),
+        });
+    }
+}
+
+fn markdown_files(folder: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            markdown_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("md"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Embeds every Markdown file below `folder` into a new index.
+pub async fn ingest_folder(
+    client: &EmbeddingsClient,
+    folder: &Path,
+) -> Result<VectorIndex, KnowledgeError> {
+    let mut files = Vec::new();
+    markdown_files(folder, &mut files)?;
+    files.sort();
+
+    let mut entries = Vec::new();
+    for file in &files {
+        let source = file
+            .strip_prefix(folder)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let markdown = std::fs::read_to_string(file)?;
+        for section in split_markdown(&markdown) {
+            for text in chunk_text(&section.text, PASSAGE_TOKENS) {
+                entries.push(IndexEntry {
+                    source: source.clone(),
+                    heading: section.heading.clone(),
+                    text,
+                    embedding: Vec::new(),
+                });
+            }
+        }
+        info!("Read {}", source);
+    }
+
+    for batch in entries.chunks_mut(EMBEDDING_BATCH) {
+        let input = batch.iter().map(|e| e.text.clone()).collect();
+        let embeddings = client.embed(input).await?;
+        for (entry, embedding) in batch.iter_mut().zip(embeddings) {
+            entry.embedding = embedding;
+        }
+    }
+    info!(
+        "Indexed {} passages from {} files",
+        entries.len(),
+        files.len()
+    );
+
+    Ok(VectorIndex {
+        model: client.model().to_string(),
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_markdown() {
+        let markdown = "Intro text\n\n# Leave\nAnnual leave is 15 days.\n\n## Sick leave\nUp to 30 days.\n# Empty\n";
+        let sections = split_markdown(markdown);
+        assert_eq!(
+            sections,
+            vec![
+                Section {
+                    heading: None,
+                    text: "Intro text".to_string()
+                },
+                Section {
+                    heading: Some("Leave".to_string()),
+                    text: "Annual leave is 15 days.".to_string()
+                },
+                Section {
+                    heading: Some("Sick leave".to_string()),
+                    text: "Up to 30 days.".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_blocks_and_hashtags_are_not_headings() {
+        let markdown = "# Build\n#hashtag stays text\n```c\n#include <stdio.h>\n# comment\n```\nAfter the code.\n#### Notes ###\n~~~\n## not a heading\n~~~\n";
+        let sections = split_markdown(markdown);
+        assert_eq!(
+            sections,
+            vec![
+                Section {
+                    heading: Some("Build".to_string()),
+                    text: "#hashtag stays text\n```c\n#include <stdio.h>\n# comment\n```\nAfter the code."
+                        .to_string()
+                },
+                Section {
+                    heading: Some("Notes".to_string()),
+                    text: "~~~\n## not a heading\n~~~".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Retrieval-augmented generation
+//! # Note
+//! Internal Markdown documents are embedded into a `VectorIndex` by the `ingest` command.
+//! At answer time the passages closest to the question are given to the model and cited in the reply.
+pub mod index;
+pub mod ingest;
+
+use std::fmt;
+use std::io;
+
+use crate::openai::client::ChatGPTError;
+use crate::openai::embeddings::EmbeddingsClient;
+
+pub use index::VectorIndex;
+pub use ingest::ingest_folder;
+
+/// Passages retrieved per question.
+pub const DEFAULT_TOP_K: usize = 4;
+
+/// Passages less similar than this are not used.
+pub const DEFAULT_MIN_SCORE: f32 = 0.3;
+
+/// Enum representing possible errors of the knowledge base.
+#[derive(Debug)]
+pub enum KnowledgeError {
+    Io(io::Error),
+    Embeddings(ChatGPTError),
+}
+
+impl fmt::Display for KnowledgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KnowledgeError::Io(error) => write!(f, "IO error: {error}"),
+            KnowledgeError::Embeddings(error) => write!(f, "Embeddings error: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for KnowledgeError {}
+
+impl From<io::Error> for KnowledgeError {
+    fn from(error: io::Error) -> Self {
+        KnowledgeError::Io(error)
+    }
+}
+
+impl From<ChatGPTError> for KnowledgeError {
+    fn from(error: ChatGPTError) -> Self {
+        KnowledgeError::Embeddings(error)
+    }
+}
+
+/// A retrieved passage, close enough to the question.
+#[derive(Debug, Clone)]
+pub struct Passage {
+    pub citation: String,
+    pub text: String,
+}
+
+/// Vector index plus the client used to embed questions.
+pub struct KnowledgeBase {
+    index: VectorIndex,
+    embeddings: EmbeddingsClient,
+    top_k: usize,
+    min_score: f32,
+}
+
+impl KnowledgeBase {
+    pub fn new(index: VectorIndex, embeddings: EmbeddingsClient) -> KnowledgeBase {
+        KnowledgeBase {
+            index,
+            embeddings,
+            top_k: DEFAULT_TOP_K,
+            min_score: DEFAULT_MIN_SCORE,
+        }
+    }
+
+    /// The passages most relevant to `question`.
+    pub async fn retrieve(&self, question: &str) -> Result<Vec<Passage>, KnowledgeError> {
+        let query = self
+            .embeddings
+            .embed(vec![question.to_string()])
+            .await?
+            .pop()
+            .unwrap_or_default();
+        Ok(self
+            .index
+            .search(&query, self.top_k)
+            .into_iter()
+            .filter(|(score, _)| *score >= self.min_score)
+            .map(|(_, entry)| Passage {
+                citation: entry.citation(),
+                text: entry.text.clone(),
+            })
+            .collect())
+    }
+}
+
+/// System prompt listing the numbered passages the answer should be based on.
+pub fn system_prompt(passages: &[Passage]) -> String {
+    let mut prompt = String::from(
+        "Answer using the numbered passages from our knowledge base below when they are relevant, \
+         and mark the passages you used like [1]. If they don't cover the question, answer normally.\n",
+    );
+    for (i, passage) in passages.iter().enumerate() {
+        prompt.push_str(&format!(
+            "\n[{}] {}\n{}\n",
+            i + 1,
+            passage.citation,
+            passage.text
+        ));
+    }
+    prompt
+}
+
+/// Citation footer appended to the LINE reply.
+pub fn citations(passages: &[Passage]) -> String {
+    let mut footer = String::from("Sources:");
+    for (i, passage) in passages.iter().enumerate() {
+        footer.push_str(&format!("\n[{}] {}", i + 1, passage.citation));
+    }
+    footer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passages() -> Vec<Passage> {
+        vec![
+            Passage {
+                citation: "hr.md § Leave".to_string(),
+                text: "Annual leave is 15 days.".to_string(),
+            },
+            Passage {
+                citation: "it.md".to_string(),
+                text: "Reset your password at the portal.".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_system_prompt_numbers_passages() {
+        let prompt = system_prompt(&passages());
+        assert!(prompt.contains("[1] hr.md § Leave\nAnnual leave is 15 days."));
+        assert!(prompt.contains("[2] it.md\nReset your password at the portal."));
+    }
+
+    #[test]
+    fn test_citations() {
+        assert_eq!(
+            citations(&passages()),
+            "Sources:\n[1] hr.md § Leave\n[2] it.md"
+        );
+    }
+}
@@ -0,0 +1,491 @@
+//! Rate limits and quotas
+//! # Note
+//! Every user, group and room has a bucket of questions refilled at
+//! `limits.requests_per_minute`, and daily quotas of tokens and cost counted from the
+//! usage OpenAI reports. In a group, both the group and the asker are limited.
+//!
+//! The daily counters are saved to `limits.state_file` after every completion, so a
+//! restart does not reset them. The file is written on a blocking thread, from the
+//! counters as they are by then. The buckets refill within a minute and are not saved.
+//! With Redis configured, the replicas share the buckets and the daily counters, and
+//! each one falls back to its own while Redis cannot be reached. Every replica counts
+//! in process as well, so that its fallback knows what it spent.
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::config::LimitsConfig;
+use crate::openai::client::Usage;
+use crate::openai::models::Model;
+use crate::shared::SharedState;
+use crate::usage::{cost_usd, Prices};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The limit a question ran into, displayed as the reply to the asker.
+#[derive(Debug, PartialEq)]
+pub enum LimitExceeded {
+    /// Too many questions in a row. One more can be asked after `retry_after`.
+    Rate {
+        retry_after: Duration,
+    },
+    DailyTokens {
+        limit: u64,
+    },
+    DailyCost {
+        limit: f64,
+    },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Rate { retry_after } => {
+                let seconds = retry_after.as_secs_f64().ceil().max(1.0);
+                write!(
+                    f,
+                    "You're asking faster than I can answer. Please try again in {seconds} seconds."
+                )
+            }
+            LimitExceeded::DailyTokens { .. } | LimitExceeded::DailyCost { .. } => write!(
+                f,
+                "You've reached today's usage limit. Please try again tomorrow."
+            ),
+        }
+    }
+}
+
+/// Enum representing possible errors while loading or saving the counters.
+#[derive(Debug)]
+pub enum LimitsError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Task(tokio::task::JoinError),
+}
+
+impl fmt::Display for LimitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitsError::Io(e) => write!(f, "IO error: {e}"),
+            LimitsError::Json(e) => write!(f, "JSON error: {e}"),
+            LimitsError::Task(e) => write!(f, "Saving task failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LimitsError {}
+
+impl From<io::Error> for LimitsError {
+    fn from(error: io::Error) -> Self {
+        LimitsError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for LimitsError {
+    fn from(error: serde_json::Error) -> Self {
+        LimitsError::Json(error)
+    }
+}
+
+impl From<tokio::task::JoinError> for LimitsError {
+    fn from(error: tokio::task::JoinError) -> Self {
+        LimitsError::Task(error)
+    }
+}
+
+/// Usage of one user, group or room during one day.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DailyUsage {
+    pub tokens: u64,
+    pub cost_usd: f64,
+}
+
+/// The counters saved to the state file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DailyCounters {
+    /// Days since the epoch, in UTC.
+    day: u64,
+    usage: HashMap<String, DailyUsage>,
+}
+
+impl DailyCounters {
+    /// Starts over when the day changed.
+    fn roll(&mut self, day: u64) {
+        if self.day != day {
+            self.day = day;
+            self.usage.clear();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    questions: f64,
+    updated: Instant,
+}
+
+/// Questions in a bucket that held `questions` `elapsed` ago.
+fn refill(questions: f64, elapsed: Duration, capacity: f64, per_second: f64) -> f64 {
+    (questions + elapsed.as_secs_f64() * per_second).min(capacity)
+}
+
+/// How long until a bucket holding `questions` has one, zero if it has.
+fn wait_for_question(questions: f64, per_second: f64) -> Duration {
+    if questions < 1.0 {
+        Duration::from_secs_f64((1.0 - questions) / per_second)
+    } else {
+        Duration::ZERO
+    }
+}
+
+/// Limits of every user, group and room.
+#[derive(Default)]
+pub struct Limiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    daily: Arc<Mutex<DailyCounters>>,
+    /// Held while writing the state file, so that writes do not interleave.
+    saving: Arc<Mutex<()>>,
+    state_file: Option<PathBuf>,
+    /// Buckets and daily counters shared with the other replicas.
+    shared: Option<Arc<SharedState>>,
+}
+
+impl Limiter {
+    pub fn new() -> Limiter {
+        Default::default()
+    }
+
+    /// A limiter that keeps its daily counters in `path`, starting from the saved ones.
+    pub fn load(path: &str) -> Result<Limiter, LimitsError> {
+        let daily = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => DailyCounters::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Limiter {
+            daily: Arc::new(Mutex::new(daily)),
+            state_file: Some(PathBuf::from(path)),
+            ..Default::default()
+        })
+    }
+
+    /// Keeps the buckets and the daily counters in `shared` rather than in process.
+    pub fn with_shared(mut self, shared: Arc<SharedState>) -> Limiter {
+        self.shared = Some(shared);
+        self
+    }
+
+    /// Takes a question from the bucket of every key if all keys are within their limits.
+    pub async fn check(&self, keys: &[&str], limits: &LimitsConfig) -> Result<(), LimitExceeded> {
+        if limits.daily_tokens.is_some() || limits.daily_cost_usd.is_some() {
+            over_quota(limits, &self.usage_of(keys, today()).await)?;
+        }
+        if let (Some(shared), Some((capacity, per_second))) = (&self.shared, bucket_size(limits)) {
+            match shared.take_questions(keys, capacity, per_second).await {
+                Ok(None) => return Ok(()),
+                Ok(Some(retry_after)) => return Err(LimitExceeded::Rate { retry_after }),
+                Err(e) => error!("Failed taking from shared buckets: {}", e),
+            }
+        }
+        self.take_questions(keys, limits, Instant::now())
+    }
+
+    /// Adds the usage of one completion to every key and saves the counters.
+    pub async fn record(
+        &self,
+        keys: &[&str],
+        model: Model,
+        usage: &Usage,
+        prices: &Prices,
+    ) -> Result<(), LimitsError> {
+        let day = today();
+        if let Some(shared) = &self.shared {
+            let (tokens, cost_usd) = spent(prices, model, usage);
+            if let Err(e) = shared.add_daily_usage(keys, day, tokens, cost_usd).await {
+                error!("Failed adding to shared daily usage: {}", e);
+            }
+        }
+        self.record_at(keys, model, usage, prices, day);
+        self.save().await
+    }
+
+    /// Writes the counters to the state file, if there is one.
+    async fn save(&self) -> Result<(), LimitsError> {
+        let Some(path) = self.state_file.clone() else {
+            return Ok(());
+        };
+        let daily = Arc::clone(&self.daily);
+        let saving = Arc::clone(&self.saving);
+        tokio::task::spawn_blocking(move || {
+            let _saving = saving.lock().unwrap();
+            // Taken while saving, so that the last write has the latest counters
+            let json = serde_json::to_vec(&*daily.lock().unwrap())?;
+            save(&path, &json)
+        })
+        .await?
+    }
+
+    /// Usage of the key today.
+    #[cfg(test)]
+    pub fn daily_usage(&self, key: &str) -> DailyUsage {
+        let mut daily = self.daily.lock().unwrap();
+        daily.roll(today());
+        daily.usage.get(key).cloned().unwrap_or_default()
+    }
+
+    /// `check` in process.
+    #[cfg(test)]
+    fn check_at(
+        &self,
+        keys: &[&str],
+        limits: &LimitsConfig,
+        now: Instant,
+        day: u64,
+    ) -> Result<(), LimitExceeded> {
+        over_quota(limits, &self.local_usage(keys, day))?;
+        self.take_questions(keys, limits, now)
+    }
+
+    /// Usage of every key during `day`, shared if Redis can be reached.
+    async fn usage_of(&self, keys: &[&str], day: u64) -> Vec<DailyUsage> {
+        if let Some(shared) = &self.shared {
+            match shared.daily_usage(keys, day).await {
+                Ok(usage) => return usage,
+                Err(e) => error!("Failed reading shared daily usage: {}", e),
+            }
+        }
+        self.local_usage(keys, day)
+    }
+
+    /// Usage of every key during `day` in process.
+    fn local_usage(&self, keys: &[&str], day: u64) -> Vec<DailyUsage> {
+        let mut daily = self.daily.lock().unwrap();
+        daily.roll(day);
+        keys.iter()
+            .map(|key| daily.usage.get(*key).cloned().unwrap_or_default())
+            .collect()
+    }
+
+    /// Takes a question from the bucket of every key in process.
+    fn take_questions(
+        &self,
+        keys: &[&str],
+        limits: &LimitsConfig,
+        now: Instant,
+    ) -> Result<(), LimitExceeded> {
+        let Some((capacity, per_second)) = bucket_size(limits) else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        // Every bucket is refilled before any is taken from, so that a refused
+        // question costs nothing
+        let mut retry_after = Duration::ZERO;
+        for key in keys {
+            let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+                questions: capacity,
+                updated: now,
+            });
+            let elapsed = now.saturating_duration_since(bucket.updated);
+            bucket.questions = refill(bucket.questions, elapsed, capacity, per_second);
+            bucket.updated = now;
+            retry_after = retry_after.max(wait_for_question(bucket.questions, per_second));
+        }
+        if !retry_after.is_zero() {
+            return Err(LimitExceeded::Rate { retry_after });
+        }
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(*key) {
+                bucket.questions -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    fn record_at(&self, keys: &[&str], model: Model, usage: &Usage, prices: &Prices, day: u64) {
+        let (tokens, cost_usd) = spent(prices, model, usage);
+        let mut daily = self.daily.lock().unwrap();
+        daily.roll(day);
+        for key in keys {
+            let used = daily.usage.entry(key.to_string()).or_default();
+            used.tokens += tokens;
+            used.cost_usd += cost_usd;
+        }
+    }
+}
+
+/// The tokens and cost of one completion.
+fn spent(prices: &Prices, model: Model, usage: &Usage) -> (u64, f64) {
+    let tokens = (usage.prompt_tokens.max(0) + usage.completion_tokens.max(0)) as u64;
+    (tokens, cost_usd(prices, model, usage))
+}
+
+/// The first quota that `usage`, of every key, ran into.
+fn over_quota(limits: &LimitsConfig, usage: &[DailyUsage]) -> Result<(), LimitExceeded> {
+    for used in usage {
+        if let Some(limit) = limits.daily_tokens.filter(|l| used.tokens >= *l) {
+            return Err(LimitExceeded::DailyTokens { limit });
+        }
+        if let Some(limit) = limits.daily_cost_usd.filter(|l| used.cost_usd >= *l) {
+            return Err(LimitExceeded::DailyCost { limit });
+        }
+    }
+    Ok(())
+}
+
+/// The capacity of the buckets, and the questions they are refilled with per second.
+/// `None` without a rate limit.
+fn bucket_size(limits: &LimitsConfig) -> Option<(f64, f64)> {
+    let per_minute = limits.requests_per_minute?;
+    Some((
+        limits.burst.unwrap_or(per_minute) as f64,
+        per_minute as f64 / 60.0,
+    ))
+}
+
+/// Writes the counters next to `path` first, so that a crash never leaves half a file.
+fn save(path: &Path, json: &[u8]) -> Result<(), LimitsError> {
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, json)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::Value;
+
+    use super::*;
+    use crate::shared::testing::{mock, MockCmd};
+
+    fn usage(prompt_tokens: i64, completion_tokens: i64) -> Usage {
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = Limiter::new();
+        let limits = LimitsConfig {
+            requests_per_minute: Some(60),
+            burst: Some(2),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        assert_eq!(limiter.check_at(&["U1"], &limits, start, 0), Ok(()));
+        assert_eq!(limiter.check_at(&["U1"], &limits, start, 0), Ok(()));
+        assert_eq!(
+            limiter.check_at(&["U1"], &limits, start, 0),
+            Err(LimitExceeded::Rate {
+                retry_after: Duration::from_secs(1)
+            })
+        );
+        // Other chats have their own bucket
+        assert_eq!(limiter.check_at(&["U2"], &limits, start, 0), Ok(()));
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check_at(&["U1"], &limits, later, 0), Ok(()));
+    }
+
+    #[test]
+    fn test_refused_question_takes_nothing() {
+        let limiter = Limiter::new();
+        let limits = LimitsConfig {
+            requests_per_minute: Some(1),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        assert_eq!(limiter.check_at(&["C1", "U1"], &limits, now, 0), Ok(()));
+        // The group is empty, so the question of U2 is refused, without costing U2
+        assert!(limiter.check_at(&["C1", "U2"], &limits, now, 0).is_err());
+        assert_eq!(limiter.check_at(&["U2"], &limits, now, 0), Ok(()));
+    }
+
+    #[test]
+    fn test_daily_quotas_reset_every_day() {
+        let limiter = Limiter::new();
+        let limits = LimitsConfig {
+            daily_tokens: Some(100),
+            daily_cost_usd: Some(1.0),
+            ..Default::default()
+        };
+        let prices = Prices::default();
+        let now = Instant::now();
+        limiter.record_at(&["U1"], Model::Gpt3_5Turbo, &usage(80, 20), &prices, 1);
+        assert_eq!(
+            limiter.check_at(&["U1"], &limits, now, 1),
+            Err(LimitExceeded::DailyTokens { limit: 100 })
+        );
+        assert_eq!(limiter.check_at(&["U1"], &limits, now, 2), Ok(()));
+
+        // 10k prompt and 10k completion tokens of gpt-4 cost $0.90
+        limiter.record_at(&["U2"], Model::Gpt_4, &usage(10_000, 10_000), &prices, 2);
+        let used = limiter.daily.lock().unwrap().usage["U2"].cost_usd;
+        assert!((used - 0.9).abs() < 1e-9);
+        let limits = LimitsConfig {
+            daily_cost_usd: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(
+            limiter.check_at(&["U2"], &limits, now, 2),
+            Err(LimitExceeded::DailyCost { limit: 0.5 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_daily_quotas_are_shared() {
+        let day = today();
+        let mut get = redis::cmd("MGET");
+        get.arg(format!("line_botx:tokens:{day}:U1"))
+            .arg(format!("line_botx:cost:{day}:U1"));
+        let counters = Value::Array(vec![Value::BulkString(b"150".to_vec()), Value::Nil]);
+        let shared = mock(vec![MockCmd::new(get, Ok(counters))]);
+        let limiter = Limiter::new().with_shared(Arc::new(shared));
+        let limits = LimitsConfig {
+            daily_tokens: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            limiter.check(&["U1"], &limits).await,
+            Err(LimitExceeded::DailyTokens { limit: 100 })
+        );
+        // Without Redis, the counters of this replica are all there is
+        assert_eq!(limiter.check(&["U1"], &limits).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_counters_persist() {
+        let path =
+            std::env::temp_dir().join(format!("line_botx_limits_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let limiter = Limiter::load(path).unwrap();
+        limiter
+            .record(
+                &["U1"],
+                Model::Gpt3_5Turbo,
+                &usage(10, 5),
+                &Prices::default(),
+            )
+            .await
+            .unwrap();
+
+        let restarted = Limiter::load(path).unwrap();
+        assert_eq!(restarted.daily_usage("U1").tokens, 15);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+//! Loading animation
+//! # Note
+//! Shows the "typing" animation in a 1:1 chat while a completion is generated. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#display-a-loading-indicator)
+//! LINE only supports it for users, so group and room chats are skipped.
+use std::future::Future;
+use std::time::Duration;
+
+use tracing::error;
+
+use crate::bot::LineBot;
+use crate::events::source::SouceType;
+use crate::events::Source;
+use crate::openai::models::Model;
+
+/// Shortest and longest animation LINE accepts, in seconds.
+const MIN_LOADING_SECONDS: u64 = 5;
+const MAX_LOADING_SECONDS: u64 = 60;
+
+/// Output tokens assumed when `max_tokens` is not set.
+const DEFAULT_COMPLETION_TOKENS: usize = 512;
+
+/// Rough completion latency: a fixed round trip plus the output tokens at the
+/// model's generation speed.
+pub fn expected_latency(model: Model, max_tokens: Option<usize>) -> Duration {
+    let tokens_per_second = match model {
+        Model::Gpt3_5Turbo => 60,
+        Model::Gpt_4 | Model::Gpt_4_32k => 20,
+        Model::Gpt_4Turbo | Model::Gpt_4Turbo_Vision => 30,
+    };
+    let tokens = max_tokens.unwrap_or(DEFAULT_COMPLETION_TOKENS);
+    Duration::from_secs(2) + Duration::from_secs_f64(tokens as f64 / tokens_per_second as f64)
+}
+
+/// `loadingSeconds` for the expected latency: a multiple of 5 between 5 and 60.
+pub fn loading_seconds(expected: Duration) -> u64 {
+    let seconds = expected.as_secs() + u64::from(expected.subsec_nanos() > 0);
+    let seconds = seconds.div_ceil(5) * 5;
+    seconds.clamp(MIN_LOADING_SECONDS, MAX_LOADING_SECONDS)
+}
+
+/// Awaits `future` while the loading animation is shown to the user.
+///
+/// The animation is sized to `expected` and started again whenever it runs out
+/// before `future` completes. `future` runs meanwhile, so the request that shows the
+/// animation never delays it. Failures to show it are logged and ignored.
+pub async fn with_loading_animation<F: Future>(
+    bot: &LineBot,
+    source: &Source,
+    expected: Duration,
+    future: F,
+) -> F::Output {
+    let SouceType::User(user) = &source.r#type else {
+        return future.await;
+    };
+    let seconds = loading_seconds(expected);
+    tokio::pin!(future);
+    loop {
+        let animation = async {
+            match bot.show_loading_animation(&user.user_id, seconds).await {
+                Ok(res) if !res.status().is_success() => {
+                    error!("Loading animation failed: {}", res.status())
+                }
+                Err(e) => error!("Error: {}", e),
+                Ok(_) => {}
+            }
+            tokio::time::sleep(Duration::from_secs(seconds)).await;
+        };
+        tokio::select! {
+            output = &mut future => return output,
+            _ = animation => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::*;
+
+    /// How long the mocked LINE API takes to show the animation.
+    const LOADING_LATENCY: Duration = Duration::from_millis(500);
+
+    #[actix_web::test]
+    async fn test_completion_is_not_delayed_by_the_animation() {
+        async fn slow_loading() -> HttpResponse {
+            actix_web::rt::time::sleep(LOADING_LATENCY).await;
+            HttpResponse::Accepted().finish()
+        }
+        let server = HttpServer::new(|| {
+            App::new().route("/v2/bot/chat/loading/start", web::post().to(slow_loading))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/v2/bot", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        let bot = LineBot::with_base_urls("secret", "token", &url, &url);
+        let source: Source =
+            serde_json::from_value(serde_json::json!({"type": "user", "userId": "U1"})).unwrap();
+
+        let started = Instant::now();
+        let completion = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            "answer"
+        };
+        let output =
+            with_loading_animation(&bot, &source, Duration::from_secs(5), completion).await;
+        assert_eq!(output, "answer");
+        assert!(started.elapsed() < LOADING_LATENCY);
+    }
+
+    #[test]
+    fn test_loading_seconds_bounds() {
+        assert_eq!(loading_seconds(Duration::ZERO), 5);
+        assert_eq!(loading_seconds(Duration::from_millis(5100)), 10);
+        assert_eq!(loading_seconds(Duration::from_secs(20)), 20);
+        assert_eq!(loading_seconds(Duration::from_secs(300)), 60);
+    }
+
+    #[test]
+    fn test_expected_latency_by_model() {
+        assert_eq!(
+            expected_latency(Model::Gpt3_5Turbo, Some(600)),
+            Duration::from_secs(12)
+        );
+        assert_eq!(
+            expected_latency(Model::Gpt_4, None),
+            Duration::from_secs_f64(2.0 + 512.0 / 20.0)
+        );
+    }
+}
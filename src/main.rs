@@ -1,29 +1,79 @@
+#![cfg_attr(feature = "bench", feature(test))]
+
+#[cfg(all(test, feature = "bench"))]
+extern crate test;
+
 use std::env;
-use std::sync::Mutex;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix_web::middleware::Logger;
 use actix_web::{web, web::Data, App, HttpResponse, HttpServer};
 use actix_web_opentelemetry::RequestTracing;
-use log::info;
-use opentelemetry::global;
-use opentelemetry::global::shutdown_tracer_provider;
-use opentelemetry::trace::Tracer as _;
-use opentelemetry_sdk::metrics::{MeterProvider, PeriodicReader};
-use rand::Rng;
-use tracing::debug;
+use log::{error, info};
 use tracing_actix_web::TracingLogger;
-use tracing_subscriber::layer::SubscriberExt;
 
-use crate::webhook::LineKeys;
+use crate::bot::LineBot;
+use crate::config::{Config, ConfigError};
+use crate::conversation::{ConversationStore, DEFAULT_MAX_TURNS};
+use crate::dedup::Deduplicator;
+use crate::documents::DocumentStore;
+use crate::knowledge::{ingest_folder, KnowledgeBase, VectorIndex};
+use crate::limits::Limiter;
+use crate::metrics::BotMetrics;
+use crate::openai::client::ChatGPTClient;
+use crate::openai::embeddings::EmbeddingsClient;
+use crate::openai::moderations::ModerationsClient;
+use crate::profiles::ProfileCache;
+use crate::reload::{LiveConfig, RuntimeConfig};
+use crate::rich_menus::RichMenuRegistry;
+use crate::settings::SettingsStore;
+use crate::shared::SharedState;
+use crate::state::AppState;
+use crate::storage::SharedStorage;
+use crate::usage::UsageStore;
 
+mod admin;
 mod bot;
 mod client;
+mod commands;
+mod config;
+mod content;
+mod conversation;
+mod dates;
+mod dedup;
+mod documents;
+// The LINE API types mirror the whole API, the bot only reads or sends part of it
+#[allow(dead_code)]
 mod events;
+mod generation;
+mod knowledge;
+mod limits;
+mod loading;
+#[allow(dead_code)]
 mod messages;
+mod metrics;
+mod moderation;
+#[allow(dead_code)]
 mod objects;
 mod openai;
+mod personas;
+mod privacy;
+mod profiles;
+mod redaction;
+mod reload;
+mod rich_menus;
+mod settings;
+mod shared;
+mod state;
+mod storage;
 mod support;
+mod telemetry;
+mod traces;
+mod usage;
 mod webhook;
+mod welcome;
 
 //use chatgpt::prelude::*;
 
@@ -32,77 +82,259 @@ async fn main() -> std::io::Result<()> {
     pretty_env_logger::init();
     info!("Starting LineChatBot");
 
-    let app_insights_connection_str = env::var("APPLICATIONINSIGHTS_CON_STRING");
-    if let Ok(app_insights_connection) = app_insights_connection_str {
-        debug!(
-            "APPLICATIONINSIGHTS_CON_STRING = {}",
-            app_insights_connection
-        );
-        let tracer_res = opentelemetry_application_insights::new_pipeline_from_connection_string(
-            &app_insights_connection,
-        );
-        if let Ok(tracer) = tracer_res {
-            tracer
-                .with_client(reqwest::Client::new())
-                .with_service_name("LineChatBot")
-                .with_live_metrics(true)
-                .install_batch(opentelemetry_sdk::runtime::Tokio);
+    // `line_botx ingest <folder> [index]` builds the knowledge base and exits
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("ingest") {
+        return ingest(&args[2..]).await;
+    }
+
+    let config = match Config::load(&args[1..]) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            std::process::exit(2);
         }
-        let exporter = opentelemetry_application_insights::Exporter::new_from_connection_string(
-            &app_insights_connection,
-            reqwest::Client::new(),
-        );
-        if let Ok(exporter) = exporter {
-            let reader =
-                PeriodicReader::builder(exporter, opentelemetry_sdk::runtime::Tokio).build();
-            let meter_provider = MeterProvider::builder().with_reader(reader).build();
-            global::set_meter_provider(meter_provider);
+    };
+
+    let telemetry = match telemetry::init(&config.telemetry) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            std::process::exit(2);
         }
-    }
+    };
     ////////
 
-    let channel_secret: &str =
-        &env::var("LINE_CHANNEL_SECRET").expect("Failed getting LINE_CHANNEL_SECRET");
+    info!("Generation parameters {:?}", config.generation);
+
+    // Instruments of the meter provider installed above, a no-op without one
+    let metrics = BotMetrics::global();
 
-    let access_token: &str =
-        &env::var("LINE_CHANNEL_ACCESS_TOKEN").expect("Failed getting LINE_CHANNEL_ACCESS_TOKEN");
+    // Clients are shared by every worker, with one connection pool each
+    let bot = LineBot::new(
+        &config.line.channel_secret,
+        &config.line.channel_access_token,
+    )
+    .with_metrics(metrics.clone());
+    let chat_gpt = ChatGPTClient::new(&config.openai.api_key, &config.openai.base_url);
+    let moderations = ModerationsClient::new(
+        &config.openai.api_key,
+        &config.openai.base_url,
+        &config.openai.moderation_model,
+    );
 
-    let chat_gpt_api_key: &str =
-        &env::var("CHATGPT_API_KEY").expect("Failed getting CHATGPT_API_KEY");
+    // The bot's own user id tells its @mentions apart from other members'
+    let bot_user_id = match bot.get_bot_info().await {
+        Ok(bot_info) => {
+            info!("Bot {} ({})", bot_info.display_name, bot_info.user_id);
+            Some(bot_info.user_id)
+        }
+        Err(e) => {
+            error!("Failed getting bot info: {}", e);
+            None
+        }
+    };
 
-    let line_chat_prompt: &str =
-        &env::var("LINE_CHAT_PROMPT").expect("Failed getting LINE_CHAT_PROMPT");
+    let knowledge = match &config.knowledge.index {
+        Some(path) => match VectorIndex::load(path) {
+            Ok(index) => {
+                info!("Loaded {} passages from {}", index.entries.len(), path);
+                let embeddings = EmbeddingsClient::new(
+                    &config.openai.api_key,
+                    &config.openai.base_url,
+                    &index.model,
+                );
+                Some(KnowledgeBase::new(index, embeddings))
+            }
+            Err(e) => {
+                error!("Failed loading knowledge.index {}: {}", path, e);
+                None
+            }
+        },
+        None => None,
+    };
 
-    let data = Data::new(Mutex::new(LineKeys {
-        channel_secret: channel_secret.to_string(),
-        access_token: access_token.to_string(),
-        chat_gpt_api_key: chat_gpt_api_key.to_string(),
-        chat_gpt_max_tokens: None,
-        chat_gpt_temperature: None,
-        line_chat_prompt: line_chat_prompt.to_string(),
+    // Prompt, personas, access lists and limits follow the configuration files
+    let runtime = match RuntimeConfig::from_config(&config) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            std::process::exit(2);
+        }
+    };
+    if let Some(personas) = &runtime.personas {
+        info!("Loaded personas {:?}", personas.names());
+    }
+    let flags = args[1..].to_vec();
+    let live = Arc::new(LiveConfig::new(runtime, move || {
+        Config::resolve(&flags, env::vars())
     }));
+    if config.reload.interval_seconds > 0 {
+        let interval = Duration::from_secs(config.reload.interval_seconds);
+        actix_web::rt::spawn(Arc::clone(&live).watch(interval));
+    }
+
+    let rich_menus = match &config.rich_menus.dir {
+        Some(dir) => match RichMenuRegistry::load(dir) {
+            Ok(mut registry) => {
+                if let Err(e) = registry.sync(&bot).await {
+                    error!("Failed creating rich menus: {}", e);
+                }
+                info!("Loaded rich menus {:?} from {}", registry.modes(), dir);
+                Some(registry)
+            }
+            Err(e) => {
+                error!("Failed loading rich_menus.dir {}: {}", dir, e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Replicas share delivered events, buckets and conversations through Redis
+    let shared = match SharedState::open(&config.redis) {
+        Ok(shared) => shared.map(Arc::new),
+        Err(e) => {
+            eprintln!("Invalid redis.url: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    // Daily counters survive restarts when they are kept in a file
+    let limiter = match &config.limits.state_file {
+        Some(path) => match Limiter::load(path) {
+            Ok(limiter) => limiter,
+            Err(e) => {
+                eprintln!("Invalid limits.state_file {path}: {e}");
+                std::process::exit(2);
+            }
+        },
+        None => Limiter::new(),
+    };
+    let limiter = match &shared {
+        Some(shared) => limiter.with_shared(Arc::clone(shared)),
+        None => limiter,
+    };
+
+    // Conversations, settings and usage survive restarts with the sqlite backend, for
+    // no longer than the global retention window
+    let storage_config = config.privacy.cap(&config.storage);
+    let storage = match storage::open(&storage_config) {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Invalid storage: {e}");
+            std::process::exit(2);
+        }
+    };
+    let storage: Arc<dyn storage::Storage> = match &shared {
+        Some(shared) => Arc::new(SharedStorage::new(
+            Arc::clone(shared),
+            storage,
+            &storage_config,
+        )),
+        None => storage,
+    };
+
+    let usage = match &config.usage.ledger_file {
+        Some(path) => {
+            let before = storage::expired_before(storage_config.usage_retention_days);
+            match UsageStore::load(Arc::clone(&storage), path, before).await {
+                Ok(usage) => usage,
+                Err(e) => {
+                    eprintln!("Invalid usage.ledger_file {path}: {e}");
+                    std::process::exit(2);
+                }
+            }
+        }
+        None => UsageStore::with_storage(Arc::clone(&storage)),
+    };
+    let usage = Arc::new(usage);
+
+    if storage_config.turn_retention_days > 0 || storage_config.usage_retention_days > 0 {
+        actix_web::rt::spawn(storage::prune_periodically(
+            Arc::clone(&storage),
+            storage_config,
+            Arc::clone(&usage),
+            Arc::clone(&live),
+        ));
+    }
+
+    let state = Data::new(AppState {
+        bot,
+        chat_gpt,
+        moderations,
+        bot_user_id,
+        live,
+        admin: config.admin.clone(),
+        documents: DocumentStore::new(Duration::from_secs(config.documents.ttl_minutes * 60)),
+        knowledge,
+        rich_menus,
+        settings: SettingsStore::with_storage(Arc::clone(&storage)),
+        conversations: ConversationStore::with_storage(storage, DEFAULT_MAX_TURNS),
+        usage,
+        limiter,
+        dedup: Deduplicator::new(shared),
+        profiles: ProfileCache::new(),
+        metrics,
+    });
 
     /////
-    HttpServer::new(move || {
+    let served = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .wrap(Logger::new("%a %{User-Agent}i"))
             .wrap(RequestTracing::new())
             .wrap(TracingLogger::default())
-            .app_data(Data::clone(&data))
+            .app_data(Data::clone(&state))
             .service(webhook::callback)
+            .service(admin::reload)
+            .service(admin::usage)
             .service(
                 web::resource("/")
                     .route(web::get().to(|| async { HttpResponse::Ok().body("Hello World!") })),
             )
     })
-    .workers(20)
-    .bind("0.0.0.0:8080")?
+    .workers(config.server.workers)
+    .bind(config.bind_address())?
     .run()
-    .await?;
+    .await;
+
+    // wait until all pending spans and metrics get exported, off the runtime that
+    // exports them
+    if let Err(e) = actix_web::rt::task::spawn_blocking(move || telemetry.shutdown()).await {
+        error!("Failed shutting telemetry down: {}", e);
+    }
 
-    // wait until all pending spans get exported.
-    shutdown_tracer_provider();
+    served
+}
+
+/// Embeds the Markdown files of a folder into the knowledge base index.
+async fn ingest(args: &[String]) -> std::io::Result<()> {
+    let Some(folder) = args.first() else {
+        eprintln!("Usage: line_botx ingest <folder> [index]");
+        std::process::exit(2);
+    };
+    let config = Config::resolve(&[], env::vars()).map_err(std::io::Error::other)?;
+    if config.openai.api_key.is_empty() {
+        return Err(std::io::Error::other(ConfigError::Missing(
+            "openai.api_key".to_string(),
+        )));
+    }
 
+    let index_path = args
+        .get(1)
+        .cloned()
+        .or_else(|| config.knowledge.index.clone())
+        .unwrap_or_else(|| "knowledge.json".to_string());
+    let client = EmbeddingsClient::new(
+        &config.openai.api_key,
+        &config.openai.base_url,
+        &config.openai.embedding_model,
+    );
+    let index = ingest_folder(&client, Path::new(folder))
+        .await
+        .map_err(std::io::Error::other)?;
+    index.save(&index_path)?;
+    info!("Wrote {} passages to {}", index.entries.len(), index_path);
     Ok(())
 }
@@ -8,6 +8,7 @@ pub mod location_message;
 pub mod sticker_message;
 pub mod template_message;
 pub mod text_message;
+pub mod text_message_v2;
 pub mod video_message;
 
 pub use audio_message::AudioMessage;
@@ -17,7 +18,8 @@ pub use image_message::ImageMessage;
 pub use location_message::LocationMessage;
 pub use sticker_message::StickerMessage;
 pub use template_message::TemplateMessage;
-pub use text_message::{Emoji, TextMessage};
+pub use text_message::TextMessage;
+pub use text_message_v2::TextMessageV2;
 pub use video_message::VideoMessage;
 
 use serde_derive::Serialize;
@@ -30,9 +32,12 @@ pub struct SendMessage {
 
 #[derive(Serialize, Debug)]
 #[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
 pub enum SendMessageType {
     #[serde(rename = "text")]
     TextMessage(TextMessage),
+    #[serde(rename = "textV2")]
+    TextMessageV2(TextMessageV2),
     #[serde(rename = "sticker")]
     StickerMessage(StickerMessage),
     #[serde(rename = "image")]
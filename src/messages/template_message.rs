@@ -1,4 +1,4 @@
-use crate::objects::Action;
+use crate::objects::{Action, QuickReply};
 
 use serde_derive::Serialize;
 
@@ -7,6 +7,8 @@ pub struct TemplateMessage {
     #[serde(rename = "altText")]
     pub alt_text: String,
     pub template: Template,
+    #[serde(rename = "quickReply", skip_serializing_if = "Option::is_none")]
+    pub quick_reply: Option<QuickReply>,
 }
 
 #[derive(Serialize, Debug)]
@@ -17,6 +19,7 @@ pub struct Template {
 
 #[derive(Serialize, Debug)]
 #[serde(tag = "type")]
+#[allow(clippy::large_enum_variant)]
 pub enum TemplateType {
     #[serde(rename = "buttons")]
     Buttons {
@@ -1,10 +1,17 @@
 use serde_derive::Serialize;
 
+use crate::objects::QuickReply;
+
 #[derive(Serialize, Debug)]
 pub struct TextMessage {
     pub text: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub emojis: Option<Vec<Emoji>>,
+    /// Quote token of the message to quote.
+    #[serde(rename = "quoteToken", skip_serializing_if = "Option::is_none")]
+    pub quote_token: Option<String>,
+    #[serde(rename = "quickReply", skip_serializing_if = "Option::is_none")]
+    pub quick_reply: Option<QuickReply>,
 }
 
 #[derive(Serialize, Debug)]
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_derive::Serialize;
+
+/// Most substitutions LINE accepts in one message.
+const MAX_SUBSTITUTIONS: usize = 100;
+
+/// Text message v2 with `{placeholder}` substitutions.
+/// <https://developers.line.biz/en/reference/messaging-api/#text-message-v2>
+#[derive(Serialize, Debug)]
+pub struct TextMessageV2 {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub substitution: Option<HashMap<String, Substitution>>,
+    #[serde(rename = "quoteToken", skip_serializing_if = "Option::is_none")]
+    pub quote_token: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub enum Substitution {
+    #[serde(rename = "mention")]
+    Mention { mentionee: Mentionee },
+    #[serde(rename = "emoji")]
+    Emoji {
+        #[serde(rename = "productId")]
+        product_id: String,
+        #[serde(rename = "emojiId")]
+        emoji_id: String,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub enum Mentionee {
+    #[serde(rename = "user")]
+    User {
+        #[serde(rename = "userId")]
+        user_id: String,
+    },
+    #[serde(rename = "all")]
+    All,
+}
+
+/// Enum representing the ways a text v2 message can be malformed.
+#[derive(Debug, PartialEq)]
+pub enum TextV2Error {
+    UnbalancedBrace(usize),
+    InvalidKey(String),
+    MissingSubstitution(String),
+    UnusedSubstitution(String),
+    TooManySubstitutions(usize),
+}
+
+impl fmt::Display for TextV2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextV2Error::UnbalancedBrace(position) => {
+                write!(f, "Unbalanced brace at byte {position}")
+            }
+            TextV2Error::InvalidKey(key) => write!(f, "Invalid placeholder key: {key}"),
+            TextV2Error::MissingSubstitution(key) => {
+                write!(f, "No substitution for placeholder: {key}")
+            }
+            TextV2Error::UnusedSubstitution(key) => {
+                write!(f, "Substitution is not used in the text: {key}")
+            }
+            TextV2Error::TooManySubstitutions(count) => {
+                write!(
+                    f,
+                    "{count} substitutions, at most {MAX_SUBSTITUTIONS} allowed"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TextV2Error {}
+
+impl TextMessageV2 {
+    pub fn new(text: &str) -> TextMessageV2 {
+        TextMessageV2 {
+            text: text.to_string(),
+            substitution: None,
+            quote_token: None,
+        }
+    }
+
+    /// Adds a substitution for `{key}`.
+    pub fn with_substitution(mut self, key: &str, substitution: Substitution) -> TextMessageV2 {
+        self.substitution
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), substitution);
+        self
+    }
+
+    /// Adds an @mention of `user_id` for `{key}`.
+    pub fn with_mention(self, key: &str, user_id: &str) -> TextMessageV2 {
+        self.with_substitution(
+            key,
+            Substitution::Mention {
+                mentionee: Mentionee::User {
+                    user_id: user_id.to_string(),
+                },
+            },
+        )
+    }
+
+    pub fn with_quote_token(mut self, quote_token: Option<&str>) -> TextMessageV2 {
+        self.quote_token = quote_token.map(str::to_string);
+        self
+    }
+
+    /// Checks that every `{key}` has a substitution and every substitution is used.
+    pub fn validate(&self) -> Result<(), TextV2Error> {
+        let empty = HashMap::new();
+        let substitution = self.substitution.as_ref().unwrap_or(&empty);
+        if substitution.len() > MAX_SUBSTITUTIONS {
+            return Err(TextV2Error::TooManySubstitutions(substitution.len()));
+        }
+        let keys = placeholders(&self.text)?;
+        for key in &keys {
+            if !substitution.contains_key(key) {
+                return Err(TextV2Error::MissingSubstitution(key.clone()));
+            }
+        }
+        let mut unused: Vec<&String> = substitution.keys().filter(|k| !keys.contains(k)).collect();
+        unused.sort();
+        match unused.first() {
+            Some(key) => Err(TextV2Error::UnusedSubstitution(key.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Escapes literal braces so `text` can be embedded in a text v2 message.
+pub fn escape_text(text: &str) -> String {
+    text.replace('{', "{{").replace('}', "}}")
+}
+
+/// Keys of the `{key}` placeholders of `text`. `{{` and `}}` are literal braces.
+fn placeholders(text: &str) -> Result<Vec<String>, TextV2Error> {
+    let mut keys = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((position, c)) = chars.next() {
+        match c {
+            '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                chars.next();
+            }
+            '}' if chars.peek().map(|(_, c)| *c) == Some('}') => {
+                chars.next();
+            }
+            '}' => return Err(TextV2Error::UnbalancedBrace(position)),
+            '{' => {
+                let mut key = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '}')) => break,
+                        Some((_, c)) => key.push(c),
+                        None => return Err(TextV2Error::UnbalancedBrace(position)),
+                    }
+                }
+                let valid = !key.is_empty()
+                    && key.chars().count() <= 20
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if !valid {
+                    return Err(TextV2Error::InvalidKey(key));
+                }
+                keys.push(key);
+            }
+            _ => {}
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_mention() {
+        let message = TextMessageV2::new("{asker} hello {everyone}")
+            .with_mention("asker", "U123")
+            .with_substitution(
+                "everyone",
+                Substitution::Mention {
+                    mentionee: Mentionee::All,
+                },
+            );
+        assert_eq!(message.validate(), Ok(()));
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["substitution"]["asker"]["type"], "mention");
+        assert_eq!(value["substitution"]["asker"]["mentionee"]["type"], "user");
+        assert_eq!(
+            value["substitution"]["asker"]["mentionee"]["userId"],
+            "U123"
+        );
+        assert_eq!(
+            value["substitution"]["everyone"]["mentionee"]["type"],
+            "all"
+        );
+        assert!(value.get("quoteToken").is_none());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let missing = TextMessageV2::new("hi {user}");
+        assert_eq!(
+            missing.validate(),
+            Err(TextV2Error::MissingSubstitution("user".to_string()))
+        );
+
+        let unused = TextMessageV2::new("hi").with_mention("user", "U1");
+        assert_eq!(
+            unused.validate(),
+            Err(TextV2Error::UnusedSubstitution("user".to_string()))
+        );
+
+        let unbalanced = TextMessageV2::new("hi {user");
+        assert_eq!(unbalanced.validate(), Err(TextV2Error::UnbalancedBrace(3)));
+
+        let invalid = TextMessageV2::new("hi {my-user}").with_mention("my-user", "U1");
+        assert_eq!(
+            invalid.validate(),
+            Err(TextV2Error::InvalidKey("my-user".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_escaped_braces() {
+        let text = format!("{{user}} {}", escape_text("fn main() { }"));
+        let message = TextMessageV2::new(&text).with_mention("user", "U1");
+        assert_eq!(message.validate(), Ok(()));
+        assert_eq!(text, "{user} fn main() {{ }}");
+    }
+}
@@ -0,0 +1,384 @@
+//! Metrics
+//! # Note
+//! Instruments of the bot, created from the meter of the installed `MeterProvider`:
+//!
+//! | Metric | Kind | Attributes |
+//! |---|---|---|
+//! | `linebot.webhook.events` | counter | `linebot.event.type` |
+//! | `linebot.webhook.queue_depth` | up-down counter | |
+//! | `linebot.openai.duration` | histogram, seconds | `gen_ai.request.model`, `error.type` on failure |
+//! | `linebot.openai.tokens` | counter | `gen_ai.request.model`, `gen_ai.token.type` |
+//! | `linebot.line.errors` | counter | `http.request.method`, `http.response.status_code`, `error.type` |
+//! | `linebot.pii.redactions` | counter | `linebot.pii.type` |
+//! | `linebot.moderation.flagged` | counter | `linebot.moderation.stage`, `linebot.moderation.category` |
+//!
+//! Without a provider the global meter is a no-op, so recording always costs little.
+use std::time::Duration;
+
+use opentelemetry::metrics::{Counter, Histogram, Meter, Unit, UpDownCounter};
+use opentelemetry::{global, KeyValue};
+
+use crate::events::EventType;
+use crate::moderation::{Flag, Stage};
+use crate::openai::client::Usage;
+use crate::openai::models::Model;
+use crate::redaction::Redactions;
+
+/// Name of the meter of the bot.
+pub const METER_NAME: &str = "line_botx";
+
+/// Type of a webhook event, e.g. `message` or `follow`.
+pub const EVENT_TYPE: &str = "linebot.event.type";
+/// Model a completion was requested from.
+pub const MODEL: &str = "gen_ai.request.model";
+/// `input` for prompt tokens, `output` for completion tokens.
+pub const TOKEN_TYPE: &str = "gen_ai.token.type";
+pub const HTTP_METHOD: &str = "http.request.method";
+pub const HTTP_STATUS: &str = "http.response.status_code";
+/// The status code of an HTTP error, `transport` when no response came back.
+pub const ERROR_TYPE: &str = "error.type";
+/// Detector that found a redacted value, e.g. `EMAIL`.
+pub const PII_TYPE: &str = "linebot.pii.type";
+/// `input` for questions, `output` for answers.
+pub const MODERATION_STAGE: &str = "linebot.moderation.stage";
+/// Category a text was flagged for, e.g. `violence` or `blocklist`.
+pub const MODERATION_CATEGORY: &str = "linebot.moderation.category";
+
+#[derive(Debug, Clone)]
+pub struct BotMetrics {
+    events: Counter<u64>,
+    queue_depth: UpDownCounter<i64>,
+    openai_duration: Histogram<f64>,
+    openai_tokens: Counter<u64>,
+    line_errors: Counter<u64>,
+    redactions: Counter<u64>,
+    flagged: Counter<u64>,
+}
+
+impl BotMetrics {
+    pub fn new(meter: &Meter) -> BotMetrics {
+        BotMetrics {
+            events: meter
+                .u64_counter("linebot.webhook.events")
+                .with_description("Webhook events received")
+                .with_unit(Unit::new("{event}"))
+                .init(),
+            queue_depth: meter
+                .i64_up_down_counter("linebot.webhook.queue_depth")
+                .with_description("Webhook events received and not handled yet")
+                .with_unit(Unit::new("{event}"))
+                .init(),
+            openai_duration: meter
+                .f64_histogram("linebot.openai.duration")
+                .with_description("Duration of the chat completions")
+                .with_unit(Unit::new("s"))
+                .init(),
+            openai_tokens: meter
+                .u64_counter("linebot.openai.tokens")
+                .with_description("Tokens used by the chat completions")
+                .with_unit(Unit::new("{token}"))
+                .init(),
+            line_errors: meter
+                .u64_counter("linebot.line.errors")
+                .with_description("Failed LINE API calls")
+                .with_unit(Unit::new("{call}"))
+                .init(),
+            redactions: meter
+                .u64_counter("linebot.pii.redactions")
+                .with_description("Personal data values replaced before calling OpenAI")
+                .with_unit(Unit::new("{value}"))
+                .init(),
+            flagged: meter
+                .u64_counter("linebot.moderation.flagged")
+                .with_description("Questions and answers blocked by moderation, by category")
+                .with_unit(Unit::new("{message}"))
+                .init(),
+        }
+    }
+
+    /// Instruments of the global meter provider.
+    pub fn global() -> BotMetrics {
+        BotMetrics::new(&global::meter(METER_NAME))
+    }
+
+    /// Counts the events of a webhook call and adds them to the queue.
+    pub fn events_received<'a, I>(&self, events: I)
+    where
+        I: IntoIterator<Item = &'a EventType>,
+    {
+        let mut received = 0;
+        for event in events {
+            self.events
+                .add(1, &[KeyValue::new(EVENT_TYPE, event.name())]);
+            received += 1;
+        }
+        self.queue_depth.add(received, &[]);
+    }
+
+    /// Removes a handled event from the queue.
+    pub fn event_handled(&self) {
+        self.queue_depth.add(-1, &[]);
+    }
+
+    /// Records a completion, with its usage if it succeeded.
+    pub fn completion(&self, model: Model, duration: Duration, usage: Option<&Usage>) {
+        let model = KeyValue::new(MODEL, model.to_string());
+        match usage {
+            Some(usage) => {
+                self.openai_duration
+                    .record(duration.as_secs_f64(), std::slice::from_ref(&model));
+                let tokens = [
+                    ("input", usage.prompt_tokens),
+                    ("output", usage.completion_tokens),
+                ];
+                for (token_type, count) in tokens {
+                    self.openai_tokens.add(
+                        count.max(0) as u64,
+                        &[model.clone(), KeyValue::new(TOKEN_TYPE, token_type)],
+                    );
+                }
+            }
+            None => self.openai_duration.record(
+                duration.as_secs_f64(),
+                &[model, KeyValue::new(ERROR_TYPE, "error")],
+            ),
+        }
+    }
+
+    /// Counts a LINE API call that failed, with the status of the response if any.
+    pub fn line_error(&self, method: &str, status: Option<u16>) {
+        let mut attributes = vec![KeyValue::new(HTTP_METHOD, method.to_string())];
+        match status {
+            Some(status) => {
+                attributes.push(KeyValue::new(HTTP_STATUS, i64::from(status)));
+                attributes.push(KeyValue::new(ERROR_TYPE, status.to_string()));
+            }
+            None => attributes.push(KeyValue::new(ERROR_TYPE, "transport")),
+        }
+        self.line_errors.add(1, &attributes);
+    }
+
+    /// Counts the values redacted while handling an event, by type.
+    pub fn redactions(&self, redactions: &Redactions) {
+        for (kind, count) in redactions.counts() {
+            self.redactions
+                .add(count, &[KeyValue::new(PII_TYPE, kind.to_string())]);
+        }
+    }
+
+    /// Counts a blocked question or answer, once per category it was flagged for.
+    pub fn flagged(&self, stage: Stage, flags: &[Flag]) {
+        for flag in flags {
+            self.flagged.add(
+                1,
+                &[
+                    KeyValue::new(MODERATION_STAGE, stage.name()),
+                    KeyValue::new(MODERATION_CATEGORY, flag.category.clone()),
+                ],
+            );
+        }
+    }
+}
+
+impl Default for BotMetrics {
+    fn default() -> Self {
+        BotMetrics::global()
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    //! A meter provider whose metrics are read back in memory.
+    use std::sync::{Arc, Weak};
+
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry::{Key, Value};
+    use opentelemetry_sdk::metrics::data::{DataPoint, Histogram, ResourceMetrics, Sum};
+    use opentelemetry_sdk::metrics::reader::{
+        AggregationSelector, MetricReader, TemporalitySelector,
+    };
+    use opentelemetry_sdk::metrics::{
+        Aggregation, InstrumentKind, ManualReader, MeterProvider, Pipeline,
+    };
+    use opentelemetry_sdk::Resource;
+
+    use super::*;
+
+    /// The reader is shared with the provider, which takes its readers by value.
+    #[derive(Debug, Clone)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl TemporalitySelector for SharedReader {
+        fn temporality(
+            &self,
+            kind: InstrumentKind,
+        ) -> opentelemetry_sdk::metrics::data::Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    impl AggregationSelector for SharedReader {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            self.0.aggregation(kind)
+        }
+    }
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.shutdown()
+        }
+    }
+
+    /// Instruments recording into memory, and the reader of what they recorded.
+    pub struct InMemoryMetrics {
+        pub metrics: BotMetrics,
+        reader: SharedReader,
+        _provider: MeterProvider,
+    }
+
+    impl InMemoryMetrics {
+        pub fn new() -> InMemoryMetrics {
+            let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+            let provider = MeterProvider::builder().with_reader(reader.clone()).build();
+            InMemoryMetrics {
+                metrics: BotMetrics::new(&provider.meter(METER_NAME)),
+                reader,
+                _provider: provider,
+            }
+        }
+
+        fn collect(&self) -> ResourceMetrics {
+            let mut rm = ResourceMetrics {
+                resource: Resource::empty(),
+                scope_metrics: Vec::new(),
+            };
+            self.reader.collect(&mut rm).unwrap();
+            rm
+        }
+
+        /// Value of the counter `name` for the points having every attribute of `attributes`.
+        pub fn sum(&self, name: &str, attributes: &[(&str, Value)]) -> i64 {
+            let rm = self.collect();
+            let mut total = 0;
+            for metric in rm.scope_metrics.iter().flat_map(|s| &s.metrics) {
+                if metric.name != name {
+                    continue;
+                }
+                let data = metric.data.as_any();
+                if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+                    total += matching(&sum.data_points, attributes)
+                        .map(|p| p.value as i64)
+                        .sum::<i64>();
+                }
+                if let Some(sum) = data.downcast_ref::<Sum<i64>>() {
+                    total += matching(&sum.data_points, attributes)
+                        .map(|p| p.value)
+                        .sum::<i64>();
+                }
+            }
+            total
+        }
+
+        /// Number of values recorded by the histogram `name` with every attribute of `attributes`.
+        pub fn count(&self, name: &str, attributes: &[(&str, Value)]) -> u64 {
+            let rm = self.collect();
+            rm.scope_metrics
+                .iter()
+                .flat_map(|s| &s.metrics)
+                .filter(|m| m.name == name)
+                .filter_map(|m| m.data.as_any().downcast_ref::<Histogram<f64>>())
+                .flat_map(|h| &h.data_points)
+                .filter(|p| has_attributes(p.attributes.iter(), attributes))
+                .map(|p| p.count)
+                .sum()
+        }
+    }
+
+    fn matching<'a, T>(
+        points: &'a [DataPoint<T>],
+        attributes: &'a [(&str, Value)],
+    ) -> impl Iterator<Item = &'a DataPoint<T>> {
+        points
+            .iter()
+            .filter(move |p| has_attributes(p.attributes.iter(), attributes))
+    }
+
+    fn has_attributes<'a>(
+        point: impl Iterator<Item = (&'a Key, &'a Value)>,
+        attributes: &[(&str, Value)],
+    ) -> bool {
+        let point: Vec<_> = point.collect();
+        attributes
+            .iter()
+            .all(|(key, value)| point.iter().any(|(k, v)| k.as_str() == *key && *v == value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::Value;
+
+    use super::testing::InMemoryMetrics;
+    use super::*;
+
+    #[test]
+    fn test_records_with_consistent_attributes() {
+        let memory = InMemoryMetrics::new();
+        let metrics = &memory.metrics;
+        let events = [EventType::Other, EventType::Other];
+        metrics.events_received(&events);
+        metrics.event_handled();
+
+        let usage = Usage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+        };
+        metrics.completion(Model::Gpt_4, Duration::from_millis(300), Some(&usage));
+        metrics.completion(Model::Gpt_4, Duration::from_millis(100), None);
+        metrics.line_error("POST", Some(429));
+        metrics.line_error("POST", None);
+
+        let gpt_4 = || (MODEL, Value::from("gpt-4"));
+        assert_eq!(memory.count("linebot.openai.duration", &[gpt_4()]), 2);
+        assert_eq!(
+            memory.count(
+                "linebot.openai.duration",
+                &[(ERROR_TYPE, Value::from("error"))]
+            ),
+            1
+        );
+        assert_eq!(
+            memory.sum(
+                "linebot.openai.tokens",
+                &[gpt_4(), (TOKEN_TYPE, Value::from("input"))]
+            ),
+            10
+        );
+        assert_eq!(
+            memory.sum("linebot.line.errors", &[(HTTP_STATUS, Value::I64(429))]),
+            1
+        );
+        assert_eq!(memory.sum("linebot.line.errors", &[]), 2);
+        assert_eq!(
+            memory.sum(
+                "linebot.webhook.events",
+                &[(EVENT_TYPE, Value::from("other"))]
+            ),
+            2
+        );
+        assert_eq!(memory.sum("linebot.webhook.queue_depth", &[]), 1);
+    }
+}
@@ -0,0 +1,388 @@
+//! Moderation
+//! # Note
+//! Questions are checked before they reach the model, and answers before they reach the
+//! chat. A blocked question is answered with `moderation.input_response`, a blocked
+//! answer is replaced with `moderation.output_response`.
+//!
+//! The local blocklist (`moderation.blocklist` keywords, matched ignoring case, and
+//! `moderation.blocklist_patterns` regular expressions) is checked first. The text then
+//! goes to the OpenAI-compatible `/v1/moderations` endpoint, unless `moderation.api` is
+//! off. When that call fails the blocklist is all there is.
+//!
+//! A category with a threshold in `moderation.thresholds` (`category=score`) is flagged
+//! when its score reaches it, any other one when the endpoint flags it.
+//!
+//! Flagged events are logged, and appended to `moderation.review_file` as JSON lines.
+//! They are pruned from it after `storage.turn_retention_days`, as the turns are.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{error, warn};
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+
+use crate::config::ModerationConfig;
+use crate::dates::format_timestamp;
+use crate::openai::moderations::{ModerationResult, ModerationsClient};
+use crate::storage::prune_lines;
+
+/// Category of the blocklist matches.
+pub const BLOCKLIST: &str = "blocklist";
+
+/// Whether a text is the question or the answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Input,
+    Output,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Input => "input",
+            Stage::Output => "output",
+        }
+    }
+}
+
+/// A category a text was flagged for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Flag {
+    pub category: String,
+    /// Score given by the endpoint, none for the blocklist.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+/// A moderation setting with an invalid value.
+#[derive(Debug, PartialEq)]
+pub struct ModerationError {
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for ModerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+impl std::error::Error for ModerationError {}
+
+/// A flagged event, as written to the review file.
+#[derive(Debug, Serialize)]
+pub struct FlaggedEvent<'a> {
+    /// `YYYY-MM-DDTHH:MM:SSZ`.
+    pub timestamp: String,
+    pub chat_id: &'a str,
+    pub user_id: Option<&'a str>,
+    pub stage: Stage,
+    pub flags: &'a [Flag],
+    /// The text that was checked, with personal data redacted.
+    pub text: &'a str,
+}
+
+#[derive(Debug, Default)]
+pub struct Moderator {
+    enabled: bool,
+    api: bool,
+    blocklist: Vec<Regex>,
+    thresholds: HashMap<String, f64>,
+    input_response: String,
+    output_response: String,
+    review_file: Option<PathBuf>,
+}
+
+/// Held while writing the review file, so that lines are not interleaved, nor lost to a
+/// pruning, whichever moderator of the reloaded configurations writes them.
+static REVIEW: Mutex<()> = Mutex::new(());
+
+impl Moderator {
+    /// The moderator of `moderation`, which lets everything through when disabled.
+    pub fn from_config(config: &ModerationConfig) -> Result<Moderator, ModerationError> {
+        let error = |key: &str, message: String| ModerationError {
+            key: key.to_string(),
+            message,
+        };
+        let mut blocklist = Vec::new();
+        for keyword in config.blocklist.iter().filter(|k| !k.trim().is_empty()) {
+            let regex = RegexBuilder::new(&regex::escape(keyword.trim()))
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| error("blocklist", e.to_string()))?;
+            blocklist.push(regex);
+        }
+        for pattern in &config.blocklist_patterns {
+            let regex =
+                Regex::new(pattern).map_err(|e| error("blocklist_patterns", e.to_string()))?;
+            blocklist.push(regex);
+        }
+        let mut thresholds = HashMap::new();
+        for threshold in &config.thresholds {
+            let (category, score) = threshold.split_once('=').ok_or_else(|| {
+                error("thresholds", format!("{threshold:?} is not category=score"))
+            })?;
+            let score: f64 = score
+                .trim()
+                .parse()
+                .map_err(|_| error("thresholds", format!("{score:?} is not a number")))?;
+            if !(0.0..=1.0).contains(&score) {
+                return Err(error(
+                    "thresholds",
+                    format!("{threshold:?} must be between 0 and 1"),
+                ));
+            }
+            thresholds.insert(category.trim().to_string(), score);
+        }
+        Ok(Moderator {
+            enabled: config.enabled,
+            api: config.api,
+            blocklist,
+            thresholds,
+            input_response: config.input_response.clone(),
+            output_response: config.output_response.clone(),
+            review_file: config.review_file.as_ref().map(PathBuf::from),
+        })
+    }
+
+    /// What the bot says instead of a blocked question or answer.
+    pub fn response(&self, stage: Stage) -> &str {
+        match stage {
+            Stage::Input => &self.input_response,
+            Stage::Output => &self.output_response,
+        }
+    }
+
+    /// The categories `text` is flagged for, none when it may go through.
+    pub async fn check(&self, client: &ModerationsClient, text: &str) -> Vec<Flag> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let flags = self.blocked(text);
+        if !flags.is_empty() || !self.api {
+            return flags;
+        }
+        match client.moderate(text).await {
+            Ok(result) => self.judge(&result),
+            Err(e) => {
+                error!("Moderation failed, using the blocklist only: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// The blocklist flag of `text`, if it matches.
+    pub fn blocked(&self, text: &str) -> Vec<Flag> {
+        if self.blocklist.iter().any(|regex| regex.is_match(text)) {
+            vec![Flag {
+                category: BLOCKLIST.to_string(),
+                score: None,
+            }]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// The categories of `result` over their threshold, or flagged by the endpoint.
+    pub fn judge(&self, result: &ModerationResult) -> Vec<Flag> {
+        let mut flags: Vec<Flag> = result
+            .category_scores
+            .iter()
+            .filter(|(category, score)| match self.thresholds.get(*category) {
+                Some(threshold) => *score >= threshold,
+                None => result.categories.get(*category).copied().unwrap_or(false),
+            })
+            .map(|(category, score)| Flag {
+                category: category.clone(),
+                score: Some(*score),
+            })
+            .collect();
+        flags.sort_by(|a, b| a.category.cmp(&b.category));
+        flags
+    }
+
+    /// Logs a flagged event, and appends it to the review file if there is one.
+    pub fn review(
+        &self,
+        chat_id: &str,
+        user_id: Option<&str>,
+        stage: Stage,
+        flags: &[Flag],
+        text: &str,
+    ) {
+        let categories: Vec<&str> = flags.iter().map(|f| f.category.as_str()).collect();
+        warn!("flagged : {} {} {:?}", chat_id, stage.name(), categories);
+        let Some(path) = &self.review_file else {
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let event = FlaggedEvent {
+            timestamp: format_timestamp(now),
+            chat_id,
+            user_id,
+            stage,
+            flags,
+            text,
+        };
+        let _review = REVIEW.lock().unwrap();
+        if let Err(e) = append_line(path, &event) {
+            error!("Failed writing {}: {}", path.display(), e);
+        }
+    }
+
+    /// Removes the events older than `before`, in milliseconds, from the review file.
+    pub fn prune_review_file(&self, before: i64) -> io::Result<usize> {
+        let Some(path) = &self.review_file else {
+            return Ok(0);
+        };
+        // The timestamps sort as their text does
+        let before = format_timestamp(u64::try_from(before / 1000).unwrap_or_default());
+        let _review = REVIEW.lock().unwrap();
+        prune_lines(path, |line| {
+            serde_json::from_str::<serde_json::Value>(line).is_ok_and(|event| {
+                event["timestamp"]
+                    .as_str()
+                    .is_some_and(|t| t < before.as_str())
+            })
+        })
+    }
+}
+
+fn append_line<T: Serialize>(path: &PathBuf, value: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(value).map_err(io::Error::other)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::moderations::DEFAULT_MODERATION_MODEL;
+
+    fn moderator(config: ModerationConfig) -> Moderator {
+        Moderator::from_config(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_blocklist_without_api() {
+        let moderator = moderator(ModerationConfig {
+            api: false,
+            blocklist: vec!["Bad Word".to_string()],
+            blocklist_patterns: vec![r"\bkill\s+\w+".to_string()],
+            ..Default::default()
+        });
+        let client = ModerationsClient::new("key", "http://127.0.0.1:9", DEFAULT_MODERATION_MODEL);
+        assert_eq!(
+            moderator.check(&client, "a BAD word").await[0].category,
+            BLOCKLIST
+        );
+        assert!(!moderator.check(&client, "kill them").await.is_empty());
+        assert!(moderator.check(&client, "skill set").await.is_empty());
+
+        let disabled = Moderator::from_config(&ModerationConfig {
+            enabled: false,
+            blocklist: vec!["bad".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(disabled.check(&client, "bad").await.is_empty());
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let moderator = moderator(ModerationConfig {
+            thresholds: vec!["violence=0.5".to_string(), "harassment=0.9".to_string()],
+            ..Default::default()
+        });
+        let result = ModerationResult {
+            flagged: true,
+            categories: HashMap::from([
+                ("violence".to_string(), false),
+                ("harassment".to_string(), true),
+                ("hate".to_string(), true),
+            ]),
+            category_scores: HashMap::from([
+                ("violence".to_string(), 0.6),
+                ("harassment".to_string(), 0.8),
+                ("hate".to_string(), 0.7),
+            ]),
+        };
+        let categories: Vec<String> = moderator
+            .judge(&result)
+            .into_iter()
+            .map(|f| f.category)
+            .collect();
+        assert_eq!(categories, vec!["hate", "violence"]);
+
+        for thresholds in [vec!["violence"], vec!["violence=high"], vec!["violence=2"]] {
+            let config = ModerationConfig {
+                thresholds: thresholds.iter().map(|t| t.to_string()).collect(),
+                ..Default::default()
+            };
+            assert_eq!(
+                Moderator::from_config(&config).unwrap_err().key,
+                "thresholds"
+            );
+        }
+    }
+
+    #[test]
+    fn test_review_file() {
+        let path = std::env::temp_dir().join(format!("moderation-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let moderator = moderator(ModerationConfig {
+            review_file: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        });
+        let flags = moderator.blocked("x");
+        assert!(flags.is_empty());
+        let flags = [Flag {
+            category: "violence".to_string(),
+            score: Some(0.9),
+        }];
+        moderator.review("C1", Some("U1"), Stage::Output, &flags, "text");
+        let text = std::fs::read_to_string(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(line["stage"], "output");
+        assert_eq!(line["flags"][0]["category"], "violence");
+        assert_eq!(line["user_id"], "U1");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_review_file_is_pruned() {
+        let path =
+            std::env::temp_dir().join(format!("moderation-pruned-{}.jsonl", std::process::id()));
+        let old = r#"{"timestamp":"2024-01-01T00:00:00Z","chat_id":"C1","stage":"input"}"#;
+        std::fs::write(&path, format!("{old}\n")).unwrap();
+        let moderator = moderator(ModerationConfig {
+            review_file: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        });
+        let flags = [Flag {
+            category: BLOCKLIST.to_string(),
+            score: None,
+        }];
+        moderator.review("C2", None, Stage::Input, &flags, "text");
+
+        // 2024-06-01
+        assert_eq!(moderator.prune_review_file(1_717_200_000_000).unwrap(), 1);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.contains(r#""chat_id":"C2""#));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -2,11 +2,11 @@
 //! # Note
 //! These are types of actions for your bot to take when a user taps a button or an image in a message.
 //! <https://developers.line.biz/en/reference/messaging-api/#action-objects>
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 
 /// Action object
 /// # Note
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Action {
     #[serde(flatten)]
     pub r#type: ActionType,
@@ -15,13 +15,13 @@ pub struct Action {
 }
 
 /// Action object types
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum ActionType {
     #[serde(rename = "postback")]
     Postback {
         data: String,
-        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "displayText", skip_serializing_if = "Option::is_none")]
         display_text: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         text: Option<String>,
@@ -51,12 +51,18 @@ pub enum ActionType {
     CameraRoll {},
     #[serde(rename = "location")]
     Location {},
+    #[serde(rename = "richmenuswitch")]
+    RichMenuSwitch {
+        #[serde(rename = "richMenuAliasId")]
+        rich_menu_alias_id: String,
+        data: String,
+    },
 }
 
 /// Alt uri object
 /// # Note
 /// URI opened on LINE for macOS and Windows when the action is performed.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct AltUri {
     pub desktop: String,
 }
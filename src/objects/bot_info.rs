@@ -0,0 +1,20 @@
+use serde_derive::Deserialize;
+
+/// Basic information about the bot. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#get-bot-info)
+#[derive(Deserialize, Debug)]
+pub struct BotInfo {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    #[serde(rename = "basicId")]
+    pub basic_id: String,
+    #[serde(rename = "premiumId")]
+    pub premium_id: Option<String>,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+    #[serde(rename = "pictureUrl")]
+    pub picture_url: Option<String>,
+    #[serde(rename = "chatMode")]
+    pub chat_mode: String,
+    #[serde(rename = "markAsReadMode")]
+    pub mark_as_read_mode: String,
+}
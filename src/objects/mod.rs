@@ -1,8 +1,20 @@
 //! Various objects needed when sending message
 
 pub mod action;
+pub mod bot_info;
+// Not sent by the bot yet
+#[allow(unused_imports)]
 pub mod narrowcast;
 pub mod profile;
+pub mod quick_reply;
+pub mod rich_menu;
+pub mod sent_message;
+pub mod transcoding;
 
 pub use action::Action;
+pub use bot_info::BotInfo;
 pub use profile::Profile;
+pub use quick_reply::QuickReply;
+pub use rich_menu::{RichMenu, RichMenuAlias, RichMenuResponse};
+pub use sent_message::{SentMessage, SentMessages};
+pub use transcoding::TranscodingStatus;
@@ -0,0 +1,69 @@
+//! Quick reply
+//! # Note
+//! Buttons shown at the bottom of the chat screen while the message is the latest one.
+//! <https://developers.line.biz/en/reference/messaging-api/#quick-reply>
+use serde_derive::Serialize;
+
+use crate::objects::action::ActionType;
+use crate::objects::Action;
+
+/// At most 13 buttons are shown.
+pub const MAX_QUICK_REPLY_ITEMS: usize = 13;
+
+#[derive(Serialize, Debug)]
+pub struct QuickReply {
+    pub items: Vec<QuickReplyItem>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub enum QuickReplyItem {
+    #[serde(rename = "action")]
+    Action {
+        #[serde(rename = "imageUrl", skip_serializing_if = "Option::is_none")]
+        image_url: Option<String>,
+        action: Action,
+    },
+}
+
+impl QuickReply {
+    /// Buttons that each send their label as a message, e.g. a command.
+    pub fn messages<I, S>(texts: I) -> QuickReply
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let items = texts
+            .into_iter()
+            .take(MAX_QUICK_REPLY_ITEMS)
+            .map(|text| {
+                let text = text.into();
+                QuickReplyItem::Action {
+                    image_url: None,
+                    action: Action {
+                        // Labels are limited to 20 characters
+                        label: Some(text.chars().take(20).collect()),
+                        r#type: ActionType::Message { text },
+                    },
+                }
+            })
+            .collect();
+        QuickReply { items }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_message_items() {
+        let quick_reply = QuickReply::messages(["/help", "/usage"]);
+        let value = serde_json::to_value(&quick_reply).unwrap();
+        assert_eq!(value["items"][0]["type"], "action");
+        assert_eq!(value["items"][0]["action"]["type"], "message");
+        assert_eq!(value["items"][0]["action"]["label"], "/help");
+        assert_eq!(value["items"][1]["action"]["text"], "/usage");
+        assert!(value["items"][0].get("imageUrl").is_none());
+    }
+}
@@ -0,0 +1,108 @@
+//! Rich menu objects
+//! # Note
+//! <https://developers.line.biz/en/reference/messaging-api/#rich-menu-object>
+use crate::objects::Action;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Rich menu object, as sent to `create_rich_menu` or loaded from a JSON file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RichMenu {
+    pub size: RichMenuSize,
+    pub selected: bool,
+    pub name: String,
+    #[serde(rename = "chatBarText")]
+    pub chat_bar_text: String,
+    pub areas: Vec<RichMenuArea>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RichMenuSize {
+    pub width: i64,
+    pub height: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RichMenuArea {
+    pub bounds: RichMenuBounds,
+    pub action: Action,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RichMenuBounds {
+    pub x: i64,
+    pub y: i64,
+    pub width: i64,
+    pub height: i64,
+}
+
+/// Rich menu returned by the API, with its id.
+#[derive(Deserialize, Debug)]
+pub struct RichMenuResponse {
+    #[serde(rename = "richMenuId")]
+    pub rich_menu_id: String,
+    #[serde(flatten)]
+    pub rich_menu: RichMenu,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RichMenuList {
+    pub richmenus: Vec<RichMenuResponse>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RichMenuId {
+    #[serde(rename = "richMenuId")]
+    pub rich_menu_id: String,
+}
+
+/// Rich menu alias object
+/// <https://developers.line.biz/en/reference/messaging-api/#get-rich-menu-alias-by-id-response>
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RichMenuAlias {
+    #[serde(rename = "richMenuAliasId")]
+    pub rich_menu_alias_id: String,
+    #[serde(rename = "richMenuId")]
+    pub rich_menu_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RichMenuAliasList {
+    pub aliases: Vec<RichMenuAlias>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::action::ActionType;
+
+    #[test]
+    fn test_rich_menu_round_trip() {
+        let json = r#"{
+            "size": {"width": 2500, "height": 843},
+            "selected": false,
+            "name": "translate",
+            "chatBarText": "Menu",
+            "areas": [
+                {
+                    "bounds": {"x": 0, "y": 0, "width": 1250, "height": 843},
+                    "action": {"type": "postback", "data": "mode=chat", "displayText": "Chat"}
+                },
+                {
+                    "bounds": {"x": 1250, "y": 0, "width": 1250, "height": 843},
+                    "action": {"type": "richmenuswitch", "richMenuAliasId": "draw", "data": "mode=draw"}
+                }
+            ]
+        }"#;
+        let menu: RichMenu = serde_json::from_str(json).unwrap();
+        assert_eq!(menu.chat_bar_text, "Menu");
+        assert!(matches!(
+            &menu.areas[1].action.r#type,
+            ActionType::RichMenuSwitch { rich_menu_alias_id, .. } if rich_menu_alias_id == "draw"
+        ));
+
+        let value = serde_json::to_value(&menu).unwrap();
+        assert_eq!(value["areas"][0]["action"]["displayText"], "Chat");
+        assert_eq!(value["areas"][1]["action"]["type"], "richmenuswitch");
+    }
+}
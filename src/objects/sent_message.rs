@@ -0,0 +1,16 @@
+use serde_derive::Deserialize;
+
+/// Response body of the reply and push message endpoints.
+#[derive(Deserialize, Debug)]
+pub struct SentMessages {
+    #[serde(rename = "sentMessages")]
+    pub sent_messages: Vec<SentMessage>,
+}
+
+/// A message sent by the bot. Its `quote_token` lets later messages quote it.
+#[derive(Deserialize, Debug)]
+pub struct SentMessage {
+    pub id: String,
+    #[serde(rename = "quoteToken")]
+    pub quote_token: Option<String>,
+}
@@ -0,0 +1,18 @@
+use serde_derive::Deserialize;
+
+/// Preparation status of a video or audio message sent by a user.
+/// [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#verify-video-or-audio-preparation-status)
+#[derive(Deserialize, Debug)]
+pub struct TranscodingStatus {
+    pub status: TranscodingState,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TranscodingState {
+    #[serde(rename = "processing")]
+    Processing,
+    #[serde(rename = "succeeded")]
+    Succeeded,
+    #[serde(rename = "failed")]
+    Failed,
+}
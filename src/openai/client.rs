@@ -1,10 +1,18 @@
 use std::fmt;
 
 use log::debug;
+use opentelemetry::trace::{FutureExt, Status, TraceContextExt};
+use opentelemetry::{Array, Context, KeyValue, StringValue, Value};
+use reqwest::header::HeaderMap;
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 
+use crate::metrics::{HTTP_STATUS, MODEL};
 use crate::openai::models::{LogitBias, Model, Role};
+use crate::traces::{
+    self, FINISH_REASONS, GEN_AI_OPERATION, GEN_AI_SYSTEM, INPUT_TOKENS, OUTPUT_TOKENS,
+    RESPONSE_MODEL,
+};
 
 /// Main ChatGPTClient struct.
 pub struct ChatGPTClient {
@@ -61,6 +69,7 @@ impl Default for ChatInput {
 
 /// Represents the response from the chat API call.
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct ChatResponse {
     pub id: String,
     pub object: String,
@@ -72,6 +81,7 @@ pub struct ChatResponse {
 
 /// Represents the usage information in the chat API response.
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct Usage {
     pub prompt_tokens: i64,
     pub completion_tokens: i64,
@@ -166,14 +176,58 @@ impl ChatGPTClient {
     ///
     /// Returns a ChatGPTError if the request fails.
     pub async fn chat(&self, input: ChatInput) -> Result<ChatResponse, ChatGPTError> {
+        let cx = traces::client_context(
+            &Context::current(),
+            format!("chat {}", input.model),
+            vec![
+                KeyValue::new(GEN_AI_SYSTEM, "openai"),
+                KeyValue::new(GEN_AI_OPERATION, "chat"),
+                KeyValue::new(MODEL, input.model.to_string()),
+            ],
+        );
+        let result = self.send_chat(&input, &cx).with_context(cx.clone()).await;
+        let span = cx.span();
+        match &result {
+            Ok(response) => {
+                let finish_reasons: Vec<StringValue> = response
+                    .choices
+                    .iter()
+                    .map(|c| c.finish_reason.clone().into())
+                    .collect();
+                span.set_attributes([
+                    KeyValue::new(RESPONSE_MODEL, response.model.clone()),
+                    KeyValue::new(INPUT_TOKENS, response.usage.prompt_tokens),
+                    KeyValue::new(OUTPUT_TOKENS, response.usage.completion_tokens),
+                    KeyValue::new(FINISH_REASONS, Value::Array(Array::from(finish_reasons))),
+                ]);
+            }
+            Err(e) => span.set_status(Status::error(e.to_string())),
+        }
+        span.end();
+        result
+    }
+
+    /// Calls the API in the span of `cx`.
+    async fn send_chat(
+        &self,
+        input: &ChatInput,
+        cx: &Context,
+    ) -> Result<ChatResponse, ChatGPTError> {
         let url = format!("{}/v1/chat/completions", self.base_url);
+        let mut headers = HeaderMap::new();
+        traces::inject(cx, &mut headers);
         let response = self
             .client
             .post(&url)
             .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&input)
+            .headers(headers)
+            .json(input)
             .send()
             .await?;
+        cx.span().set_attribute(KeyValue::new(
+            HTTP_STATUS,
+            i64::from(response.status().as_u16()),
+        ));
 
         debug!(
             "API call to url: {}\n with json payload: {:?}",
@@ -0,0 +1,138 @@
+use log::debug;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::openai::client::ChatGPTError;
+
+/// Embedding model used when none is given.
+pub const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+/// Client for the OpenAI-compatible `/v1/embeddings` endpoint.
+pub struct EmbeddingsClient {
+    base_url: String,
+    api_key: String,
+    model: String,
+    client: Client,
+}
+
+/// Represents the input for the embeddings API call.
+#[derive(Debug, Serialize)]
+pub struct EmbeddingInput {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+/// Represents the response from the embeddings API call.
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct EmbeddingResponse {
+    pub model: String,
+    pub data: Vec<Embedding>,
+    pub usage: EmbeddingUsage,
+}
+
+/// One embedding, `index` is the position of the matching input.
+#[derive(Debug, Deserialize)]
+pub struct Embedding {
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+/// Represents the usage information in the embeddings API response.
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct EmbeddingUsage {
+    pub prompt_tokens: i64,
+    pub total_tokens: i64,
+}
+
+impl EmbeddingsClient {
+    /// Creates a new EmbeddingsClient with the given API key, base URL and model.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - The API key for the embeddings API.
+    /// * `base_url` - The base URL for the embeddings API.
+    /// * `model` - The embedding model, e.g. `text-embedding-3-small`.
+    pub fn new(api_key: &str, base_url: &str, model: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            api_key: api_key.to_string(),
+            model: model.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Embeds every string of `input`, returning the vectors in input order.
+    ///
+    /// # Errors
+    ///
+    /// Returns a ChatGPTError if the request fails.
+    pub async fn embed(&self, input: Vec<String>) -> Result<Vec<Vec<f32>>, ChatGPTError> {
+        let url = format!("{}/v1/embeddings", self.base_url);
+        let input = EmbeddingInput {
+            model: self.model.clone(),
+            input,
+        };
+        debug!(
+            "API call to url: {} with {} inputs",
+            &url,
+            input.input.len()
+        );
+        let response = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&input)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::OK {
+            let mut response = response.json::<EmbeddingResponse>().await?;
+            response.data.sort_by_key(|e| e.index);
+            Ok(response.data.into_iter().map(|e| e.embedding).collect())
+        } else {
+            let status_code = response.status();
+            let body = response.text().await?;
+            Err(ChatGPTError::RequestFailed(format!(
+                "Request failed with status code: {status_code}\nBody: {body}"
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_embed_error_handling() {
+        let client = EmbeddingsClient::new(
+            "dummy_api_key",
+            "https://dummy-api-url.com",
+            DEFAULT_EMBEDDING_MODEL,
+        );
+        assert_eq!(client.model(), "text-embedding-3-small");
+        let result = client.embed(vec!["hello".to_string()]).await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_response() {
+        let json = r#"{
+            "object": "list",
+            "model": "text-embedding-3-small",
+            "data": [
+                {"object": "embedding", "index": 0, "embedding": [0.1, 0.2]}
+            ],
+            "usage": {"prompt_tokens": 2, "total_tokens": 2}
+        }"#;
+        let response: EmbeddingResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(response.data[0].embedding, vec![0.1, 0.2]);
+        assert_eq!(response.usage.total_tokens, 2);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod client;
+pub mod embeddings;
 pub mod models;
+pub mod moderations;
 pub mod tokenizer;
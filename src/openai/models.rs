@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 /// Currently supported models are:
 /// - Gpt3_5Turbo
 /// - Gpt4
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 #[allow(non_camel_case_types)] // Add this line to suppress the warning
 pub enum Model {
@@ -28,6 +28,15 @@ pub enum Model {
 }
 
 impl Model {
+    /// Every model, in the order they are offered to users.
+    pub const ALL: [Model; 5] = [
+        Model::Gpt3_5Turbo,
+        Model::Gpt_4,
+        Model::Gpt_4_32k,
+        Model::Gpt_4Turbo,
+        Model::Gpt_4Turbo_Vision,
+    ];
+
     pub fn max_tokens(&self) -> usize {
         match self {
             Model::Gpt3_5Turbo => 4096,
@@ -37,6 +46,18 @@ impl Model {
             Model::Gpt_4Turbo_Vision => 128000,
         }
     }
+
+    /// List price in USD of 1,000 prompt tokens and of 1,000 completion tokens,
+    /// unless `usage.prices` sets another.
+    pub fn price_per_1k_tokens(&self) -> (f64, f64) {
+        match self {
+            Model::Gpt3_5Turbo => (0.0015, 0.002),
+            Model::Gpt_4 => (0.03, 0.06),
+            Model::Gpt_4_32k => (0.06, 0.12),
+            Model::Gpt_4Turbo => (0.01, 0.03),
+            Model::Gpt_4Turbo_Vision => (0.01, 0.03),
+        }
+    }
 }
 
 /// Implement Display to convert the enum back to a string representation.
@@ -331,6 +352,13 @@ mod tests {
         assert_eq!(model.max_tokens(), 128000);
     }
 
+    // Test the price of Gpt_4 tokens.
+    #[test]
+    fn test_price_per_1k_tokens_gpt_4() {
+        let model = Model::Gpt_4;
+        assert_eq!(model.price_per_1k_tokens(), (0.03, 0.06));
+    }
+
     // Test the max tokens for Gpt_4Turbo_Vision.
     #[test]
     fn test_max_tokens_gpt_4turbo_vision() {
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use log::debug;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::openai::client::ChatGPTError;
+
+/// Moderation model used when none is given.
+pub const DEFAULT_MODERATION_MODEL: &str = "omni-moderation-latest";
+
+/// Client for the OpenAI-compatible `/v1/moderations` endpoint.
+pub struct ModerationsClient {
+    base_url: String,
+    api_key: String,
+    model: String,
+    client: Client,
+}
+
+/// Represents the input for the moderations API call.
+#[derive(Debug, Serialize)]
+pub struct ModerationInput {
+    pub model: String,
+    pub input: String,
+}
+
+/// Represents the response from the moderations API call.
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct ModerationResponse {
+    pub model: String,
+    pub results: Vec<ModerationResult>,
+}
+
+/// The classification of one input, by category such as `violence` or `self-harm`.
+#[derive(Debug, Default, Deserialize)]
+#[allow(dead_code)]
+pub struct ModerationResult {
+    pub flagged: bool,
+    pub categories: HashMap<String, bool>,
+    pub category_scores: HashMap<String, f64>,
+}
+
+impl ModerationsClient {
+    /// Creates a new ModerationsClient with the given API key, base URL and model.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - The API key for the moderations API.
+    /// * `base_url` - The base URL for the moderations API.
+    /// * `model` - The moderation model, e.g. `omni-moderation-latest`.
+    pub fn new(api_key: &str, base_url: &str, model: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            api_key: api_key.to_string(),
+            model: model.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    /// Classifies `text`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a ChatGPTError if the request fails.
+    pub async fn moderate(&self, text: &str) -> Result<ModerationResult, ChatGPTError> {
+        let url = format!("{}/v1/moderations", self.base_url);
+        let input = ModerationInput {
+            model: self.model.clone(),
+            input: text.to_string(),
+        };
+        debug!("API call to url: {} with {} chars", &url, text.len());
+        let response = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&input)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::OK {
+            let response = response.json::<ModerationResponse>().await?;
+            response.results.into_iter().next().ok_or_else(|| {
+                ChatGPTError::RequestFailed("Moderation response has no result".to_string())
+            })
+        } else {
+            let status_code = response.status();
+            let body = response.text().await?;
+            Err(ChatGPTError::RequestFailed(format!(
+                "Request failed with status code: {status_code}\nBody: {body}"
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_moderate_error_handling() {
+        let client = ModerationsClient::new(
+            "dummy_api_key",
+            "https://dummy-api-url.com",
+            DEFAULT_MODERATION_MODEL,
+        );
+        assert!(client.moderate("hello").await.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_response() {
+        let json = r#"{
+            "id": "modr-1",
+            "model": "omni-moderation-latest",
+            "results": [{
+                "flagged": true,
+                "categories": {"violence": true, "harassment": false},
+                "category_scores": {"violence": 0.91, "harassment": 0.02}
+            }]
+        }"#;
+        let response: ModerationResponse = serde_json::from_str(json).unwrap();
+        let result = &response.results[0];
+        assert!(result.flagged);
+        assert!(result.categories["violence"]);
+        assert_eq!(result.category_scores["harassment"], 0.02);
+    }
+}
@@ -0,0 +1,199 @@
+//! Personas
+//! # Note
+//! Named system prompts, each with its own model and generation parameters, loaded from
+//! the JSON file of `PERSONAS_FILE`:
+//! ```json
+//! {
+//!   "default": "assistant",
+//!   "personas": [
+//!     {
+//!       "name": "assistant",
+//!       "system_prompt": "You are a helpful assistant talking to {display_name}. Today is {date}. Answer in {language}.",
+//!       "model": "gpt-4",
+//!       "temperature": 0.7,
+//!       "max_tokens": 800,
+//!       "presence_penalty": 0.5
+//!     }
+//!   ]
+//! }
+//! ```
+//! Every chat uses the default persona until it picks another one with `/persona`.
+//! The generation parameters of a persona replace the global ones.
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dates::format_date;
+use crate::generation::GenerationParams;
+use crate::openai::models::Model;
+use crate::settings::ChatSettings;
+
+/// A system prompt and the generation parameters that go with it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Persona {
+    pub name: String,
+    /// May contain `{display_name}`, `{date}` and `{language}`.
+    pub system_prompt: String,
+    #[serde(default)]
+    pub model: Option<Model>,
+    #[serde(flatten)]
+    pub params: GenerationParams,
+}
+
+/// Values of the template variables of a system prompt.
+#[derive(Debug, Clone, Default)]
+pub struct PromptVars {
+    pub display_name: Option<String>,
+    pub language: Option<String>,
+    /// Seconds since the epoch, now if unset.
+    pub now: Option<u64>,
+}
+
+impl Persona {
+    /// Whether the system prompt needs the profile of the user.
+    pub fn uses_profile(&self) -> bool {
+        self.system_prompt.contains("{display_name}") || self.system_prompt.contains("{language}")
+    }
+
+    /// The system prompt with its template variables filled in.
+    pub fn system_prompt(&self, vars: &PromptVars) -> String {
+        let now = vars.now.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+        self.system_prompt
+            .replace(
+                "{display_name}",
+                vars.display_name.as_deref().unwrap_or("the user"),
+            )
+            .replace("{language}", vars.language.as_deref().unwrap_or("en"))
+            .replace("{date}", &format_date(now))
+    }
+}
+
+/// Contents of the personas file.
+#[derive(Debug, Deserialize)]
+struct PersonasFile {
+    default: Option<String>,
+    personas: Vec<Persona>,
+}
+
+/// Every persona, and the one used by chats that did not pick one.
+#[derive(Debug, Default)]
+pub struct PersonaRegistry {
+    personas: Vec<Persona>,
+    default: Option<String>,
+}
+
+impl PersonaRegistry {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<PersonaRegistry> {
+        let json = std::fs::read_to_string(path)?;
+        PersonaRegistry::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> io::Result<PersonaRegistry> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let file: PersonasFile = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        for (i, persona) in file.personas.iter().enumerate() {
+            if persona.name.trim().is_empty() || persona.name.contains(char::is_whitespace) {
+                return Err(invalid(format!("invalid persona name: {:?}", persona.name)));
+            }
+            if file.personas[..i]
+                .iter()
+                .any(|p| p.name.eq_ignore_ascii_case(&persona.name))
+            {
+                return Err(invalid(format!("duplicate persona: {}", persona.name)));
+            }
+            if let Err(e) = persona.params.validate() {
+                return Err(invalid(format!("{} of {}", e, persona.name)));
+            }
+        }
+        if let Some(default) = &file.default {
+            if !file.personas.iter().any(|p| &p.name == default) {
+                return Err(invalid(format!("unknown default persona: {default}")));
+            }
+        }
+        Ok(PersonaRegistry {
+            personas: file.personas,
+            default: file.default,
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.personas.iter().map(|p| p.name.clone()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Persona> {
+        self.personas.iter().find(|p| p.name == name)
+    }
+
+    /// The persona picked by the chat, or the default one.
+    pub fn for_chat(&self, settings: &ChatSettings) -> Option<&Persona> {
+        settings
+            .persona
+            .as_deref()
+            .and_then(|name| self.get(name))
+            .or_else(|| self.get(self.default.as_deref()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERSONAS: &str = r#"{
+        "default": "assistant",
+        "personas": [
+            {"name": "assistant", "system_prompt": "Help {display_name} on {date} in {language}."},
+            {"name": "teacher", "system_prompt": "Teach.", "model": "gpt-4", "temperature": 0.2, "max_tokens": 300}
+        ]
+    }"#;
+
+    #[test]
+    fn test_for_chat_falls_back_to_default() {
+        let registry = PersonaRegistry::from_json(PERSONAS).unwrap();
+        assert_eq!(registry.names(), vec!["assistant", "teacher"]);
+
+        let mut settings = ChatSettings::default();
+        assert_eq!(registry.for_chat(&settings).unwrap().name, "assistant");
+        settings.persona = Some("teacher".to_string());
+        let teacher = registry.for_chat(&settings).unwrap();
+        assert_eq!(teacher.model, Some(Model::Gpt_4));
+        assert_eq!(teacher.params.max_tokens, Some(300));
+        settings.persona = Some("removed".to_string());
+        assert_eq!(registry.for_chat(&settings).unwrap().name, "assistant");
+    }
+
+    #[test]
+    fn test_system_prompt_variables() {
+        let registry = PersonaRegistry::from_json(PERSONAS).unwrap();
+        let assistant = registry.get("assistant").unwrap();
+        assert!(assistant.uses_profile());
+        let vars = PromptVars {
+            display_name: Some("Somchai".to_string()),
+            language: Some("th".to_string()),
+            now: Some(1_700_000_000),
+        };
+        assert_eq!(
+            assistant.system_prompt(&vars),
+            "Help Somchai on 2023-11-14 in th."
+        );
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let duplicate = r#"{"personas": [
+            {"name": "a", "system_prompt": ""}, {"name": "A", "system_prompt": ""}
+        ]}"#;
+        assert!(PersonaRegistry::from_json(duplicate).is_err());
+        let unknown_default =
+            r#"{"default": "b", "personas": [{"name": "a", "system_prompt": ""}]}"#;
+        assert!(PersonaRegistry::from_json(unknown_default).is_err());
+        let hot = r#"{"personas": [{"name": "a", "system_prompt": "", "temperature": 5}]}"#;
+        assert!(PersonaRegistry::from_json(hot).is_err());
+    }
+}
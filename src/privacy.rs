@@ -0,0 +1,206 @@
+//! Privacy
+//! # Note
+//! What the bot keeps about a user or a chat, forgotten or handed over on request:
+//! - when a user blocks the bot, or the bot leaves a group or room, the conversation,
+//!   settings, uploaded document and cached profiles of that chat are forgotten. The
+//!   usage ledger is kept for accounting until `storage.usage_retention_days`,
+//! - `/export` sends a user everything stored about them, as JSON.
+//!
+//! `privacy.retention_days` caps how long turns and ledger entries are kept, in the
+//! storage and in `usage.ledger_file`, and flagged events in `moderation.review_file`.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::conversation::{ConversationStore, Turn};
+use crate::dates::format_timestamp;
+use crate::documents::DocumentStore;
+use crate::messages::{SendMessageType, TextMessage};
+use crate::objects::Profile;
+use crate::profiles::ProfileCache;
+use crate::settings::{ChatSettings, SettingsStore};
+use crate::usage::{LedgerEntry, LedgerQuery, UsageError, UsageStore};
+
+/// Characters LINE accepts in one text message.
+const MAX_TEXT_CHARS: usize = 5000;
+
+/// Everything stored about a user.
+#[derive(Debug, Serialize)]
+pub struct UserExport {
+    pub user_id: String,
+    /// `YYYY-MM-DDTHH:MM:SSZ`.
+    pub exported_at: String,
+    /// Profiles looked up from LINE, in 1:1 chats and groups.
+    pub profiles: Vec<Profile>,
+    /// The 1:1 conversation with the bot.
+    pub conversation: Vec<Turn>,
+    /// Settings of the 1:1 chat.
+    pub settings: ChatSettings,
+    /// Completions the user asked for, in any chat.
+    pub usage: Vec<LedgerEntry>,
+}
+
+impl UserExport {
+    /// The export as pretty JSON, split into as many text messages as it takes.
+    pub fn to_messages(&self) -> Vec<SendMessageType> {
+        let json = serde_json::to_string_pretty(self).unwrap_or_default();
+        let chars: Vec<char> = json.chars().collect();
+        chars
+            .chunks(MAX_TEXT_CHARS)
+            .map(|chunk| {
+                SendMessageType::TextMessage(TextMessage {
+                    text: chunk.iter().collect(),
+                    emojis: None,
+                    quote_token: None,
+                    quick_reply: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The stores that keep data about users and chats.
+pub struct Stores<'a> {
+    pub conversations: &'a ConversationStore,
+    pub settings: &'a SettingsStore,
+    pub documents: &'a DocumentStore,
+    pub profiles: &'a ProfileCache,
+    pub usage: &'a UsageStore,
+}
+
+impl Stores<'_> {
+    /// Forgets the chat of `id`, a user, group or room id.
+    pub async fn forget(&self, id: &str) {
+        self.conversations.clear(id).await;
+        self.settings.remove(id).await;
+        self.documents.remove(id);
+        self.profiles.remove(id);
+    }
+
+    /// Everything stored about the user.
+    pub async fn export(&self, user_id: &str) -> Result<UserExport, UsageError> {
+        let query = LedgerQuery {
+            user_id: Some(user_id.to_string()),
+            ..Default::default()
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Ok(UserExport {
+            user_id: user_id.to_string(),
+            exported_at: format_timestamp(now),
+            profiles: self.profiles.of_user(user_id),
+            conversation: self.conversations.turns(user_id).await,
+            settings: self.settings.get(user_id).await,
+            usage: self.usage.query(&query).await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::openai::client::Usage;
+    use crate::openai::models::{Model, Role};
+    use crate::usage::Prices;
+
+    fn profile(user_id: &str) -> Profile {
+        Profile {
+            user_id: Some(user_id.to_string()),
+            display_name: Some("Alice".to_string()),
+            picture_url: None,
+            status_message: None,
+            language: Some("th".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forget_and_export() {
+        let conversations = ConversationStore::default();
+        let settings = SettingsStore::new();
+        let documents = DocumentStore::new(Duration::from_secs(60));
+        let profiles = ProfileCache::new();
+        let usage = UsageStore::new();
+        let stores = Stores {
+            conversations: &conversations,
+            settings: &settings,
+            documents: &documents,
+            profiles: &profiles,
+            usage: &usage,
+        };
+        for chat_id in ["U1", "C1"] {
+            conversations
+                .push(chat_id, Turn::new(Role::User, "hi", None))
+                .await;
+            settings
+                .update(chat_id, |s| s.temperature = Some(0.5))
+                .await;
+            profiles.insert(chat_id, "U1", profile("U1"));
+            let tokens = Usage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                total_tokens: 15,
+            };
+            let entry = LedgerEntry::new(
+                chat_id,
+                Some("U1"),
+                Model::Gpt_4,
+                None,
+                &tokens,
+                &Prices::default(),
+            );
+            usage.record(entry).await.unwrap();
+        }
+
+        let export = stores.export("U1").await.unwrap();
+        assert_eq!(export.profiles.len(), 2);
+        assert_eq!(export.conversation.len(), 1);
+        assert_eq!(export.settings.temperature, Some(0.5));
+        assert_eq!(export.usage.len(), 2);
+
+        // The bot left the group: the user is still known in 1:1
+        stores.forget("C1").await;
+        assert!(conversations.turns("C1").await.is_empty());
+        assert_eq!(settings.get("C1").await, ChatSettings::default());
+        assert!(profiles.get("C1", "U1").is_none());
+        assert!(profiles.get("U1", "U1").is_some());
+
+        // The user blocked the bot
+        stores.forget("U1").await;
+        let export = stores.export("U1").await.unwrap();
+        assert!(export.profiles.is_empty());
+        assert!(export.conversation.is_empty());
+        assert_eq!(export.settings, ChatSettings::default());
+        // Kept for accounting
+        assert_eq!(export.usage.len(), 2);
+    }
+
+    #[test]
+    fn test_long_export_is_split() {
+        let export = UserExport {
+            user_id: "U1".to_string(),
+            exported_at: format_timestamp(0),
+            profiles: Vec::new(),
+            conversation: vec![Turn::new(Role::User, &"あ".repeat(6000), None)],
+            settings: ChatSettings::default(),
+            usage: Vec::new(),
+        };
+        let messages = export.to_messages();
+        assert_eq!(messages.len(), 2);
+        let text: String = messages
+            .iter()
+            .map(|message| match message {
+                SendMessageType::TextMessage(text) => {
+                    assert!(text.text.chars().count() <= MAX_TEXT_CHARS);
+                    text.text.clone()
+                }
+                _ => panic!("not a text message"),
+            })
+            .collect();
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["user_id"], "U1");
+    }
+}
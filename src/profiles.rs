@@ -0,0 +1,60 @@
+//! Profile cache
+//! # Note
+//! LINE profiles of the users the bot talks to, kept for `PROFILE_TTL` so that personas
+//! do not cost a profile lookup per message. A member profile is looked up in its group
+//! or room, so profiles are cached per chat and user.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::objects::Profile;
+
+/// How long a profile is used before it is looked up again.
+pub const PROFILE_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Default)]
+pub struct ProfileCache {
+    /// Profiles by chat id and user id, with when they were looked up.
+    profiles: Mutex<HashMap<(String, String), (Profile, Instant)>>,
+}
+
+impl ProfileCache {
+    pub fn new() -> ProfileCache {
+        Default::default()
+    }
+
+    /// The profile of the user in the chat, unless it has expired.
+    pub fn get(&self, chat_id: &str, user_id: &str) -> Option<Profile> {
+        let profiles = self.profiles.lock().unwrap();
+        let key = (chat_id.to_string(), user_id.to_string());
+        match profiles.get(&key) {
+            Some((profile, at)) if at.elapsed() < PROFILE_TTL => Some(profile.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, chat_id: &str, user_id: &str, profile: Profile) {
+        let mut profiles = self.profiles.lock().unwrap();
+        profiles.retain(|_, (_, at)| at.elapsed() < PROFILE_TTL);
+        let key = (chat_id.to_string(), user_id.to_string());
+        profiles.insert(key, (profile, Instant::now()));
+    }
+
+    /// Every cached profile of the user, in any chat.
+    pub fn of_user(&self, user_id: &str) -> Vec<Profile> {
+        let profiles = self.profiles.lock().unwrap();
+        profiles
+            .iter()
+            .filter(|((_, user), (_, at))| user == user_id && at.elapsed() < PROFILE_TTL)
+            .map(|(_, (profile, _))| profile.clone())
+            .collect()
+    }
+
+    /// Forgets the profiles cached in the chat of `id`, and those of the user of `id`.
+    pub fn remove(&self, id: &str) {
+        self.profiles
+            .lock()
+            .unwrap()
+            .retain(|(chat_id, user_id), _| chat_id != id && user_id != id);
+    }
+}
@@ -0,0 +1,163 @@
+//! Detectors
+//! # Note
+//! The built-in detectors match with a regular expression, then check the digits where
+//! the number has a checksum, so that an order number is not taken for a card.
+use std::fmt;
+use std::ops::Range;
+
+use regex::Regex;
+
+use super::Detector;
+
+pub const EMAIL: &str = "EMAIL";
+pub const PHONE: &str = "PHONE";
+pub const THAI_ID: &str = "THAI_ID";
+pub const CREDIT_CARD: &str = "CREDIT_CARD";
+
+/// A detector of the matches of a regular expression that pass `validate`, if any.
+pub struct RegexDetector {
+    name: String,
+    regex: Regex,
+    validate: Option<fn(&str) -> bool>,
+}
+
+impl RegexDetector {
+    pub fn new(name: &str, regex: Regex) -> RegexDetector {
+        RegexDetector {
+            name: name.to_string(),
+            regex,
+            validate: None,
+        }
+    }
+
+    /// Keeps only the matches for which `validate` is true.
+    pub fn with_validation(mut self, validate: fn(&str) -> bool) -> RegexDetector {
+        self.validate = Some(validate);
+        self
+    }
+}
+
+impl fmt::Debug for RegexDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegexDetector")
+            .field("name", &self.name)
+            .field("regex", &self.regex.as_str())
+            .finish()
+    }
+}
+
+impl Detector for RegexDetector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn find(&self, text: &str) -> Vec<Range<usize>> {
+        self.regex
+            .find_iter(text)
+            .filter(|m| self.validate.is_none_or(|validate| validate(m.as_str())))
+            .map(|m| m.range())
+            .collect()
+    }
+}
+
+/// The built-in detector called `name`, `None` if there is none.
+pub fn builtin(name: &str) -> Option<RegexDetector> {
+    let regex = |pattern: &str| Regex::new(pattern).expect("built-in pattern");
+    let detector = match name {
+        EMAIL => RegexDetector::new(
+            EMAIL,
+            regex(r"(?i)\b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b"),
+        ),
+        // Thai numbers, 0 and 8 or 9 digits, and international ones
+        PHONE => RegexDetector::new(PHONE, regex(r"(?:\+\d{1,3}[ -]?|\b0)\d(?:[ -]?\d){7,10}\b")),
+        // 13 digits, usually written 1-2345-67890-12-3
+        THAI_ID => {
+            RegexDetector::new(THAI_ID, regex(r"\b\d(?:[ -]?\d){12}\b")).with_validation(is_thai_id)
+        }
+        CREDIT_CARD => RegexDetector::new(CREDIT_CARD, regex(r"\b\d(?:[ -]?\d){12,18}\b"))
+            .with_validation(passes_luhn),
+        _ => return None,
+    };
+    Some(detector)
+}
+
+fn digits(text: &str) -> Vec<u32> {
+    text.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+/// Checks the last digit of a Thai national ID number against the first twelve.
+pub fn is_thai_id(text: &str) -> bool {
+    let digits = digits(text);
+    if digits.len() != 13 {
+        return false;
+    }
+    let sum: u32 = digits[..12]
+        .iter()
+        .enumerate()
+        .map(|(i, d)| d * (13 - i as u32))
+        .sum();
+    (11 - sum % 11) % 10 == digits[12]
+}
+
+/// The Luhn check of card numbers.
+pub fn passes_luhn(text: &str) -> bool {
+    let digits = digits(text);
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2, d * 2) {
+            (0, _) => d,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(name: &str, text: &str) -> Vec<String> {
+        builtin(name)
+            .unwrap()
+            .find(text)
+            .into_iter()
+            .map(|range| text[range].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert!(is_thai_id("1-1037-02071-81-1"));
+        assert!(!is_thai_id("1-1037-02071-81-2"));
+        assert!(passes_luhn("4111 1111 1111 1111"));
+        assert!(!passes_luhn("4111 1111 1111 1112"));
+        assert!(!passes_luhn("4111"));
+    }
+
+    #[test]
+    fn test_builtin_detectors() {
+        assert_eq!(
+            found(EMAIL, "mail somchai.j@example.co.th now"),
+            vec!["somchai.j@example.co.th"]
+        );
+        assert_eq!(
+            found(PHONE, "call 081-234-5678 or +66 81 234 5678"),
+            vec!["081-234-5678", "+66 81 234 5678"]
+        );
+        assert_eq!(
+            found(THAI_ID, "ID 1-1037-02071-81-1, not 1-1037-02071-81-2"),
+            vec!["1-1037-02071-81-1"]
+        );
+        assert_eq!(
+            found(CREDIT_CARD, "card 4111-1111-1111-1111 order 1234567890123"),
+            vec!["4111-1111-1111-1111"]
+        );
+        assert!(builtin("IBAN").is_none());
+    }
+}
@@ -0,0 +1,265 @@
+//! PII redaction
+//! # Note
+//! Phone numbers, emails, Thai national ID numbers and card numbers in user text are
+//! replaced with placeholders such as `[EMAIL_1]` before the text is sent to OpenAI.
+//! The same value gets the same placeholder within an event, so the model can still
+//! tell values apart, and with `redaction.restore` the placeholders in the answer are
+//! replaced back before it is sent to the chat.
+//!
+//! Detectors implement [`Detector`]. The built-in ones are listed in
+//! `redaction.detectors`, in order of priority when matches overlap, and
+//! `redaction.custom` adds regular expressions as `NAME=pattern`.
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use regex::Regex;
+
+use self::detectors::{builtin, RegexDetector};
+use crate::config::RedactionConfig;
+
+pub mod detectors;
+
+/// Finds one kind of personal data in text.
+pub trait Detector: Send + Sync + fmt::Debug {
+    /// Name of what is detected, used in placeholders and metrics, e.g. `EMAIL`.
+    fn name(&self) -> &str;
+
+    /// Byte ranges of the matches in `text`, in order.
+    fn find(&self, text: &str) -> Vec<Range<usize>>;
+}
+
+/// A redaction setting with an invalid value.
+#[derive(Debug, PartialEq)]
+pub struct RedactionError {
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for RedactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+impl std::error::Error for RedactionError {}
+
+/// One value replaced with a placeholder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+    /// Name of the detector that found it.
+    pub kind: String,
+    pub placeholder: String,
+    pub original: String,
+}
+
+/// The values redacted while handling one event.
+#[derive(Debug, Default)]
+pub struct Redactions {
+    replacements: Vec<Replacement>,
+}
+
+impl Redactions {
+    pub fn is_empty(&self) -> bool {
+        self.replacements.is_empty()
+    }
+
+    /// How many values of every kind were redacted.
+    pub fn counts(&self) -> HashMap<&str, u64> {
+        let mut counts = HashMap::new();
+        for replacement in &self.replacements {
+            *counts.entry(replacement.kind.as_str()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// The placeholder of `original`, a new one if it was not redacted yet.
+    fn placeholder(&mut self, kind: &str, original: &str) -> String {
+        if let Some(replacement) = self
+            .replacements
+            .iter()
+            .find(|r| r.kind == kind && r.original == original)
+        {
+            return replacement.placeholder.clone();
+        }
+        let number = self.replacements.iter().filter(|r| r.kind == kind).count() + 1;
+        let placeholder = format!("[{kind}_{number}]");
+        self.replacements.push(Replacement {
+            kind: kind.to_string(),
+            placeholder: placeholder.clone(),
+            original: original.to_string(),
+        });
+        placeholder
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Redactor {
+    detectors: Vec<Box<dyn Detector>>,
+    restore: bool,
+}
+
+impl Redactor {
+    /// The redactor of `redaction`, which redacts nothing when disabled.
+    pub fn from_config(config: &RedactionConfig) -> Result<Redactor, RedactionError> {
+        let error = |key: &str, message: String| RedactionError {
+            key: key.to_string(),
+            message,
+        };
+        let mut detectors: Vec<Box<dyn Detector>> = Vec::new();
+        for name in &config.detectors {
+            let detector = builtin(name)
+                .ok_or_else(|| error("detectors", format!("unknown detector {name:?}")))?;
+            detectors.push(Box::new(detector));
+        }
+        for custom in &config.custom {
+            let (name, pattern) = custom
+                .split_once('=')
+                .ok_or_else(|| error("custom", format!("{custom:?} is not NAME=pattern")))?;
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+                return Err(error(
+                    "custom",
+                    format!("{name:?} must be in uppercase letters and underscores"),
+                ));
+            }
+            let regex = Regex::new(pattern).map_err(|e| error("custom", e.to_string()))?;
+            detectors.push(Box::new(RegexDetector::new(name, regex)));
+        }
+        if !config.enabled {
+            detectors.clear();
+        }
+        Ok(Redactor {
+            detectors,
+            restore: config.restore,
+        })
+    }
+
+    /// `text` with every value found replaced with its placeholder in `redactions`.
+    pub fn redact(&self, text: &str, redactions: &mut Redactions) -> String {
+        // Earlier detectors win over later ones on overlapping matches
+        let mut matches: Vec<(Range<usize>, &str)> = Vec::new();
+        for detector in &self.detectors {
+            for range in detector.find(text) {
+                let overlaps = matches
+                    .iter()
+                    .any(|(taken, _)| range.start < taken.end && taken.start < range.end);
+                if !overlaps {
+                    matches.push((range, detector.name()));
+                }
+            }
+        }
+        if matches.is_empty() {
+            return text.to_string();
+        }
+        matches.sort_by_key(|(range, _)| range.start);
+
+        let mut redacted = String::with_capacity(text.len());
+        let mut end = 0;
+        for (range, kind) in matches {
+            redacted.push_str(&text[end..range.start]);
+            redacted.push_str(&redactions.placeholder(kind, &text[range.clone()]));
+            end = range.end;
+        }
+        redacted.push_str(&text[end..]);
+        redacted
+    }
+
+    /// `text` with the placeholders of `redactions` replaced back, unless
+    /// `redaction.restore` is off.
+    pub fn restore(&self, text: &str, redactions: &Redactions) -> String {
+        if !self.restore {
+            return text.to_string();
+        }
+        redactions
+            .replacements
+            .iter()
+            .fold(text.to_string(), |text, r| {
+                text.replace(&r.placeholder, &r.original)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        Redactor::from_config(&RedactionConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn test_redact_and_restore() {
+        let redactor = redactor();
+        let mut redactions = Redactions::default();
+        let text = "I'm a@b.co, or b@c.co. Again a@b.co, call 0812345678, ID 1103702071811";
+        assert_eq!(
+            redactor.redact(text, &mut redactions),
+            "I'm [EMAIL_1], or [EMAIL_2]. Again [EMAIL_1], call [PHONE_1], ID [THAI_ID_1]"
+        );
+        // The same values keep their placeholders in the rest of the event
+        assert_eq!(
+            redactor.redact("Mail a@b.co", &mut redactions),
+            "Mail [EMAIL_1]"
+        );
+        let counts = redactions.counts();
+        assert_eq!(counts["EMAIL"], 2);
+        assert_eq!(counts["THAI_ID"], 1);
+
+        assert_eq!(
+            redactor.restore("Sent to [EMAIL_2] and [PHONE_1].", &redactions),
+            "Sent to b@c.co and 0812345678."
+        );
+        let config = RedactionConfig {
+            restore: false,
+            ..Default::default()
+        };
+        let redactor = Redactor::from_config(&config).unwrap();
+        assert_eq!(
+            redactor.restore("Sent to [EMAIL_2].", &redactions),
+            "Sent to [EMAIL_2]."
+        );
+    }
+
+    #[test]
+    fn test_custom_detectors() {
+        let config = RedactionConfig {
+            custom: vec![r"EMPLOYEE_ID=\bEMP-\d{6}\b".to_string()],
+            ..Default::default()
+        };
+        let redactor = Redactor::from_config(&config).unwrap();
+        let mut redactions = Redactions::default();
+        assert_eq!(
+            redactor.redact("I'm EMP-123456", &mut redactions),
+            "I'm [EMPLOYEE_ID_1]"
+        );
+
+        let invalid = |custom: &str| RedactionConfig {
+            custom: vec![custom.to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            Redactor::from_config(&invalid("EMP-\\d+")).unwrap_err().key,
+            "custom"
+        );
+        assert_eq!(
+            Redactor::from_config(&invalid("employee=\\d+"))
+                .unwrap_err()
+                .key,
+            "custom"
+        );
+        assert_eq!(
+            Redactor::from_config(&invalid("EMPLOYEE=("))
+                .unwrap_err()
+                .key,
+            "custom"
+        );
+
+        let disabled = RedactionConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        let redactor = Redactor::from_config(&disabled).unwrap();
+        assert_eq!(redactor.redact("a@b.co", &mut redactions), "a@b.co");
+    }
+}
@@ -0,0 +1,270 @@
+//! Live configuration
+//! # Note
+//! The non-secret settings (trigger prompt, generation parameters, personas, access
+//! lists, limits, redaction, moderation, prices and greetings) can change while the bot runs.
+//! They are reloaded when the configuration, personas or welcome file changes on disk,
+//! or on `POST /admin/reload`.
+//!
+//! A reload builds a complete [`RuntimeConfig`] before swapping it in, so a webhook call
+//! sees either the old or the new settings, never a mix. An invalid file is rejected and
+//! the running settings are kept. Secrets and the server settings need a restart.
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use log::{error, info};
+
+use crate::config::{AccessConfig, Config, ConfigError, LimitsConfig};
+use crate::generation::GenerationParams;
+use crate::moderation::Moderator;
+use crate::personas::PersonaRegistry;
+use crate::redaction::Redactor;
+use crate::usage::Prices;
+use crate::welcome::WelcomeRegistry;
+
+/// Settings that can be reloaded.
+#[derive(Debug)]
+pub struct RuntimeConfig {
+    /// Text that marks a message as a question for the bot.
+    pub chat_prompt: String,
+    pub generation: GenerationParams,
+    pub personas: Option<PersonaRegistry>,
+    pub access: AccessConfig,
+    pub limits: LimitsConfig,
+    /// LINE user ids exempt from the limits.
+    pub admins: Vec<String>,
+    pub redaction: Redactor,
+    pub moderation: Moderator,
+    pub prices: Prices,
+    /// Greetings, none when `welcome.enabled` is off.
+    pub welcome: Option<WelcomeRegistry>,
+    /// Files whose changes trigger a reload.
+    watched: Vec<PathBuf>,
+}
+
+/// Enum representing the reasons a reload is rejected.
+#[derive(Debug)]
+pub enum ReloadError {
+    Config(ConfigError),
+    Personas { path: String, error: io::Error },
+    Welcome { path: String, error: io::Error },
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Config(error) => write!(f, "{error}"),
+            ReloadError::Personas { path, error } => write!(f, "{path}: {error}"),
+            ReloadError::Welcome { path, error } => write!(f, "{path}: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ReloadError {}
+
+impl From<ConfigError> for ReloadError {
+    fn from(error: ConfigError) -> Self {
+        ReloadError::Config(error)
+    }
+}
+
+impl RuntimeConfig {
+    pub fn from_config(config: &Config) -> Result<RuntimeConfig, ReloadError> {
+        let personas = match &config.personas.file {
+            Some(path) => {
+                Some(
+                    PersonaRegistry::load(path).map_err(|error| ReloadError::Personas {
+                        path: path.clone(),
+                        error,
+                    })?,
+                )
+            }
+            None => None,
+        };
+        let redaction =
+            Redactor::from_config(&config.redaction).map_err(|e| ConfigError::InvalidValue {
+                key: format!("redaction.{}", e.key),
+                message: e.message,
+            })?;
+        let moderation =
+            Moderator::from_config(&config.moderation).map_err(|e| ConfigError::InvalidValue {
+                key: format!("moderation.{}", e.key),
+                message: e.message,
+            })?;
+        let welcome = match &config.welcome.file {
+            _ if !config.welcome.enabled => None,
+            Some(path) => {
+                Some(
+                    WelcomeRegistry::load(path).map_err(|error| ReloadError::Welcome {
+                        path: path.clone(),
+                        error,
+                    })?,
+                )
+            }
+            None => Some(WelcomeRegistry::default()),
+        };
+        let watched = [&config.file, &config.personas.file, &config.welcome.file]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::from)
+            .collect();
+        Ok(RuntimeConfig {
+            chat_prompt: config.line.chat_prompt.clone(),
+            generation: config.generation.clone(),
+            personas,
+            access: config.access.clone(),
+            limits: config.limits.clone(),
+            admins: config.admin.users.clone(),
+            redaction,
+            moderation,
+            prices: Prices::from_config(&config.usage),
+            welcome,
+            watched,
+        })
+    }
+}
+
+type Loader = Box<dyn Fn() -> Result<Config, ConfigError> + Send + Sync>;
+
+/// The current [`RuntimeConfig`], swapped atomically on reload.
+pub struct LiveConfig {
+    current: RwLock<Arc<RuntimeConfig>>,
+    /// Reads the configuration again.
+    loader: Loader,
+    /// Modification times of the watched files when last checked.
+    modified: Mutex<Vec<Option<SystemTime>>>,
+    /// Held during a reload, so that two reloads do not race.
+    reloading: Mutex<()>,
+}
+
+impl LiveConfig {
+    pub fn new<F>(runtime: RuntimeConfig, loader: F) -> LiveConfig
+    where
+        F: Fn() -> Result<Config, ConfigError> + Send + Sync + 'static,
+    {
+        let modified = modification_times(&runtime.watched);
+        LiveConfig {
+            current: RwLock::new(Arc::new(runtime)),
+            loader: Box::new(loader),
+            modified: Mutex::new(modified),
+            reloading: Mutex::new(()),
+        }
+    }
+
+    /// The settings in effect. Keep the returned value for a whole event so that it
+    /// is handled with one consistent configuration.
+    pub fn get(&self) -> Arc<RuntimeConfig> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// Reads and validates the configuration, and swaps it in if it is valid.
+    pub fn reload(&self) -> Result<Arc<RuntimeConfig>, ReloadError> {
+        let _reloading = self.reloading.lock().unwrap();
+        let config = (self.loader)()?;
+        config.validate()?;
+        let runtime = Arc::new(RuntimeConfig::from_config(&config)?);
+        *self.modified.lock().unwrap() = modification_times(&runtime.watched);
+        *self.current.write().unwrap() = Arc::clone(&runtime);
+        Ok(runtime)
+    }
+
+    /// Reloads if a watched file changed since the last check, `None` if none did.
+    pub fn reload_if_changed(&self) -> Option<Result<Arc<RuntimeConfig>, ReloadError>> {
+        let times = modification_times(&self.get().watched);
+        {
+            let mut modified = self.modified.lock().unwrap();
+            if *modified == times {
+                return None;
+            }
+            // Remembered even if the change is rejected, so that it is reported once
+            *modified = times;
+        }
+        Some(self.reload())
+    }
+
+    /// Checks the watched files every `interval`, forever.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            let live = Arc::clone(&self);
+            match tokio::task::spawn_blocking(move || live.reload_if_changed()).await {
+                Ok(Some(Ok(_))) => info!("Reloaded configuration"),
+                Ok(Some(Err(e))) => error!("Rejected configuration change: {}", e),
+                Ok(None) => {}
+                Err(e) => error!("Error: {}", e),
+            }
+        }
+    }
+}
+
+fn modification_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::write(path, contents).unwrap();
+        // Make sure the modification time moves even on coarse file systems
+        let later = SystemTime::now() + Duration::from_secs(1);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+    }
+
+    fn live_config(path: &Path) -> LiveConfig {
+        let path = path.to_str().unwrap().to_string();
+        let loader = move || {
+            let args = vec!["--config".to_string(), path.clone()];
+            let vars = [
+                ("LINE_CHANNEL_SECRET", "secret"),
+                ("LINE_CHANNEL_ACCESS_TOKEN", "token"),
+                ("CHATGPT_API_KEY", "key"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+            Config::resolve(&args, vars)
+        };
+        let runtime = RuntimeConfig::from_config(&loader().unwrap()).unwrap();
+        LiveConfig::new(runtime, loader)
+    }
+
+    #[test]
+    fn test_reload_swaps_valid_and_rejects_invalid() {
+        let path =
+            std::env::temp_dir().join(format!("line_botx_reload_{}.toml", std::process::id()));
+        write(&path, "[line]\nchat_prompt = \"Nick:>\"\n");
+        let live = live_config(&path);
+        let before = live.get();
+        assert_eq!(before.chat_prompt, "Nick:>");
+        assert!(live.reload_if_changed().is_none());
+
+        write(
+            &path,
+            "[line]\nchat_prompt = \"Bot:\"\n[access]\nallowed_users = [\"U1\"]\n",
+        );
+        assert!(live.reload_if_changed().unwrap().is_ok());
+        assert_eq!(live.get().chat_prompt, "Bot:");
+        assert_eq!(live.get().access.allowed_users, vec!["U1"]);
+        // Holders of the old settings keep them
+        assert_eq!(before.chat_prompt, "Nick:>");
+
+        write(&path, "[generation]\ntemperature = 9\n");
+        assert!(live.reload_if_changed().unwrap().is_err());
+        assert_eq!(live.get().chat_prompt, "Bot:");
+        assert!(live.reload_if_changed().is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
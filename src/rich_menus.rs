@@ -0,0 +1,311 @@
+//! Rich menus per bot mode
+//! # Note
+//! Every `<mode>.json` file of the rich menu folder defines the menu of one bot mode
+//! (chat, translate, draw, ...), with an optional `<mode>.png` or `<mode>.jpg` image next to it.
+//! The menus are created on LINE at startup and linked to a user when they switch mode.
+//! A menu whose definition changed is created again, and the old one deleted.
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{error, info};
+use reqwest::Error;
+
+use crate::bot::LineBot;
+use crate::objects::RichMenu;
+
+/// Mode whose menu is set as the default rich menu.
+pub const DEFAULT_MODE: &str = "chat";
+
+/// Rich menu definition of one mode.
+#[derive(Debug)]
+pub struct ModeMenu {
+    pub mode: String,
+    pub rich_menu: RichMenu,
+    pub image: Option<PathBuf>,
+}
+
+/// Rich menus by mode.
+#[derive(Debug, Default)]
+pub struct RichMenuRegistry {
+    menus: Vec<ModeMenu>,
+    rich_menu_ids: HashMap<String, String>,
+}
+
+impl RichMenuRegistry {
+    /// Reads the menu definitions of `dir`.
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<RichMenuRegistry> {
+        let dir = dir.as_ref();
+        let mut menus = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(mode) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let file = std::fs::File::open(&path)?;
+            let rich_menu: RichMenu =
+                serde_json::from_reader(io::BufReader::new(file)).map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {e}"))
+                })?;
+            let image = ["png", "jpg", "jpeg"]
+                .iter()
+                .map(|ext| path.with_extension(ext))
+                .find(|p| p.exists());
+            menus.push(ModeMenu {
+                mode: mode.to_string(),
+                rich_menu,
+                image,
+            });
+        }
+        menus.sort_by(|a, b| a.mode.cmp(&b.mode));
+        Ok(RichMenuRegistry {
+            menus,
+            ..Default::default()
+        })
+    }
+
+    pub fn modes(&self) -> Vec<&str> {
+        self.menus.iter().map(|m| m.mode.as_str()).collect()
+    }
+
+    /// Creates the menus missing on LINE, points a `<mode>` alias at each of them
+    /// and sets the `chat` menu as default.
+    ///
+    /// A menu already on LINE is reused when its whole definition is the same. The
+    /// other menus of the same name are deleted once nothing points at them.
+    pub async fn sync(&mut self, bot: &LineBot) -> Result<(), Error> {
+        let existing = bot.get_rich_menu_list().await?;
+        let aliases: HashMap<String, String> = bot
+            .get_rich_menu_alias_list()
+            .await?
+            .into_iter()
+            .map(|a| (a.rich_menu_alias_id, a.rich_menu_id))
+            .collect();
+
+        let mut stale = Vec::new();
+        for menu in &self.menus {
+            let same_name = existing
+                .iter()
+                .filter(|r| r.rich_menu.name == menu.rich_menu.name);
+            let (same, changed): (Vec<_>, Vec<_>) =
+                same_name.partition(|r| same_definition(&r.rich_menu, &menu.rich_menu));
+            stale.extend(changed.into_iter().map(|r| r.rich_menu_id.as_str()));
+            stale.extend(same.iter().skip(1).map(|r| r.rich_menu_id.as_str()));
+            let rich_menu_id = match same.first() {
+                Some(r) => r.rich_menu_id.clone(),
+                None => {
+                    let id = bot.create_rich_menu(&menu.rich_menu).await?;
+                    if let Some(image) = &menu.image {
+                        upload_image(bot, &id, image).await?;
+                    }
+                    info!("Created rich menu {} for mode {}", id, menu.mode);
+                    id
+                }
+            };
+            match aliases.get(&menu.mode) {
+                Some(id) if *id == rich_menu_id => {}
+                Some(_) => {
+                    bot.update_rich_menu_alias(&menu.mode, &rich_menu_id)
+                        .await?
+                        .error_for_status()?;
+                }
+                None => {
+                    bot.create_rich_menu_alias(&menu.mode, &rich_menu_id)
+                        .await?
+                        .error_for_status()?;
+                }
+            }
+            self.rich_menu_ids.insert(menu.mode.clone(), rich_menu_id);
+        }
+
+        if let Some(id) = self.rich_menu_ids.get(DEFAULT_MODE) {
+            bot.set_default_rich_menu(id).await?.error_for_status()?;
+        }
+        for id in stale {
+            bot.delete_rich_menu(id).await?.error_for_status()?;
+            info!("Deleted outdated rich menu {}", id);
+        }
+        Ok(())
+    }
+
+    pub fn rich_menu_id(&self, mode: &str) -> Option<&str> {
+        self.rich_menu_ids.get(mode).map(String::as_str)
+    }
+
+    /// Links the menu of `mode` to the user. Returns `false` for an unknown mode.
+    pub async fn switch_mode(
+        &self,
+        bot: &LineBot,
+        user_id: &str,
+        mode: &str,
+    ) -> Result<bool, Error> {
+        let Some(rich_menu_id) = self.rich_menu_id(mode) else {
+            return Ok(false);
+        };
+        bot.link_rich_menu_to_user(user_id, rich_menu_id)
+            .await?
+            .error_for_status()?;
+        Ok(true)
+    }
+}
+
+async fn upload_image(bot: &LineBot, rich_menu_id: &str, image: &Path) -> Result<(), Error> {
+    let content_type = match image.extension().and_then(|e| e.to_str()) {
+        Some("png") => "image/png",
+        _ => "image/jpeg",
+    };
+    // A missing image only leaves the menu blank, it should not stop the bot
+    match std::fs::read(image) {
+        Ok(bytes) => {
+            bot.upload_rich_menu_image(rich_menu_id, content_type, bytes)
+                .await?
+                .error_for_status()?;
+        }
+        Err(e) => error!("Failed reading {:?}: {}", image, e),
+    }
+    Ok(())
+}
+
+/// Whether two menus have the same size, name, chat bar text and areas.
+fn same_definition(a: &RichMenu, b: &RichMenu) -> bool {
+    match (serde_json::to_value(a), serde_json::to_value(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The mode requested by postback data like `mode=translate`.
+pub fn parse_mode_postback(data: &str) -> Option<&str> {
+    data.split('&')
+        .find_map(|pair| pair.strip_prefix("mode="))
+        .filter(|mode| !mode.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::json;
+
+    use super::*;
+
+    fn menu(name: &str, chat_bar_text: &str) -> RichMenu {
+        serde_json::from_value(json!({
+            "size": {"width": 2500, "height": 843},
+            "selected": true,
+            "name": name,
+            "chatBarText": chat_bar_text,
+            "areas": []
+        }))
+        .unwrap()
+    }
+
+    /// Requests received by the mocked LINE API, as `<method> <path>`.
+    static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Mocks a LINE API with an outdated `chat` menu and an up to date `translate` one.
+    fn mock_line() -> String {
+        async fn list() -> HttpResponse {
+            let mut old = serde_json::to_value(menu("chat", "Menu v1")).unwrap();
+            old["richMenuId"] = json!("old");
+            let mut kept = serde_json::to_value(menu("translate", "Menu")).unwrap();
+            kept["richMenuId"] = json!("kept");
+            HttpResponse::Ok().json(json!({"richmenus": [old, kept]}))
+        }
+        async fn aliases() -> HttpResponse {
+            HttpResponse::Ok().json(json!({"aliases": [
+                {"richMenuAliasId": "chat", "richMenuId": "old"},
+                {"richMenuAliasId": "translate", "richMenuId": "kept"}
+            ]}))
+        }
+        async fn other(request: HttpRequest) -> HttpResponse {
+            let call = format!("{} {}", request.method(), request.path());
+            CALLS.lock().unwrap().push(call);
+            HttpResponse::Ok().json(json!({"richMenuId": "new"}))
+        }
+        let server = HttpServer::new(|| {
+            App::new()
+                .route("/v2/bot/richmenu/list", web::get().to(list))
+                .route("/v2/bot/richmenu/alias/list", web::get().to(aliases))
+                .default_service(web::to(other))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{address}/v2/bot")
+    }
+
+    #[actix_web::test]
+    async fn test_changed_menu_is_replaced() {
+        let url = mock_line();
+        let bot = LineBot::with_base_urls("secret", "token", &url, &url);
+        let mut registry = RichMenuRegistry {
+            menus: vec![
+                ModeMenu {
+                    mode: "chat".to_string(),
+                    rich_menu: menu("chat", "Menu v2"),
+                    image: None,
+                },
+                ModeMenu {
+                    mode: "translate".to_string(),
+                    rich_menu: menu("translate", "Menu"),
+                    image: None,
+                },
+            ],
+            ..Default::default()
+        };
+        registry.sync(&bot).await.unwrap();
+
+        assert_eq!(registry.rich_menu_id("chat"), Some("new"));
+        assert_eq!(registry.rich_menu_id("translate"), Some("kept"));
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            vec![
+                "POST /v2/bot/richmenu",
+                "POST /v2/bot/richmenu/alias/chat",
+                "POST /v2/bot/user/all/richmenu/new",
+                "DELETE /v2/bot/richmenu/old",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_mode_postback() {
+        assert_eq!(parse_mode_postback("mode=translate"), Some("translate"));
+        assert_eq!(parse_mode_postback("action=switch&mode=draw"), Some("draw"));
+        assert_eq!(parse_mode_postback("mode="), None);
+        assert_eq!(parse_mode_postback("action=buy"), None);
+    }
+
+    #[test]
+    fn test_load_definitions() {
+        let dir = std::env::temp_dir().join(format!("rich-menus-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let menu = r#"{
+            "size": {"width": 2500, "height": 843},
+            "selected": true,
+            "name": "NAME",
+            "chatBarText": "Menu",
+            "areas": []
+        }"#;
+        for mode in ["translate", "chat"] {
+            std::fs::write(dir.join(format!("{mode}.json")), menu.replace("NAME", mode)).unwrap();
+        }
+        std::fs::write(dir.join("chat.png"), b"png").unwrap();
+
+        let registry = RichMenuRegistry::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(registry.modes(), vec!["chat", "translate"]);
+        assert!(registry.menus[0].image.is_some());
+        assert!(registry.menus[1].image.is_none());
+        assert_eq!(registry.menus[1].rich_menu.name, "translate");
+        assert_eq!(registry.rich_menu_id("chat"), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Per-chat settings
+//! # Note
+//! Settings are keyed by source id, so a user, a group and a room each have their own.
+//! A storage failure is logged, and the chat falls back to the default settings.
+use std::sync::Arc;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::openai::models::Model;
+use crate::storage::{MemoryStorage, Storage};
+
+/// Model used when a chat has not chosen one.
+pub const DEFAULT_MODEL: Model = Model::Gpt3_5Turbo;
+
+/// Settings of one chat.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatSettings {
+    /// Whether the bot answers in this chat. Only groups and rooms can turn it off.
+    pub enabled: bool,
+    /// Model chosen with `/model`, the default model if unset.
+    pub model: Option<Model>,
+    /// Temperature chosen with `/temp`.
+    pub temperature: Option<f32>,
+    /// Persona chosen with `/persona`.
+    pub persona: Option<String>,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            model: None,
+            temperature: None,
+            persona: None,
+        }
+    }
+}
+
+impl ChatSettings {
+    pub fn model(&self) -> Model {
+        self.model.unwrap_or(DEFAULT_MODEL)
+    }
+}
+
+/// Settings of every chat, defaults for chats never configured.
+pub struct SettingsStore {
+    storage: Arc<dyn Storage>,
+    /// Held from reading to writing back the settings of an update.
+    update: Mutex<()>,
+}
+
+impl SettingsStore {
+    /// A store kept in memory.
+    pub fn new() -> SettingsStore {
+        SettingsStore::with_storage(Arc::new(MemoryStorage::new()))
+    }
+
+    pub fn with_storage(storage: Arc<dyn Storage>) -> SettingsStore {
+        SettingsStore {
+            storage,
+            update: Mutex::new(()),
+        }
+    }
+
+    pub async fn get(&self, chat_id: &str) -> ChatSettings {
+        match self.storage.settings(chat_id).await {
+            Ok(settings) => settings.unwrap_or_default(),
+            Err(e) => {
+                error!("Failed reading settings of {}: {}", chat_id, e);
+                ChatSettings::default()
+            }
+        }
+    }
+
+    /// Applies `update` to the settings of the chat and returns the result.
+    pub async fn update<F>(&self, chat_id: &str, update: F) -> ChatSettings
+    where
+        F: FnOnce(&mut ChatSettings),
+    {
+        let _update = self.update.lock().await;
+        let mut settings = self.get(chat_id).await;
+        update(&mut settings);
+        if let Err(e) = self.storage.save_settings(chat_id, &settings).await {
+            error!("Failed storing settings of {}: {}", chat_id, e);
+        }
+        settings
+    }
+
+    pub async fn remove(&self, chat_id: &str) {
+        if let Err(e) = self.storage.remove_settings(chat_id).await {
+            error!("Failed removing settings of {}: {}", chat_id, e);
+        }
+    }
+}
+
+impl Default for SettingsStore {
+    fn default() -> Self {
+        SettingsStore::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_defaults_and_update() {
+        let store = SettingsStore::new();
+        assert!(store.get("C1").await.enabled);
+
+        let updated = store.update("C1", |s| s.enabled = false).await;
+        assert!(!updated.enabled);
+        assert!(!store.get("C1").await.enabled);
+        assert!(store.get("C2").await.enabled);
+
+        store.remove("C1").await;
+        assert_eq!(store.get("C1").await, ChatSettings::default());
+    }
+}
@@ -0,0 +1,498 @@
+//! Shared state
+//! # Note
+//! With `redis.url` set, the replicas behind a load balancer keep in Redis, or any
+//! server speaking its protocol, what each process would otherwise keep for itself:
+//! - the ids of the webhook events already handled, see `dedup`,
+//! - the rate-limit buckets and daily quotas of every chat, see `limits`,
+//! - the conversation history, see `storage::shared`.
+//!
+//! Commands are sent asynchronously over one multiplexed connection, which reconnects
+//! by itself, and are bounded by `redis.timeout_ms`. A failed command is logged by its
+//! caller, which falls back to the in-process store. Once Redis could not be reached,
+//! it is left alone for `RECONNECT_DELAY`, so that an outage does not slow down every
+//! event.
+//!
+//! The buckets are taken from by a Lua script, so that the replicas taking from the same
+//! bucket never both see it full.
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use redis::aio::{ConnectionLike, ConnectionManager, ConnectionManagerConfig};
+use redis::{
+    Client, Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, RedisResult, Script, Value,
+};
+
+use crate::config::RedisConfig;
+use crate::limits::DailyUsage;
+
+/// How long Redis is not tried after it could not be reached.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+/// How long the daily counters are kept, longer than a day whatever the time zone.
+const DAILY_TTL: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// Takes a question from every bucket of `KEYS` if all of them have one, and returns 0.
+/// Otherwise takes nothing, and returns how many milliseconds until they all have one.
+/// A bucket is saved as its questions and the milliseconds it was last updated at.
+///
+/// `ARGV`: capacity, questions refilled per second, now and time to live in milliseconds.
+const TAKE_QUESTIONS: &str = r"
+local capacity = tonumber(ARGV[1])
+local per_second = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local buckets = {}
+local wait = 0
+for i, key in ipairs(KEYS) do
+    local questions, updated = capacity, now
+    local saved = redis.call('GET', key)
+    if saved then
+        local q, u = string.match(saved, '^(%S+) (%S+)$')
+        questions, updated = tonumber(q) or capacity, tonumber(u) or now
+    end
+    questions = math.min(capacity, questions + math.max(0, now - updated) / 1000 * per_second)
+    if questions < 1 then
+        wait = math.max(wait, math.ceil((1 - questions) / per_second * 1000))
+    end
+    buckets[i] = questions
+end
+if wait > 0 then
+    return wait
+end
+for i, key in ipairs(KEYS) do
+    redis.call('SET', key, buckets[i] - 1 .. ' ' .. now, 'PX', ARGV[4])
+end
+return 0
+";
+
+/// The connection to Redis, or to a scripted stand-in in tests.
+#[derive(Clone)]
+// The stand-in only exists in tests
+#[cfg_attr(test, allow(clippy::large_enum_variant))]
+pub enum Connection {
+    Manager(ConnectionManager),
+    #[cfg(test)]
+    Mock(redis_test::MockRedisConnection),
+}
+
+impl ConnectionLike for Connection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Connection::Manager(connection) => connection.req_packed_command(cmd),
+            #[cfg(test)]
+            Connection::Mock(connection) => connection.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            Connection::Manager(connection) => connection.req_packed_commands(cmd, offset, count),
+            #[cfg(test)]
+            Connection::Mock(connection) => connection.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Connection::Manager(connection) => connection.get_db(),
+            #[cfg(test)]
+            Connection::Mock(connection) => connection.get_db(),
+        }
+    }
+}
+
+pub struct SharedState {
+    client: Client,
+    key_prefix: String,
+    timeout: Duration,
+    /// Opened by the first command.
+    connection: tokio::sync::Mutex<Option<Connection>>,
+    /// Until when Redis is left alone, after it could not be reached.
+    unreachable_until: Mutex<Option<Instant>>,
+    take_questions: Script,
+}
+
+impl SharedState {
+    /// The shared state of `redis.url`, `None` without one. Nothing is connected until
+    /// the first command.
+    pub fn open(config: &RedisConfig) -> RedisResult<Option<SharedState>> {
+        let Some(url) = &config.url else {
+            return Ok(None);
+        };
+        Ok(Some(SharedState {
+            client: Client::open(url.as_str())?,
+            key_prefix: config.key_prefix.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            connection: tokio::sync::Mutex::new(None),
+            unreachable_until: Mutex::new(None),
+            take_questions: Script::new(TAKE_QUESTIONS),
+        }))
+    }
+
+    /// The key of `id` among the keys of `kind`.
+    pub fn key(&self, kind: &str, id: &str) -> String {
+        format!("{}{}:{}", self.key_prefix, kind, id)
+    }
+
+    /// Runs `f` on the connection, opened first if it is not yet.
+    pub async fn with_connection<T, F, Fut>(&self, f: F) -> RedisResult<T>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        let result = match self.connection().await {
+            Ok(connection) => f(connection).await,
+            Err(e) => Err(e),
+        };
+        match &result {
+            Err(e) if e.is_io_error() || e.is_connection_dropped() || e.is_timeout() => {
+                *self.unreachable_until.lock().unwrap() = Some(Instant::now() + RECONNECT_DELAY);
+            }
+            _ => {}
+        }
+        result
+    }
+
+    async fn connection(&self) -> RedisResult<Connection> {
+        let unreachable_until = *self.unreachable_until.lock().unwrap();
+        if unreachable_until.is_some_and(|until| Instant::now() < until) {
+            return Err(RedisError::from((
+                ErrorKind::IoError,
+                "server unreachable, retrying later",
+            )));
+        }
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = &*connection {
+            return Ok(connection.clone());
+        }
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(self.timeout)
+            .set_response_timeout(self.timeout)
+            .set_number_of_retries(1);
+        let manager = ConnectionManager::new_with_config(self.client.clone(), config).await?;
+        Ok(connection.insert(Connection::Manager(manager)).clone())
+    }
+
+    /// Remembers the event id for `ttl`. `false` if it already was remembered.
+    pub async fn first_delivery(&self, event_id: &str, ttl: Duration) -> RedisResult<bool> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(self.key("event", event_id))
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64);
+        let set: Option<String> = self
+            .with_connection(|mut connection| async move { cmd.query_async(&mut connection).await })
+            .await?;
+        Ok(set.is_some())
+    }
+
+    /// Takes a question from the bucket of every key if all of them have one, like
+    /// `Limiter::check` does in process. Otherwise nothing is taken, and the result is
+    /// how long until they all have one.
+    pub async fn take_questions(
+        &self,
+        keys: &[&str],
+        capacity: f64,
+        per_second: f64,
+    ) -> RedisResult<Option<Duration>> {
+        self.take_questions_at(keys, capacity, per_second, now_millis())
+            .await
+    }
+
+    async fn take_questions_at(
+        &self,
+        keys: &[&str],
+        capacity: f64,
+        per_second: f64,
+        now: u64,
+    ) -> RedisResult<Option<Duration>> {
+        // A bucket left alone that long is full, as if it did not exist
+        let ttl = (capacity / per_second * 1000.0).ceil() as u64 + 1000;
+        let mut invocation = self.take_questions.prepare_invoke();
+        for key in keys {
+            invocation.key(self.key("bucket", key));
+        }
+        invocation.arg(capacity).arg(per_second).arg(now).arg(ttl);
+        let wait: u64 = self
+            .with_connection(|mut connection| async move {
+                invocation.invoke_async(&mut connection).await
+            })
+            .await?;
+        Ok((wait > 0).then(|| Duration::from_millis(wait)))
+    }
+
+    /// Adds to the counters of every key during `day`, days since the epoch.
+    pub async fn add_daily_usage(
+        &self,
+        keys: &[&str],
+        day: u64,
+        tokens: u64,
+        cost_usd: f64,
+    ) -> RedisResult<()> {
+        let ttl = DAILY_TTL.as_millis() as u64;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for key in keys {
+            let (tokens_key, cost_key) = self.daily_keys(key, day);
+            pipe.cmd("INCRBY").arg(&tokens_key).arg(tokens).ignore();
+            pipe.cmd("PEXPIRE").arg(&tokens_key).arg(ttl).ignore();
+            pipe.cmd("INCRBYFLOAT")
+                .arg(&cost_key)
+                .arg(cost_usd)
+                .ignore();
+            pipe.cmd("PEXPIRE").arg(&cost_key).arg(ttl).ignore();
+        }
+        self.with_connection(|mut connection| async move {
+            pipe.query_async::<()>(&mut connection).await
+        })
+        .await
+    }
+
+    /// The usage of every key during `day`, in the order of `keys`.
+    pub async fn daily_usage(&self, keys: &[&str], day: u64) -> RedisResult<Vec<DailyUsage>> {
+        let mut cmd = redis::cmd("MGET");
+        for key in keys {
+            let (tokens_key, cost_key) = self.daily_keys(key, day);
+            cmd.arg(tokens_key).arg(cost_key);
+        }
+        let values: Vec<Option<f64>> = self
+            .with_connection(|mut connection| async move { cmd.query_async(&mut connection).await })
+            .await?;
+        Ok(values
+            .chunks(2)
+            .map(|counters| DailyUsage {
+                tokens: counters[0].unwrap_or_default() as u64,
+                cost_usd: counters[1].unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// The keys of the tokens and the cost of `key` during `day`.
+    fn daily_keys(&self, key: &str, day: u64) -> (String, String) {
+        let id = format!("{day}:{key}");
+        (self.key("tokens", &id), self.key("cost", &id))
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub mod testing {
+    //! Shared state answering a script of commands, with `redis-test`.
+    pub use redis_test::MockCmd;
+
+    use super::*;
+
+    /// Shared state expecting `commands`, in order, and failing any other command.
+    pub fn mock(commands: Vec<MockCmd>) -> SharedState {
+        let shared = SharedState::open(&RedisConfig {
+            url: Some("redis://127.0.0.1:1".to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        let connection = Connection::Mock(redis_test::MockRedisConnection::new(commands));
+        SharedState {
+            connection: tokio::sync::Mutex::new(Some(connection)),
+            ..shared
+        }
+    }
+
+    /// The `SET` of `first_delivery`.
+    pub fn first_delivery_cmd(event_id: &str, ttl: Duration) -> Cmd {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(format!("line_botx:event:{event_id}"))
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64);
+        cmd
+    }
+
+    /// The `EVALSHA` of `take_questions`, without its arguments.
+    pub fn take_questions_cmd(keys: &[&str]) -> Cmd {
+        let mut cmd = redis::cmd("EVALSHA");
+        cmd.arg(Script::new(TAKE_QUESTIONS).get_hash())
+            .arg(keys.len());
+        for key in keys {
+            cmd.arg(format!("line_botx:bucket:{key}"));
+        }
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use redis_test::IntoRedisValue;
+
+    use super::testing::*;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_event_is_delivered_once() {
+        let ttl = Duration::from_secs(60);
+        let shared = mock(vec![
+            MockCmd::new(first_delivery_cmd("01H", ttl), Ok("OK")),
+            MockCmd::new(first_delivery_cmd("01H", ttl), Ok(Value::Nil)),
+        ]);
+        assert!(shared.first_delivery("01H", ttl).await.unwrap());
+        assert!(!shared.first_delivery("01H", ttl).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_buckets_are_taken_by_script() {
+        let mut take = take_questions_cmd(&["C1", "U2"]);
+        take.arg(2.0).arg(1.0).arg(5_000).arg(3_000);
+        let mut refused = take_questions_cmd(&["U1"]);
+        refused.arg(2.0).arg(1.0).arg(5_000).arg(3_000);
+        // Not loaded yet, then loaded and run
+        let not_loaded = RedisError::from((ErrorKind::NoScriptError, "NOSCRIPT"));
+        let shared = mock(vec![
+            MockCmd::new(take.clone(), Err::<Value, _>(not_loaded)),
+            MockCmd::new(
+                redis::cmd("SCRIPT").arg("LOAD").arg(TAKE_QUESTIONS),
+                Ok("sha"),
+            ),
+            MockCmd::new(take, Ok(0)),
+            MockCmd::new(refused, Ok(250)),
+        ]);
+        let taken = shared
+            .take_questions_at(&["C1", "U2"], 2.0, 1.0, 5_000)
+            .await;
+        assert_eq!(taken.unwrap(), None);
+        let refused = shared.take_questions_at(&["U1"], 2.0, 1.0, 5_000).await;
+        assert_eq!(refused.unwrap(), Some(Duration::from_millis(250)));
+    }
+
+    #[tokio::test]
+    async fn test_daily_usage_is_counted_per_day() {
+        let mut add = redis::pipe();
+        add.atomic()
+            .cmd("INCRBY")
+            .arg("line_botx:tokens:3:U1")
+            .arg(150)
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg("line_botx:tokens:3:U1")
+            .arg(DAILY_TTL.as_millis() as u64)
+            .ignore()
+            .cmd("INCRBYFLOAT")
+            .arg("line_botx:cost:3:U1")
+            .arg(0.25)
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg("line_botx:cost:3:U1")
+            .arg(DAILY_TTL.as_millis() as u64)
+            .ignore();
+        let added = Value::Array(vec![
+            Value::Int(150),
+            Value::Int(1),
+            "0.25".into_redis_value(),
+            Value::Int(1),
+        ]);
+        let mut get = redis::cmd("MGET");
+        get.arg("line_botx:tokens:3:U1")
+            .arg("line_botx:cost:3:U1")
+            .arg("line_botx:tokens:3:C1")
+            .arg("line_botx:cost:3:C1");
+        let counters = Value::Array(vec![
+            "150".into_redis_value(),
+            "0.25".into_redis_value(),
+            Value::Nil,
+            Value::Nil,
+        ]);
+        let shared = mock(vec![
+            MockCmd::with_values(add, Ok(vec![added])),
+            MockCmd::new(get, Ok(counters)),
+        ]);
+        shared.add_daily_usage(&["U1"], 3, 150, 0.25).await.unwrap();
+        let usage = shared.daily_usage(&["U1", "C1"], 3).await.unwrap();
+        assert_eq!(
+            usage,
+            vec![
+                DailyUsage {
+                    tokens: 150,
+                    cost_usd: 0.25
+                },
+                DailyUsage::default()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_left_alone() {
+        // Nothing listens on a port just released
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = RedisConfig {
+            url: Some(format!("redis://127.0.0.1:{port}")),
+            ..Default::default()
+        };
+        let shared = SharedState::open(&config).unwrap().unwrap();
+        let ttl = Duration::from_secs(60);
+        assert!(shared.first_delivery("01H", ttl).await.is_err());
+        assert!(shared.unreachable_until.lock().unwrap().is_some());
+        let err = shared.first_delivery("01H", ttl).await.unwrap_err();
+        assert!(err.to_string().contains("retrying later"));
+
+        assert!(SharedState::open(&RedisConfig::default())
+            .unwrap()
+            .is_none());
+    }
+
+    /// Runs against the server of `REDIS_TEST_URL`, or a local `redis-server`:
+    /// `cargo test -- --ignored real_server`.
+    #[tokio::test]
+    #[ignore = "needs a Redis server"]
+    async fn test_script_and_delivery_on_real_server() {
+        let url = std::env::var("REDIS_TEST_URL")
+            .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        // Fresh keys on every run
+        let config = RedisConfig {
+            url: Some(url),
+            key_prefix: format!("line_botx_test:{}:", rand::random::<u64>()),
+            ..Default::default()
+        };
+        let shared = SharedState::open(&config).unwrap().unwrap();
+
+        let ttl = Duration::from_secs(60);
+        assert!(shared.first_delivery("01H", ttl).await.unwrap());
+        assert!(!shared.first_delivery("01H", ttl).await.unwrap());
+        assert!(shared.first_delivery("01J", ttl).await.unwrap());
+
+        // Two questions, then one a second
+        let now = now_millis();
+        for _ in 0..2 {
+            let taken = shared.take_questions_at(&["C1", "U2"], 2.0, 1.0, now).await;
+            assert_eq!(taken.unwrap(), None);
+        }
+        let refused = shared.take_questions_at(&["C1", "U3"], 2.0, 1.0, now).await;
+        assert_eq!(refused.unwrap(), Some(Duration::from_millis(1000)));
+        // Nothing was taken from the bucket that had questions left
+        for _ in 0..2 {
+            let taken = shared.take_questions_at(&["U3"], 2.0, 1.0, now).await;
+            assert_eq!(taken.unwrap(), None);
+        }
+        let refilled = shared.take_questions_at(&["C1"], 2.0, 1.0, now + 500).await;
+        assert_eq!(refilled.unwrap(), Some(Duration::from_millis(500)));
+        let refilled = shared
+            .take_questions_at(&["C1"], 2.0, 1.0, now + 1000)
+            .await;
+        assert_eq!(refilled.unwrap(), None);
+    }
+}
@@ -0,0 +1,45 @@
+//! Application state
+//! # Note
+//! Everything the handlers share, built once at startup. The LINE and OpenAI clients
+//! keep their connection pools across requests, and the stores only lock for the
+//! length of a lookup, so webhook calls never wait on each other.
+use std::sync::Arc;
+
+use crate::bot::LineBot;
+use crate::config::AdminConfig;
+use crate::conversation::ConversationStore;
+use crate::dedup::Deduplicator;
+use crate::documents::DocumentStore;
+use crate::knowledge::KnowledgeBase;
+use crate::limits::Limiter;
+use crate::metrics::BotMetrics;
+use crate::openai::client::ChatGPTClient;
+use crate::openai::moderations::ModerationsClient;
+use crate::profiles::ProfileCache;
+use crate::reload::LiveConfig;
+use crate::rich_menus::RichMenuRegistry;
+use crate::settings::SettingsStore;
+use crate::usage::UsageStore;
+
+pub struct AppState {
+    pub bot: LineBot,
+    pub chat_gpt: ChatGPTClient,
+    pub moderations: ModerationsClient,
+    /// User id of the bot itself, used to detect @mentions in groups.
+    pub bot_user_id: Option<String>,
+    /// Settings that can be reloaded while running.
+    pub live: Arc<LiveConfig>,
+    pub admin: AdminConfig,
+    pub documents: DocumentStore,
+    pub knowledge: Option<KnowledgeBase>,
+    pub rich_menus: Option<RichMenuRegistry>,
+    pub settings: SettingsStore,
+    pub conversations: ConversationStore,
+    /// Shared with the task pruning the ledger file.
+    pub usage: Arc<UsageStore>,
+    pub limiter: Limiter,
+    /// Ids of the webhook events already handled.
+    pub dedup: Deduplicator,
+    pub profiles: ProfileCache,
+    pub metrics: BotMetrics,
+}
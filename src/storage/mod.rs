@@ -0,0 +1,497 @@
+//! Storage
+//! # Note
+//! Where conversations, per-chat settings and the usage ledger are kept, chosen with
+//! `storage.backend`:
+//! - `memory`: lost on restart, the default and what the tests use,
+//! - `sqlite`: an embedded database at `storage.sqlite_path`, which survives restarts.
+//!
+//! With Redis configured, `SharedStorage` keeps the conversations there instead, for
+//! every replica to see, and the rest in the backend.
+//!
+//! The methods are async, as handlers call them: backends that block, like `sqlite`,
+//! do so on the blocking threads of the runtime.
+//!
+//! `prune_periodically` deletes turns and ledger entries older than their retention, and
+//! the lines of `usage.ledger_file` and `moderation.review_file` as old: flagged events
+//! are kept as long as turns.
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use log::{error, info};
+
+use crate::config::{StorageBackend, StorageConfig};
+use crate::conversation::Turn;
+use crate::reload::LiveConfig;
+use crate::settings::ChatSettings;
+use crate::usage::{LedgerEntry, LedgerQuery, UsageStore};
+
+pub use self::shared::SharedStorage;
+pub use self::sqlite::SqliteStorage;
+
+pub mod shared;
+pub mod sqlite;
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Appends a turn to the conversation of the chat, dropping the oldest turns
+    /// beyond `max_turns`.
+    async fn push_turn(
+        &self,
+        chat_id: &str,
+        turn: &Turn,
+        max_turns: usize,
+    ) -> Result<(), StorageError>;
+
+    /// Every stored turn of the chat, oldest first.
+    async fn turns(&self, chat_id: &str) -> Result<Vec<Turn>, StorageError>;
+
+    /// The turn of the chat with the LINE message id `message_id`.
+    async fn find_turn(
+        &self,
+        chat_id: &str,
+        message_id: &str,
+    ) -> Result<Option<Turn>, StorageError>;
+
+    /// Deletes the conversation of the chat.
+    async fn clear_turns(&self, chat_id: &str) -> Result<(), StorageError>;
+
+    /// Settings of the chat, `None` if never configured.
+    async fn settings(&self, chat_id: &str) -> Result<Option<ChatSettings>, StorageError>;
+
+    async fn save_settings(
+        &self,
+        chat_id: &str,
+        settings: &ChatSettings,
+    ) -> Result<(), StorageError>;
+
+    async fn remove_settings(&self, chat_id: &str) -> Result<(), StorageError>;
+
+    async fn record_usage(&self, entry: &LedgerEntry) -> Result<(), StorageError>;
+
+    /// Ledger entries matching `query`, oldest first.
+    async fn usage(&self, query: &LedgerQuery) -> Result<Vec<LedgerEntry>, StorageError>;
+
+    /// Deletes the turns and ledger entries older than the given timestamps, in
+    /// milliseconds, and the conversations left empty.
+    async fn prune(
+        &self,
+        turns_before: Option<i64>,
+        usage_before: Option<i64>,
+    ) -> Result<Pruned, StorageError>;
+}
+
+/// What a pruning deleted.
+#[derive(Debug, Default, PartialEq)]
+pub struct Pruned {
+    pub turns: usize,
+    pub usage: usize,
+}
+
+/// Enum representing possible errors of a storage backend.
+#[derive(Debug)]
+pub enum StorageError {
+    Sqlite(rusqlite::Error),
+    Redis(redis::RedisError),
+    /// The blocking thread running a call panicked or was cancelled.
+    Task(tokio::task::JoinError),
+    /// A value this build cannot use, e.g. a stored model it does not know.
+    Invalid(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Sqlite(e) => write!(f, "SQLite error: {e}"),
+            StorageError::Redis(e) => write!(f, "Redis error: {e}"),
+            StorageError::Task(e) => write!(f, "Storage task failed: {e}"),
+            StorageError::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(error: rusqlite::Error) -> Self {
+        StorageError::Sqlite(error)
+    }
+}
+
+impl From<tokio::task::JoinError> for StorageError {
+    fn from(error: tokio::task::JoinError) -> Self {
+        StorageError::Task(error)
+    }
+}
+
+impl From<redis::RedisError> for StorageError {
+    fn from(error: redis::RedisError) -> Self {
+        StorageError::Redis(error)
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryData {
+    conversations: HashMap<String, VecDeque<Turn>>,
+    settings: HashMap<String, ChatSettings>,
+    ledger: Vec<LedgerEntry>,
+}
+
+/// Storage of the process memory, which never fails.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    data: Mutex<MemoryData>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        Default::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn push_turn(
+        &self,
+        chat_id: &str,
+        turn: &Turn,
+        max_turns: usize,
+    ) -> Result<(), StorageError> {
+        let mut data = self.data.lock().unwrap();
+        let turns = data.conversations.entry(chat_id.to_string()).or_default();
+        turns.push_back(turn.clone());
+        while turns.len() > max_turns {
+            turns.pop_front();
+        }
+        Ok(())
+    }
+
+    async fn turns(&self, chat_id: &str) -> Result<Vec<Turn>, StorageError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .conversations
+            .get(chat_id)
+            .map(|t| t.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn find_turn(
+        &self,
+        chat_id: &str,
+        message_id: &str,
+    ) -> Result<Option<Turn>, StorageError> {
+        let data = self.data.lock().unwrap();
+        Ok(data.conversations.get(chat_id).and_then(|turns| {
+            turns
+                .iter()
+                .find(|t| t.message_id.as_deref() == Some(message_id))
+                .cloned()
+        }))
+    }
+
+    async fn clear_turns(&self, chat_id: &str) -> Result<(), StorageError> {
+        self.data.lock().unwrap().conversations.remove(chat_id);
+        Ok(())
+    }
+
+    async fn settings(&self, chat_id: &str) -> Result<Option<ChatSettings>, StorageError> {
+        Ok(self.data.lock().unwrap().settings.get(chat_id).cloned())
+    }
+
+    async fn save_settings(
+        &self,
+        chat_id: &str,
+        settings: &ChatSettings,
+    ) -> Result<(), StorageError> {
+        self.data
+            .lock()
+            .unwrap()
+            .settings
+            .insert(chat_id.to_string(), settings.clone());
+        Ok(())
+    }
+
+    async fn remove_settings(&self, chat_id: &str) -> Result<(), StorageError> {
+        self.data.lock().unwrap().settings.remove(chat_id);
+        Ok(())
+    }
+
+    async fn record_usage(&self, entry: &LedgerEntry) -> Result<(), StorageError> {
+        self.data.lock().unwrap().ledger.push(entry.clone());
+        Ok(())
+    }
+
+    async fn usage(&self, query: &LedgerQuery) -> Result<Vec<LedgerEntry>, StorageError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .ledger
+            .iter()
+            .filter(|entry| query.matches(entry))
+            .cloned()
+            .collect())
+    }
+
+    async fn prune(
+        &self,
+        turns_before: Option<i64>,
+        usage_before: Option<i64>,
+    ) -> Result<Pruned, StorageError> {
+        let mut data = self.data.lock().unwrap();
+        let mut pruned = Pruned::default();
+        if let Some(before) = turns_before {
+            for turns in data.conversations.values_mut() {
+                let len = turns.len();
+                turns.retain(|turn| turn.timestamp >= before);
+                pruned.turns += len - turns.len();
+            }
+            data.conversations.retain(|_, turns| !turns.is_empty());
+        }
+        if let Some(before) = usage_before {
+            let len = data.ledger.len();
+            data.ledger.retain(|entry| entry.timestamp >= before);
+            pruned.usage = len - data.ledger.len();
+        }
+        Ok(pruned)
+    }
+}
+
+/// The storage of `storage.backend`.
+pub fn open(config: &StorageConfig) -> Result<Arc<dyn Storage>, StorageError> {
+    match config.backend().map_err(StorageError::Invalid)? {
+        StorageBackend::Memory => Ok(Arc::new(MemoryStorage::new())),
+        StorageBackend::Sqlite => Ok(Arc::new(SqliteStorage::open(&config.sqlite_path)?)),
+    }
+}
+
+/// The timestamp, in milliseconds, before which what is kept `days` has expired. `None`
+/// when it is kept forever.
+pub fn expired_before(days: u64) -> Option<i64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default();
+    (days > 0).then(|| now - days as i64 * MILLIS_PER_DAY)
+}
+
+/// Rewrites the JSON lines file at `path` without the lines that have `expired`, and
+/// returns how many there were. A missing file has none.
+pub fn prune_lines(path: &Path, expired: impl Fn(&str) -> bool) -> io::Result<usize> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let (expired, kept): (Vec<&str>, Vec<&str>) = text.lines().partition(|line| expired(line));
+    if expired.is_empty() {
+        return Ok(0);
+    }
+    let mut pruned = path.as_os_str().to_owned();
+    pruned.push(".pruned");
+    let kept: String = kept.iter().map(|line| format!("{line}\n")).collect();
+    // Swapped in whole, so that a crash leaves either file
+    fs::write(&pruned, kept)?;
+    fs::rename(&pruned, path)?;
+    Ok(expired.len())
+}
+
+/// Prunes the storage, the ledger file and the review file every
+/// `storage.prune_interval_minutes`, forever.
+pub async fn prune_periodically(
+    storage: Arc<dyn Storage>,
+    config: StorageConfig,
+    usage: Arc<UsageStore>,
+    live: Arc<LiveConfig>,
+) {
+    let interval = Duration::from_secs(config.prune_interval_minutes * 60);
+    loop {
+        tokio::time::sleep(interval).await;
+        let turns_before = expired_before(config.turn_retention_days);
+        let usage_before = expired_before(config.usage_retention_days);
+        match storage.prune(turns_before, usage_before).await {
+            Ok(pruned) if pruned != Pruned::default() => info!(
+                "Pruned {} turns and {} ledger entries",
+                pruned.turns, pruned.usage
+            ),
+            Ok(_) => {}
+            Err(e) => error!("Failed pruning storage: {}", e),
+        }
+        if let Some(before) = usage_before {
+            match usage.prune_file(before).await {
+                Ok(0) => {}
+                Ok(pruned) => info!("Pruned {} lines of the ledger file", pruned),
+                Err(e) => error!("Failed pruning the ledger file: {}", e),
+            }
+        }
+        if let Some(before) = turns_before {
+            // The file of the configuration in effect, which a reload may change
+            let runtime = live.get();
+            let pruned =
+                tokio::task::spawn_blocking(move || runtime.moderation.prune_review_file(before))
+                    .await
+                    .unwrap_or_else(|e| Err(io::Error::other(e)));
+            match pruned {
+                Ok(0) => {}
+                Ok(pruned) => info!("Pruned {} lines of the review file", pruned),
+                Err(e) => error!("Failed pruning the review file: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    //! The same checks against every backend.
+    use crate::openai::models::{Model, Role};
+
+    use super::*;
+
+    fn turn(content: &str, message_id: Option<&str>, timestamp: i64) -> Turn {
+        Turn {
+            timestamp,
+            ..Turn::new(Role::User, content, message_id)
+        }
+    }
+
+    fn entry(chat_id: &str, timestamp: i64) -> LedgerEntry {
+        LedgerEntry {
+            timestamp,
+            chat_id: chat_id.to_string(),
+            user_id: Some("U1".to_string()),
+            model: Model::Gpt_4,
+            persona: None,
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            cost_usd: 0.0006,
+        }
+    }
+
+    pub async fn check_conversations(storage: &dyn Storage) {
+        for i in 0..3 {
+            let id = format!("m{i}");
+            storage
+                .push_turn("U1", &turn(&i.to_string(), Some(&id), i), 2)
+                .await
+                .unwrap();
+        }
+        storage
+            .push_turn("C1", &turn("other", None, 0), 2)
+            .await
+            .unwrap();
+        let contents: Vec<String> = storage
+            .turns("U1")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|t| t.content)
+            .collect();
+        assert_eq!(contents, vec!["1", "2"]);
+        assert_eq!(
+            storage.find_turn("U1", "m2").await.unwrap(),
+            Some(turn("2", Some("m2"), 2))
+        );
+        assert_eq!(storage.find_turn("U1", "m0").await.unwrap(), None);
+        assert_eq!(storage.find_turn("C1", "m2").await.unwrap(), None);
+
+        storage.clear_turns("U1").await.unwrap();
+        assert!(storage.turns("U1").await.unwrap().is_empty());
+        assert_eq!(storage.turns("C1").await.unwrap().len(), 1);
+    }
+
+    pub async fn check_settings(storage: &dyn Storage) {
+        assert_eq!(storage.settings("C1").await.unwrap(), None);
+        let settings = ChatSettings {
+            enabled: false,
+            model: Some(Model::Gpt_4),
+            temperature: Some(0.5),
+            persona: Some("Teacher".to_string()),
+        };
+        storage.save_settings("C1", &settings).await.unwrap();
+        assert_eq!(storage.settings("C1").await.unwrap(), Some(settings));
+        storage
+            .save_settings("C1", &ChatSettings::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.settings("C1").await.unwrap(),
+            Some(ChatSettings::default())
+        );
+        storage.remove_settings("C1").await.unwrap();
+        assert_eq!(storage.settings("C1").await.unwrap(), None);
+    }
+
+    pub async fn check_usage_and_pruning(storage: &dyn Storage) {
+        for (chat_id, timestamp) in [("C1", 1_000), ("C1", 2_000), ("U2", 3_000)] {
+            storage
+                .record_usage(&entry(chat_id, timestamp))
+                .await
+                .unwrap();
+        }
+        let query = LedgerQuery {
+            from: Some(2_000),
+            chat_id: Some("C1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            storage.usage(&query).await.unwrap(),
+            vec![entry("C1", 2_000)]
+        );
+        let query = LedgerQuery {
+            user_id: Some("U1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(storage.usage(&query).await.unwrap().len(), 3);
+        let query = LedgerQuery {
+            user_id: Some("U9".to_string()),
+            ..Default::default()
+        };
+        assert!(storage.usage(&query).await.unwrap().is_empty());
+        assert_eq!(
+            storage.usage(&LedgerQuery::default()).await.unwrap().len(),
+            3
+        );
+
+        storage
+            .push_turn("U1", &turn("old", None, 1_000), 10)
+            .await
+            .unwrap();
+        storage
+            .push_turn("U1", &turn("new", None, 3_000), 10)
+            .await
+            .unwrap();
+        storage
+            .push_turn("U3", &turn("old", None, 1_000), 10)
+            .await
+            .unwrap();
+        let pruned = storage.prune(Some(2_000), None).await.unwrap();
+        assert_eq!(pruned, Pruned { turns: 2, usage: 0 });
+        assert_eq!(storage.turns("U1").await.unwrap().len(), 1);
+        assert!(storage.turns("U3").await.unwrap().is_empty());
+
+        let pruned = storage.prune(None, Some(2_500)).await.unwrap();
+        assert_eq!(pruned, Pruned { turns: 0, usage: 2 });
+        assert_eq!(
+            storage.usage(&LedgerQuery::default()).await.unwrap().len(),
+            1
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        check_conversations(&MemoryStorage::new()).await;
+        check_settings(&MemoryStorage::new()).await;
+        check_usage_and_pruning(&MemoryStorage::new()).await;
+    }
+}
@@ -0,0 +1,228 @@
+//! Shared conversations
+//! # Note
+//! Conversations in Redis, one list of JSON turns per chat, so that every replica
+//! behind the load balancer continues the same one. Settings and usage stay in the
+//! backend of `storage.backend`.
+//!
+//! Redis expires a conversation as a whole, `storage.turn_retention_days` after its last
+//! turn, so pruning leaves it alone.
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::{Pruned, Storage, StorageError};
+use crate::config::StorageConfig;
+use crate::conversation::Turn;
+use crate::settings::ChatSettings;
+use crate::shared::SharedState;
+use crate::usage::{LedgerEntry, LedgerQuery};
+
+pub struct SharedStorage {
+    shared: Arc<SharedState>,
+    /// Everything but the conversations.
+    backend: Arc<dyn Storage>,
+    /// How long a conversation is kept after its last turn, forever if `None`.
+    turn_retention: Option<Duration>,
+}
+
+impl SharedStorage {
+    pub fn new(
+        shared: Arc<SharedState>,
+        backend: Arc<dyn Storage>,
+        config: &StorageConfig,
+    ) -> SharedStorage {
+        let days = config.turn_retention_days;
+        SharedStorage {
+            shared,
+            backend,
+            turn_retention: (days > 0).then(|| Duration::from_secs(days * 24 * 60 * 60)),
+        }
+    }
+
+    fn key(&self, chat_id: &str) -> String {
+        self.shared.key("turns", chat_id)
+    }
+}
+
+fn parse_turns(values: Vec<String>) -> Result<Vec<Turn>, StorageError> {
+    values
+        .iter()
+        .map(|value| serde_json::from_str(value).map_err(|e| StorageError::Invalid(e.to_string())))
+        .collect()
+}
+
+#[async_trait]
+impl Storage for SharedStorage {
+    async fn push_turn(
+        &self,
+        chat_id: &str,
+        turn: &Turn,
+        max_turns: usize,
+    ) -> Result<(), StorageError> {
+        let key = self.key(chat_id);
+        let value =
+            serde_json::to_string(turn).map_err(|e| StorageError::Invalid(e.to_string()))?;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("RPUSH")
+            .arg(&key)
+            .arg(&value)
+            .ignore()
+            .cmd("LTRIM")
+            .arg(&key)
+            .arg(-(max_turns as i64))
+            .arg(-1)
+            .ignore();
+        if let Some(retention) = self.turn_retention {
+            pipe.cmd("PEXPIRE")
+                .arg(&key)
+                .arg(retention.as_millis() as u64)
+                .ignore();
+        }
+        self.shared
+            .with_connection(|mut connection| async move {
+                pipe.query_async::<()>(&mut connection).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn turns(&self, chat_id: &str) -> Result<Vec<Turn>, StorageError> {
+        let mut cmd = redis::cmd("LRANGE");
+        cmd.arg(self.key(chat_id)).arg(0).arg(-1);
+        let values: Vec<String> = self
+            .shared
+            .with_connection(|mut connection| async move { cmd.query_async(&mut connection).await })
+            .await?;
+        parse_turns(values)
+    }
+
+    async fn find_turn(
+        &self,
+        chat_id: &str,
+        message_id: &str,
+    ) -> Result<Option<Turn>, StorageError> {
+        Ok(self
+            .turns(chat_id)
+            .await?
+            .into_iter()
+            .find(|turn| turn.message_id.as_deref() == Some(message_id)))
+    }
+
+    async fn clear_turns(&self, chat_id: &str) -> Result<(), StorageError> {
+        let mut cmd = redis::cmd("DEL");
+        cmd.arg(self.key(chat_id));
+        self.shared
+            .with_connection(|mut connection| async move {
+                cmd.query_async::<()>(&mut connection).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn settings(&self, chat_id: &str) -> Result<Option<ChatSettings>, StorageError> {
+        self.backend.settings(chat_id).await
+    }
+
+    async fn save_settings(
+        &self,
+        chat_id: &str,
+        settings: &ChatSettings,
+    ) -> Result<(), StorageError> {
+        self.backend.save_settings(chat_id, settings).await
+    }
+
+    async fn remove_settings(&self, chat_id: &str) -> Result<(), StorageError> {
+        self.backend.remove_settings(chat_id).await
+    }
+
+    async fn record_usage(&self, entry: &LedgerEntry) -> Result<(), StorageError> {
+        self.backend.record_usage(entry).await
+    }
+
+    async fn usage(&self, query: &LedgerQuery) -> Result<Vec<LedgerEntry>, StorageError> {
+        self.backend.usage(query).await
+    }
+
+    async fn prune(
+        &self,
+        turns_before: Option<i64>,
+        usage_before: Option<i64>,
+    ) -> Result<Pruned, StorageError> {
+        self.backend.prune(turns_before, usage_before).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::Value;
+
+    use super::*;
+    use crate::openai::models::Role;
+    use crate::shared::testing::{mock, MockCmd};
+    use crate::storage::testing::check_settings;
+    use crate::storage::MemoryStorage;
+
+    fn storage(commands: Vec<MockCmd>) -> SharedStorage {
+        SharedStorage::new(
+            Arc::new(mock(commands)),
+            Arc::new(MemoryStorage::new()),
+            &StorageConfig {
+                turn_retention_days: 1,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_conversations_in_redis() {
+        let turn = Turn {
+            role: Role::User,
+            content: "Hello".to_string(),
+            message_id: Some("M1".to_string()),
+            timestamp: 1,
+        };
+        let json = serde_json::to_string(&turn).unwrap();
+        let key = "line_botx:turns:C1";
+        let mut push = redis::pipe();
+        push.atomic()
+            .cmd("RPUSH")
+            .arg(key)
+            .arg(&json)
+            .ignore()
+            .cmd("LTRIM")
+            .arg(key)
+            .arg(-10)
+            .arg(-1)
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg(key)
+            .arg(24 * 60 * 60 * 1000)
+            .ignore();
+        let lrange = || redis::cmd("LRANGE").arg(key).arg(0).arg(-1).clone();
+        let turns = || Value::Array(vec![Value::BulkString(json.clone().into_bytes())]);
+        let storage = storage(vec![
+            MockCmd::with_values(
+                push,
+                Ok(vec![Value::Array(vec![
+                    Value::Int(1),
+                    Value::Okay,
+                    Value::Int(1),
+                ])]),
+            ),
+            MockCmd::new(lrange(), Ok(turns())),
+            MockCmd::new(lrange(), Ok(turns())),
+            MockCmd::new(redis::cmd("DEL").arg(key), Ok(1)),
+        ]);
+        storage.push_turn("C1", &turn, 10).await.unwrap();
+        assert_eq!(storage.turns("C1").await.unwrap(), vec![turn.clone()]);
+        assert_eq!(storage.find_turn("C1", "M1").await.unwrap(), Some(turn));
+        storage.clear_turns("C1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_settings_stay_in_backend() {
+        check_settings(&storage(Vec::new())).await;
+    }
+}
@@ -0,0 +1,471 @@
+//! SQLite storage
+//! # Note
+//! Conversations, settings and the usage ledger in one database file, with the SQLite
+//! that `rusqlite` bundles. The schema is versioned with `PRAGMA user_version`: every
+//! migration of `MIGRATIONS` not applied yet runs in its own transaction when the file
+//! is opened, and a file written by a newer build is refused rather than misread.
+//!
+//! SQLite calls block, so they run on the blocking threads of the runtime rather than
+//! on the workers answering webhooks.
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, Params, Row, TransactionBehavior};
+
+use super::{Pruned, Storage, StorageError};
+use crate::conversation::Turn;
+use crate::openai::models::{Model, Role};
+use crate::settings::ChatSettings;
+use crate::usage::{LedgerEntry, LedgerQuery};
+
+/// Schema changes, in order. Version `n` of the schema is the result of the first `n`.
+const MIGRATIONS: &[&str] = &[
+    // 1: conversations, settings and usage
+    "CREATE TABLE conversations (
+        chat_id TEXT PRIMARY KEY,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE turns (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id TEXT NOT NULL REFERENCES conversations (chat_id) ON DELETE CASCADE,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        message_id TEXT,
+        timestamp INTEGER NOT NULL
+    );
+    CREATE INDEX turns_chat_id ON turns (chat_id, id);
+    CREATE INDEX turns_timestamp ON turns (timestamp);
+    CREATE TABLE chat_settings (
+        chat_id TEXT PRIMARY KEY,
+        enabled INTEGER NOT NULL,
+        model TEXT,
+        temperature REAL,
+        persona TEXT
+    );
+    CREATE TABLE usage (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL,
+        chat_id TEXT NOT NULL,
+        user_id TEXT,
+        model TEXT NOT NULL,
+        persona TEXT,
+        prompt_tokens INTEGER NOT NULL,
+        completion_tokens INTEGER NOT NULL,
+        cost_usd REAL NOT NULL
+    );
+    CREATE INDEX usage_chat_id ON usage (chat_id, timestamp);
+    CREATE INDEX usage_timestamp ON usage (timestamp);",
+];
+
+const TURN_COLUMNS: &str = "role, content, message_id, timestamp";
+const USAGE_COLUMNS: &str =
+    "timestamp, chat_id, user_id, model, persona, prompt_tokens, completion_tokens, cost_usd";
+
+/// How long a call waits for another connection to the file to finish writing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it if needed, and migrates its schema.
+    pub fn open(path: &str) -> Result<SqliteStorage, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute_batch("PRAGMA journal_mode = WAL;")?;
+        SqliteStorage::new(connection)
+    }
+
+    /// A database of its own that is gone once dropped.
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<SqliteStorage, StorageError> {
+        SqliteStorage::new(Connection::open_in_memory()?)
+    }
+
+    fn new(mut connection: Connection) -> Result<SqliteStorage, StorageError> {
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        migrate(&mut connection)?;
+        Ok(SqliteStorage {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Version of the schema of the database.
+    #[cfg(test)]
+    pub fn schema_version(&self) -> Result<usize, StorageError> {
+        schema_version(&self.connection.lock().unwrap())
+    }
+
+    /// Runs `f` with the connection on a blocking thread.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let connection = Arc::clone(&self.connection);
+        tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap())).await?
+    }
+
+    /// Runs `f` in a transaction, committed if it succeeds and rolled back otherwise.
+    async fn transaction<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        self.with_connection(|connection| {
+            let transaction =
+                connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let value = f(&transaction)?;
+            transaction.commit()?;
+            Ok(value)
+        })
+        .await
+    }
+}
+
+fn schema_version(connection: &Connection) -> Result<usize, StorageError> {
+    let version: i64 = connection.query_row("PRAGMA user_version;", [], |row| row.get(0))?;
+    Ok(version.max(0) as usize)
+}
+
+fn migrate(connection: &mut Connection) -> Result<(), StorageError> {
+    let version = schema_version(connection)?;
+    if version > MIGRATIONS.len() {
+        return Err(StorageError::Invalid(format!(
+            "schema version {version} is newer than this build, which knows up to {}",
+            MIGRATIONS.len()
+        )));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", (index + 1) as i64)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+/// Every row `sql` selects, read with `read`.
+fn select<T, P: Params>(
+    connection: &Connection,
+    sql: &str,
+    params: P,
+    read: fn(&Row) -> Result<T, StorageError>,
+) -> Result<Vec<T>, StorageError> {
+    let mut statement = connection.prepare_cached(sql)?;
+    let mut rows = statement.query(params)?;
+    let mut values = Vec::new();
+    while let Some(row) = rows.next()? {
+        values.push(read(row)?);
+    }
+    Ok(values)
+}
+
+fn role_name(role: &Role) -> &'static str {
+    match role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+    }
+}
+
+fn parse_role(name: &str) -> Result<Role, StorageError> {
+    match name {
+        "system" => Ok(Role::System),
+        "user" => Ok(Role::User),
+        "assistant" => Ok(Role::Assistant),
+        other => Err(StorageError::Invalid(format!("unknown role {other:?}"))),
+    }
+}
+
+fn parse_model(name: &str) -> Result<Model, StorageError> {
+    Model::from_str(name).map_err(|_| StorageError::Invalid(format!("unknown model {name:?}")))
+}
+
+fn read_turn(row: &Row) -> Result<Turn, StorageError> {
+    Ok(Turn {
+        role: parse_role(&row.get::<_, String>(0)?)?,
+        content: row.get(1)?,
+        message_id: row.get(2)?,
+        timestamp: row.get(3)?,
+    })
+}
+
+fn read_entry(row: &Row) -> Result<LedgerEntry, StorageError> {
+    Ok(LedgerEntry {
+        timestamp: row.get(0)?,
+        chat_id: row.get(1)?,
+        user_id: row.get(2)?,
+        model: parse_model(&row.get::<_, String>(3)?)?,
+        persona: row.get(4)?,
+        prompt_tokens: row.get::<_, i64>(5)?.max(0) as u64,
+        completion_tokens: row.get::<_, i64>(6)?.max(0) as u64,
+        cost_usd: row.get(7)?,
+    })
+}
+
+fn read_settings(row: &Row) -> Result<ChatSettings, StorageError> {
+    Ok(ChatSettings {
+        enabled: row.get(0)?,
+        model: row
+            .get::<_, Option<String>>(1)?
+            .as_deref()
+            .map(parse_model)
+            .transpose()?,
+        temperature: row.get::<_, Option<f64>>(2)?.map(|t| t as f32),
+        persona: row.get(3)?,
+    })
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn push_turn(
+        &self,
+        chat_id: &str,
+        turn: &Turn,
+        max_turns: usize,
+    ) -> Result<(), StorageError> {
+        let (chat_id, turn) = (chat_id.to_string(), turn.clone());
+        self.transaction(move |connection| {
+            connection.execute(
+                "INSERT INTO conversations (chat_id, updated_at) VALUES (?1, ?2)
+                 ON CONFLICT (chat_id) DO UPDATE SET updated_at = excluded.updated_at",
+                params![chat_id, now_millis()],
+            )?;
+            connection.execute(
+                &format!("INSERT INTO turns (chat_id, {TURN_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5)"),
+                params![
+                    chat_id,
+                    role_name(&turn.role),
+                    turn.content,
+                    turn.message_id,
+                    turn.timestamp,
+                ],
+            )?;
+            connection.execute(
+                "DELETE FROM turns WHERE chat_id = ?1 AND id NOT IN (
+                     SELECT id FROM turns WHERE chat_id = ?1 ORDER BY id DESC LIMIT ?2
+                 )",
+                params![chat_id, max_turns as i64],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn turns(&self, chat_id: &str) -> Result<Vec<Turn>, StorageError> {
+        let chat_id = chat_id.to_string();
+        self.with_connection(move |connection| {
+            select(
+                connection,
+                &format!("SELECT {TURN_COLUMNS} FROM turns WHERE chat_id = ?1 ORDER BY id"),
+                [chat_id],
+                read_turn,
+            )
+        })
+        .await
+    }
+
+    async fn find_turn(
+        &self,
+        chat_id: &str,
+        message_id: &str,
+    ) -> Result<Option<Turn>, StorageError> {
+        let (chat_id, message_id) = (chat_id.to_string(), message_id.to_string());
+        let turns = self
+            .with_connection(move |connection| {
+                select(
+                    connection,
+                    &format!(
+                        "SELECT {TURN_COLUMNS} FROM turns WHERE chat_id = ?1 AND message_id = ?2
+                         ORDER BY id DESC LIMIT 1"
+                    ),
+                    [chat_id, message_id],
+                    read_turn,
+                )
+            })
+            .await?;
+        Ok(turns.into_iter().next())
+    }
+
+    async fn clear_turns(&self, chat_id: &str) -> Result<(), StorageError> {
+        let chat_id = chat_id.to_string();
+        self.with_connection(move |connection| {
+            // The turns go with their conversation
+            connection.execute("DELETE FROM conversations WHERE chat_id = ?1", [chat_id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn settings(&self, chat_id: &str) -> Result<Option<ChatSettings>, StorageError> {
+        let chat_id = chat_id.to_string();
+        let settings = self
+            .with_connection(move |connection| {
+                select(
+                    connection,
+                    "SELECT enabled, model, temperature, persona FROM chat_settings
+                     WHERE chat_id = ?1",
+                    [chat_id],
+                    read_settings,
+                )
+            })
+            .await?;
+        Ok(settings.into_iter().next())
+    }
+
+    async fn save_settings(
+        &self,
+        chat_id: &str,
+        settings: &ChatSettings,
+    ) -> Result<(), StorageError> {
+        let (chat_id, settings) = (chat_id.to_string(), settings.clone());
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO chat_settings (chat_id, enabled, model, temperature, persona)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    chat_id,
+                    settings.enabled,
+                    settings.model.map(|m| m.to_string()),
+                    settings.temperature.map(f64::from),
+                    settings.persona,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_settings(&self, chat_id: &str) -> Result<(), StorageError> {
+        let chat_id = chat_id.to_string();
+        self.with_connection(move |connection| {
+            connection.execute("DELETE FROM chat_settings WHERE chat_id = ?1", [chat_id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_usage(&self, entry: &LedgerEntry) -> Result<(), StorageError> {
+        let entry = entry.clone();
+        self.with_connection(move |connection| {
+            connection.execute(
+                &format!(
+                    "INSERT INTO usage ({USAGE_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+                ),
+                params![
+                    entry.timestamp,
+                    entry.chat_id,
+                    entry.user_id,
+                    entry.model.to_string(),
+                    entry.persona,
+                    entry.prompt_tokens as i64,
+                    entry.completion_tokens as i64,
+                    entry.cost_usd,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn usage(&self, query: &LedgerQuery) -> Result<Vec<LedgerEntry>, StorageError> {
+        let query = query.clone();
+        self.with_connection(move |connection| {
+            select(
+                connection,
+                &format!(
+                    "SELECT {USAGE_COLUMNS} FROM usage
+                     WHERE (?1 IS NULL OR timestamp >= ?1)
+                       AND (?2 IS NULL OR timestamp < ?2)
+                       AND (?3 IS NULL OR chat_id = ?3)
+                       AND (?4 IS NULL OR user_id = ?4)
+                     ORDER BY id"
+                ),
+                params![query.from, query.to, query.chat_id, query.user_id],
+                read_entry,
+            )
+        })
+        .await
+    }
+
+    async fn prune(
+        &self,
+        turns_before: Option<i64>,
+        usage_before: Option<i64>,
+    ) -> Result<Pruned, StorageError> {
+        self.transaction(move |connection| {
+            let mut pruned = Pruned::default();
+            if let Some(before) = turns_before {
+                pruned.turns =
+                    connection.execute("DELETE FROM turns WHERE timestamp < ?1", [before])?;
+                connection.execute(
+                    "DELETE FROM conversations
+                     WHERE chat_id NOT IN (SELECT DISTINCT chat_id FROM turns)",
+                    [],
+                )?;
+            }
+            if let Some(before) = usage_before {
+                pruned.usage =
+                    connection.execute("DELETE FROM usage WHERE timestamp < ?1", [before])?;
+            }
+            Ok(pruned)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sqlite_storage() {
+        check_conversations(&SqliteStorage::open_in_memory().unwrap()).await;
+        check_settings(&SqliteStorage::open_in_memory().unwrap()).await;
+        check_usage_and_pruning(&SqliteStorage::open_in_memory().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_file_survives_restart_and_newer_schema_is_refused() {
+        let path =
+            std::env::temp_dir().join(format!("line_botx_storage_{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let storage = SqliteStorage::open(path).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), MIGRATIONS.len());
+        storage
+            .push_turn("U1", &Turn::new(Role::User, "hi", Some("m1")), 10)
+            .await
+            .unwrap();
+        drop(storage);
+
+        let restarted = SqliteStorage::open(path).unwrap();
+        assert_eq!(restarted.turns("U1").await.unwrap()[0].content, "hi");
+        restarted
+            .connection
+            .lock()
+            .unwrap()
+            .pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1)
+            .unwrap();
+        drop(restarted);
+
+        assert!(matches!(
+            SqliteStorage::open(path),
+            Err(StorageError::Invalid(_))
+        ));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use actix_web::dev::Payload;
+use actix_web::{error::ErrorUnauthorized, Error, FromRequest, HttpRequest};
+use std::{future::Future, pin::Pin};
+
+/// Bearer token of an admin request.
+#[derive(Debug)]
+pub struct AdminToken {
+    pub token: String,
+}
+
+impl FromRequest for AdminToken {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let res = match token {
+            Some(token) => Ok(AdminToken {
+                token: token.trim().to_string(),
+            }),
+            None => Err(ErrorUnauthorized("authorization is missing")),
+        };
+        Box::pin(async move { res })
+    }
+}
@@ -1,2 +1,3 @@
 //! Support for framework
+pub mod admin_token;
 pub mod signature;
@@ -0,0 +1,230 @@
+//! Telemetry
+//! # Note
+//! Installs the global tracer and meter providers that `traces` and `metrics` record
+//! into, with the exporter of `telemetry.exporter`:
+//! - `application_insights`: Azure Application Insights, with live metrics,
+//! - `otlp`: an OpenTelemetry collector, Jaeger or any other OTLP endpoint, over gRPC or
+//!   HTTP as `telemetry.otlp_protocol` says,
+//! - `stdout`: JSON on the standard output, for development,
+//! - `none`: spans and metrics are dropped.
+//!
+//! Both are exported in batches in the background. `Telemetry::shutdown` exports what
+//! is left before the process exits.
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use log::error;
+use opentelemetry::global;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricsExporterBuilder, Protocol, SpanExporterBuilder, WithExportConfig};
+use opentelemetry_sdk::export::trace::SpanExporter;
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{DefaultAggregationSelector, DefaultTemporalitySelector};
+use opentelemetry_sdk::metrics::{MeterProvider, PeriodicReader};
+use opentelemetry_sdk::runtime::Tokio;
+use opentelemetry_sdk::trace::{self, TracerProvider};
+use opentelemetry_sdk::Resource;
+use reqwest::Client;
+
+use crate::config::{TelemetryConfig, TelemetryExporter};
+use crate::telemetry::stdout::{StdoutMetricExporter, StdoutSpanExporter};
+use crate::traces;
+
+pub mod stdout;
+
+/// The installed providers, `None` when telemetry is off.
+#[derive(Default)]
+pub struct Telemetry {
+    tracer_provider: Option<TracerProvider>,
+    meter_provider: Option<MeterProvider>,
+}
+
+/// Enum representing possible errors while installing the exporters.
+#[derive(Debug)]
+pub enum TelemetryError {
+    InvalidExporter(String),
+    ApplicationInsights(Box<dyn Error + Send + Sync>),
+    Otlp(Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::InvalidExporter(message) => write!(f, "{message}"),
+            TelemetryError::ApplicationInsights(e) => {
+                write!(f, "invalid Application Insights connection string: {e}")
+            }
+            TelemetryError::Otlp(e) => write!(f, "invalid OTLP exporter: {e}"),
+        }
+    }
+}
+
+impl Error for TelemetryError {}
+
+/// Installs the providers of the configured exporter. Must run inside the Tokio runtime,
+/// which the batches are exported on.
+pub fn init(config: &TelemetryConfig) -> Result<Telemetry, TelemetryError> {
+    traces::init_propagator();
+    let exporter = config.exporter().map_err(TelemetryError::InvalidExporter)?;
+    let resource = Resource::default().merge(&Resource::new([KeyValue::new(
+        "service.name",
+        config.service_name.clone(),
+    )]));
+    let interval = Duration::from_secs(config.metrics_interval_seconds);
+
+    let (tracer_provider, meter_provider) = match exporter {
+        TelemetryExporter::None => return Ok(Telemetry::default()),
+        TelemetryExporter::ApplicationInsights => {
+            let connection_string = config
+                .application_insights_connection_string
+                .as_deref()
+                .unwrap_or_default();
+            let tracer_provider =
+                opentelemetry_application_insights::new_pipeline_from_connection_string(
+                    connection_string,
+                )
+                .map_err(TelemetryError::ApplicationInsights)?
+                .with_client(Client::new())
+                .with_service_name(config.service_name.clone())
+                .with_live_metrics(true)
+                .build_batch(Tokio);
+            let exporter =
+                opentelemetry_application_insights::Exporter::new_from_connection_string(
+                    connection_string,
+                    Client::new(),
+                )
+                .map_err(TelemetryError::ApplicationInsights)?;
+            (
+                tracer_provider,
+                meter_provider(exporter, interval, resource),
+            )
+        }
+        TelemetryExporter::Otlp => {
+            let protocol = config
+                .otlp_protocol()
+                .map_err(TelemetryError::InvalidExporter)?;
+            let (spans, metrics) = otlp_builders(&config.otlp_endpoint, protocol);
+            let spans = spans
+                .build_span_exporter()
+                .map_err(|e| TelemetryError::Otlp(e.into()))?;
+            let metrics = metrics
+                .build_metrics_exporter(
+                    Box::new(DefaultTemporalitySelector::new()),
+                    Box::new(DefaultAggregationSelector::new()),
+                )
+                .map_err(|e| TelemetryError::Otlp(e.into()))?;
+            (
+                tracer_provider(spans, resource.clone()),
+                meter_provider(metrics, interval, resource),
+            )
+        }
+        TelemetryExporter::Stdout => (
+            tracer_provider(StdoutSpanExporter, resource.clone()),
+            meter_provider(StdoutMetricExporter, interval, resource),
+        ),
+    };
+
+    global::set_tracer_provider(tracer_provider.clone());
+    global::set_meter_provider(meter_provider.clone());
+    Ok(Telemetry {
+        tracer_provider: Some(tracer_provider),
+        meter_provider: Some(meter_provider),
+    })
+}
+
+/// The builders of the span and metric exporters sending to the collector at `endpoint`.
+fn otlp_builders(
+    endpoint: &str,
+    protocol: Protocol,
+) -> (SpanExporterBuilder, MetricsExporterBuilder) {
+    let endpoint = endpoint.trim_end_matches('/');
+    match protocol {
+        Protocol::Grpc => {
+            let builder = || {
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint)
+            };
+            (builder().into(), builder().into())
+        }
+        Protocol::HttpBinary => {
+            let builder = || {
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_http_client(Client::new())
+                    .with_endpoint(endpoint)
+            };
+            (builder().into(), builder().into())
+        }
+    }
+}
+
+fn tracer_provider<E>(exporter: E, resource: Resource) -> TracerProvider
+where
+    E: SpanExporter + 'static,
+{
+    TracerProvider::builder()
+        .with_batch_exporter(exporter, Tokio)
+        .with_config(trace::config().with_resource(resource))
+        .build()
+}
+
+fn meter_provider<E>(exporter: E, interval: Duration, resource: Resource) -> MeterProvider
+where
+    E: PushMetricsExporter,
+{
+    let reader = PeriodicReader::builder(exporter, Tokio)
+        .with_interval(interval)
+        .build();
+    MeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource)
+        .build()
+}
+
+impl Telemetry {
+    /// Exports the spans and metrics still buffered, and stops exporting. Blocks until
+    /// the exports are done, so it must not run on a thread of the Tokio runtime.
+    pub fn shutdown(self) {
+        if let Some(provider) = self.tracer_provider {
+            for result in provider.force_flush() {
+                if let Err(e) = result {
+                    error!("Failed exporting spans: {}", e);
+                }
+            }
+            global::shutdown_tracer_provider();
+            // The span processors shut down with the last reference to the provider
+            drop(provider);
+        }
+        if let Some(provider) = self.meter_provider {
+            if let Err(e) = provider.force_flush() {
+                error!("Failed exporting metrics: {}", e);
+            }
+            // The periodic reader of SDK 0.21 fails its last export on shutdown, as it
+            // is marked shut down first, hence the flush above
+            let _ = provider.shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_otlp_exporters_of_both_protocols() {
+        for (endpoint, protocol) in [
+            ("http://localhost:4317/", Protocol::Grpc),
+            ("http://localhost:4318", Protocol::HttpBinary),
+        ] {
+            let (spans, metrics) = otlp_builders(endpoint, protocol);
+            assert!(spans.build_span_exporter().is_ok());
+            let metrics = metrics.build_metrics_exporter(
+                Box::new(DefaultTemporalitySelector::new()),
+                Box::new(DefaultAggregationSelector::new()),
+            );
+            assert!(metrics.is_ok());
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Stdout
+//! # Note
+//! Spans and metrics written to the standard output, one JSON document per line, for
+//! development. The documents are the OTLP export requests of `opentelemetry-proto`
+//! serialized with serde.
+use std::io::Write;
+
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use opentelemetry::metrics::{MetricsError, Result as MetricsResult};
+use opentelemetry::trace::TraceError;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::metrics::data::{ResourceMetrics, Temporality};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{
+    AggregationSelector, DefaultAggregationSelector, TemporalitySelector,
+};
+use opentelemetry_sdk::metrics::{Aggregation, InstrumentKind};
+use serde::Serialize;
+
+/// Writes `document` as one line.
+fn print<T: Serialize>(document: &T) -> Result<(), String> {
+    let line = serde_json::to_string(document).map_err(|e| e.to_string())?;
+    writeln!(std::io::stdout().lock(), "{line}").map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Default)]
+pub struct StdoutSpanExporter;
+
+impl SpanExporter for StdoutSpanExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let request = ExportTraceServiceRequest {
+            resource_spans: batch.into_iter().map(Into::into).collect(),
+        };
+        Box::pin(std::future::ready(
+            print(&request).map_err(TraceError::from),
+        ))
+    }
+}
+
+/// Exports cumulative sums and histograms, like the OTLP exporter does.
+#[derive(Debug, Default)]
+pub struct StdoutMetricExporter;
+
+impl TemporalitySelector for StdoutMetricExporter {
+    fn temporality(&self, _kind: InstrumentKind) -> Temporality {
+        Temporality::Cumulative
+    }
+}
+
+impl AggregationSelector for StdoutMetricExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for StdoutMetricExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        print(&ExportMetricsServiceRequest::from(&*metrics)).map_err(MetricsError::Other)
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+}
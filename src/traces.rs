@@ -0,0 +1,193 @@
+//! Traces
+//! # Note
+//! Every webhook event is a trace of its own, linked to the span of the webhook request
+//! that delivered it, so that the events of one batch can be followed separately. The
+//! OpenAI and LINE calls made for the event are client spans of that trace, and carry
+//! it to the services in a W3C `traceparent` header.
+//!
+//! Spans are created with the global tracer, so they go to whichever exporter the
+//! installed `TracerProvider` has, and nowhere without one.
+use std::borrow::Cow;
+
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::propagation::Injector;
+use opentelemetry::trace::{Link, SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Error, Response};
+
+use crate::events::EventType;
+use crate::metrics::{EVENT_TYPE, HTTP_STATUS};
+
+/// Name of the tracer of the bot.
+pub const TRACER_NAME: &str = "line_botx";
+
+/// Full URL of an outbound request.
+pub const URL: &str = "url.full";
+/// `openai` for the completions.
+pub const GEN_AI_SYSTEM: &str = "gen_ai.system";
+/// `chat` for the completions.
+pub const GEN_AI_OPERATION: &str = "gen_ai.operation.name";
+pub const RESPONSE_MODEL: &str = "gen_ai.response.model";
+pub const INPUT_TOKENS: &str = "gen_ai.usage.input_tokens";
+pub const OUTPUT_TOKENS: &str = "gen_ai.usage.output_tokens";
+/// Why each choice stopped, e.g. `stop` or `length`.
+pub const FINISH_REASONS: &str = "gen_ai.response.finish_reasons";
+
+/// Sends the trace context of outbound requests as W3C `traceparent` headers.
+pub fn init_propagator() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+pub fn tracer() -> BoxedTracer {
+    global::tracer(TRACER_NAME)
+}
+
+/// Starts the trace of a webhook event, linked to the current span, which is the span
+/// of the webhook request.
+pub fn event_context(event: &EventType) -> Context {
+    let tracer = tracer();
+    let request = Context::current();
+    let mut builder = tracer
+        .span_builder(format!("line.event {}", event.name()))
+        .with_kind(SpanKind::Consumer)
+        .with_attributes(vec![KeyValue::new(EVENT_TYPE, event.name())]);
+    let request_span = request.span().span_context().clone();
+    if request_span.is_valid() {
+        builder = builder.with_links(vec![Link::new(request_span, Vec::new())]);
+    }
+    Context::new().with_span(builder.start_with_context(&tracer, &Context::new()))
+}
+
+/// Starts a client span, child of the span of `parent`.
+pub fn client_context<N>(parent: &Context, name: N, attributes: Vec<KeyValue>) -> Context
+where
+    N: Into<Cow<'static, str>>,
+{
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Client)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, parent);
+    parent.with_span(span)
+}
+
+/// Adds the trace context of `cx` to the headers of an outbound request.
+pub fn inject(cx: &Context, headers: &mut HeaderMap) {
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(cx, &mut HeaderInjector(headers))
+    });
+}
+
+/// Records the outcome of an HTTP call on the span of `cx`, and ends the span.
+pub fn end_http(cx: &Context, res: &Result<Response, Error>) {
+    let span = cx.span();
+    match res {
+        Ok(response) => {
+            let status = response.status();
+            span.set_attribute(KeyValue::new(HTTP_STATUS, i64::from(status.as_u16())));
+            if !status.is_success() {
+                span.set_status(Status::error(status.to_string()));
+            }
+        }
+        Err(e) => span.set_status(Status::error(e.to_string())),
+    }
+    span.end();
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    //! A global tracer provider whose spans are kept in memory.
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    use futures_util::future::BoxFuture;
+    use opentelemetry::trace::TraceResult;
+    use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use opentelemetry_sdk::trace::TracerProvider;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for InMemoryExporter {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    /// Spans ended since the provider was installed.
+    pub struct InMemorySpans {
+        exporter: InMemoryExporter,
+        provider: TracerProvider,
+    }
+
+    impl InMemorySpans {
+        /// Installs the provider for the whole test binary, once.
+        pub fn global() -> &'static InMemorySpans {
+            static SPANS: OnceLock<InMemorySpans> = OnceLock::new();
+            SPANS.get_or_init(|| {
+                let exporter = InMemoryExporter::default();
+                let provider = TracerProvider::builder()
+                    .with_simple_exporter(exporter.clone())
+                    .build();
+                global::set_tracer_provider(provider.clone());
+                init_propagator();
+                InMemorySpans { exporter, provider }
+            })
+        }
+
+        pub fn spans(&self) -> Vec<SpanData> {
+            let flushed: TraceResult<()> = self.provider.force_flush().into_iter().collect();
+            flushed.unwrap();
+            self.exporter.0.lock().unwrap().clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+    use super::*;
+
+    #[test]
+    fn test_inject_traceparent() {
+        init_propagator();
+        let span = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let cx = Context::new().with_remote_span_context(span);
+        let mut headers = HeaderMap::new();
+        inject(&cx, &mut headers);
+        assert_eq!(
+            headers["traceparent"],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        // Nothing to propagate outside of a trace
+        let mut headers = HeaderMap::new();
+        inject(&Context::new(), &mut headers);
+        assert!(headers.get("traceparent").is_none());
+    }
+}
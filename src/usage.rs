@@ -0,0 +1,584 @@
+//! Token usage
+//! # Note
+//! Every completion is recorded in a ledger with the tokens OpenAI reported, the chat,
+//! user, model and persona it was for, and its cost at the price of the model: its list
+//! price, or the one set in `[usage.prices]`.
+//! The ledger is kept in the storage. With `usage.ledger_file` set, it is also appended
+//! to that file as JSON lines, which are read back at startup into a storage that has
+//! no ledger yet: every time with the `memory` storage, once with `sqlite`. Entries
+//! older than `storage.usage_retention_days` are pruned from the file as from the
+//! storage, and not read back.
+//!
+//! Totals are shown by `/usage`, and the ledger is queried and exported as CSV through
+//! `GET /admin/usage`.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::config::UsageConfig;
+use crate::dates::format_timestamp;
+use crate::openai::client::Usage;
+use crate::openai::models::Model;
+use crate::storage::{prune_lines, MemoryStorage, Storage, StorageError};
+
+/// Prices in USD of 1,000 prompt tokens and of 1,000 completion tokens, by model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prices {
+    per_1k_tokens: HashMap<Model, (f64, f64)>,
+}
+
+impl Prices {
+    /// The list prices, replaced by those set in `usage.prices`.
+    pub fn from_config(config: &UsageConfig) -> Prices {
+        let per_1k_tokens = Model::ALL
+            .into_iter()
+            .map(|model| {
+                let (mut prompt, mut completion) = model.price_per_1k_tokens();
+                if let Some(price) = config.prices.get(&model.to_string()) {
+                    prompt = price.prompt.unwrap_or(prompt);
+                    completion = price.completion.unwrap_or(completion);
+                }
+                (model, (prompt, completion))
+            })
+            .collect();
+        Prices { per_1k_tokens }
+    }
+
+    pub fn per_1k_tokens(&self, model: Model) -> (f64, f64) {
+        self.per_1k_tokens[&model]
+    }
+}
+
+impl Default for Prices {
+    fn default() -> Self {
+        Prices::from_config(&UsageConfig::default())
+    }
+}
+
+/// Cost in USD of a completion at the price of the model.
+pub fn cost_usd(prices: &Prices, model: Model, usage: &Usage) -> f64 {
+    let (prompt_price, completion_price) = prices.per_1k_tokens(model);
+    (usage.prompt_tokens.max(0) as f64 * prompt_price
+        + usage.completion_tokens.max(0) as f64 * completion_price)
+        / 1000.0
+}
+
+/// One completion.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// Milliseconds since the epoch.
+    pub timestamp: i64,
+    pub chat_id: String,
+    /// User who asked, when LINE provides it.
+    pub user_id: Option<String>,
+    pub model: Model,
+    pub persona: Option<String>,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl LedgerEntry {
+    pub fn new(
+        chat_id: &str,
+        user_id: Option<&str>,
+        model: Model,
+        persona: Option<&str>,
+        usage: &Usage,
+        prices: &Prices,
+    ) -> LedgerEntry {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        LedgerEntry {
+            timestamp,
+            chat_id: chat_id.to_string(),
+            user_id: user_id.map(str::to_string),
+            model,
+            persona: persona.map(str::to_string),
+            prompt_tokens: usage.prompt_tokens.max(0) as u64,
+            completion_tokens: usage.completion_tokens.max(0) as u64,
+            cost_usd: cost_usd(prices, model, usage),
+        }
+    }
+}
+
+/// Usage totals of one chat, or of any group of entries.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl ChatUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    fn add(&mut self, entry: &LedgerEntry) {
+        self.requests += 1;
+        self.prompt_tokens += entry.prompt_tokens;
+        self.completion_tokens += entry.completion_tokens;
+        self.cost_usd += entry.cost_usd;
+    }
+}
+
+/// Entries to select from the ledger. Timestamps are in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LedgerQuery {
+    /// First timestamp included.
+    pub from: Option<i64>,
+    /// First timestamp excluded.
+    pub to: Option<i64>,
+    pub chat_id: Option<String>,
+    /// The user who asked, in any chat.
+    pub user_id: Option<String>,
+}
+
+impl LedgerQuery {
+    pub fn matches(&self, entry: &LedgerEntry) -> bool {
+        self.from.is_none_or(|from| entry.timestamp >= from)
+            && self.to.is_none_or(|to| entry.timestamp < to)
+            && self.chat_id.as_ref().is_none_or(|id| *id == entry.chat_id)
+            && self
+                .user_id
+                .as_ref()
+                .is_none_or(|id| Some(id) == entry.user_id.as_ref())
+    }
+}
+
+/// Totals of entries, overall and broken down for reconciliation.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct UsageReport {
+    pub total: ChatUsage,
+    pub by_model: BTreeMap<String, ChatUsage>,
+    pub by_persona: BTreeMap<String, ChatUsage>,
+    pub by_chat: BTreeMap<String, ChatUsage>,
+}
+
+impl UsageReport {
+    pub fn new(entries: &[LedgerEntry]) -> UsageReport {
+        let mut report = UsageReport::default();
+        for entry in entries {
+            report.total.add(entry);
+            report
+                .by_model
+                .entry(entry.model.to_string())
+                .or_default()
+                .add(entry);
+            let persona = entry.persona.as_deref().unwrap_or("none");
+            report
+                .by_persona
+                .entry(persona.to_string())
+                .or_default()
+                .add(entry);
+            report
+                .by_chat
+                .entry(entry.chat_id.clone())
+                .or_default()
+                .add(entry);
+        }
+        report
+    }
+}
+
+/// Enum representing possible errors while reading or writing the ledger.
+#[derive(Debug)]
+pub enum UsageError {
+    Io(io::Error),
+    Json {
+        line: usize,
+        error: serde_json::Error,
+    },
+    Storage(StorageError),
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsageError::Io(e) => write!(f, "IO error: {e}"),
+            UsageError::Json { line, error } => write!(f, "line {line}: {error}"),
+            UsageError::Storage(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+impl From<io::Error> for UsageError {
+    fn from(error: io::Error) -> Self {
+        UsageError::Io(error)
+    }
+}
+
+impl From<StorageError> for UsageError {
+    fn from(error: StorageError) -> Self {
+        UsageError::Storage(error)
+    }
+}
+
+/// Ledger of every completion, kept in the storage and optionally in a file.
+pub struct UsageStore {
+    storage: Arc<dyn Storage>,
+    ledger_file: Option<PathBuf>,
+    /// Held while recording, so that the file keeps the order of the ledger.
+    record: Mutex<()>,
+}
+
+impl UsageStore {
+    /// A store kept in memory.
+    pub fn new() -> UsageStore {
+        UsageStore::with_storage(Arc::new(MemoryStorage::new()))
+    }
+
+    pub fn with_storage(storage: Arc<dyn Storage>) -> UsageStore {
+        UsageStore {
+            storage,
+            ledger_file: None,
+            record: Mutex::new(()),
+        }
+    }
+
+    /// A store that appends to the ledger file at `path`, whose entries are added to
+    /// the storage if it has no ledger yet, but for those older than `before`.
+    pub async fn load(
+        storage: Arc<dyn Storage>,
+        path: &str,
+        before: Option<i64>,
+    ) -> Result<UsageStore, UsageError> {
+        let replay = storage.usage(&LedgerQuery::default()).await?.is_empty();
+        match std::fs::File::open(path) {
+            Ok(file) if replay => {
+                for (index, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let entry: LedgerEntry =
+                        serde_json::from_str(&line).map_err(|error| UsageError::Json {
+                            line: index + 1,
+                            error,
+                        })?;
+                    if before.is_some_and(|before| entry.timestamp < before) {
+                        continue;
+                    }
+                    storage.record_usage(&entry).await?;
+                }
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(UsageStore {
+            ledger_file: Some(PathBuf::from(path)),
+            ..UsageStore::with_storage(storage)
+        })
+    }
+
+    /// Adds one completion to the ledger, and to the file if there is one.
+    pub async fn record(&self, entry: LedgerEntry) -> Result<(), UsageError> {
+        let _record = self.record.lock().await;
+        if let Some(path) = &self.ledger_file {
+            let mut line = serde_json::to_string(&entry).map_err(io::Error::other)?;
+            line.push('\n');
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            // A tokio file finishes writing in the background unless flushed
+            file.flush().await?;
+        }
+        self.storage.record_usage(&entry).await?;
+        Ok(())
+    }
+
+    /// Removes the entries older than `before`, in milliseconds, from the ledger file.
+    pub async fn prune_file(&self, before: i64) -> Result<usize, UsageError> {
+        let Some(path) = self.ledger_file.clone() else {
+            return Ok(0);
+        };
+        let _record = self.record.lock().await;
+        let pruned = tokio::task::spawn_blocking(move || {
+            prune_lines(&path, |line| {
+                serde_json::from_str::<LedgerEntry>(line)
+                    .is_ok_and(|entry| entry.timestamp < before)
+            })
+        })
+        .await
+        .map_err(io::Error::other)??;
+        Ok(pruned)
+    }
+
+    /// Totals of the chat since the ledger started, none if the storage fails.
+    pub async fn get(&self, chat_id: &str) -> ChatUsage {
+        let query = LedgerQuery {
+            chat_id: Some(chat_id.to_string()),
+            ..Default::default()
+        };
+        let mut usage = ChatUsage::default();
+        match self.storage.usage(&query).await {
+            Ok(entries) => entries.iter().for_each(|entry| usage.add(entry)),
+            Err(e) => error!("Failed reading usage of {}: {}", chat_id, e),
+        }
+        usage
+    }
+
+    /// Entries matching `query`, oldest first.
+    pub async fn query(&self, query: &LedgerQuery) -> Result<Vec<LedgerEntry>, UsageError> {
+        Ok(self.storage.usage(query).await?)
+    }
+}
+
+impl Default for UsageStore {
+    fn default() -> Self {
+        UsageStore::new()
+    }
+}
+
+/// The entries as CSV, one row per completion.
+pub fn to_csv(entries: &[LedgerEntry]) -> String {
+    let mut csv = String::from(
+        "timestamp,chat_id,user_id,model,persona,prompt_tokens,completion_tokens,total_tokens,cost_usd\n",
+    );
+    for entry in entries {
+        let row = [
+            format_timestamp((entry.timestamp.max(0) / 1000) as u64),
+            csv_field(&entry.chat_id),
+            csv_field(entry.user_id.as_deref().unwrap_or_default()),
+            entry.model.to_string(),
+            csv_field(entry.persona.as_deref().unwrap_or_default()),
+            entry.prompt_tokens.to_string(),
+            entry.completion_tokens.to_string(),
+            (entry.prompt_tokens + entry.completion_tokens).to_string(),
+            format!("{:.6}", entry.cost_usd),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes a field if it contains a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PriceConfig;
+
+    fn usage(prompt_tokens: i64, completion_tokens: i64) -> Usage {
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+
+    fn entry(chat_id: &str, timestamp: i64, persona: Option<&str>) -> LedgerEntry {
+        LedgerEntry {
+            timestamp,
+            ..LedgerEntry::new(
+                chat_id,
+                Some("U1"),
+                Model::Gpt_4,
+                persona,
+                &usage(10, 5),
+                &Prices::default(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_accumulates_per_chat() {
+        let store = UsageStore::new();
+        store
+            .record(LedgerEntry::new(
+                "U1",
+                Some("U1"),
+                Model::Gpt_4,
+                None,
+                &usage(10, 5),
+                &Prices::default(),
+            ))
+            .await
+            .unwrap();
+        store
+            .record(LedgerEntry::new(
+                "U1",
+                Some("U1"),
+                Model::Gpt_4,
+                None,
+                &usage(10, 5),
+                &Prices::default(),
+            ))
+            .await
+            .unwrap();
+
+        let chat = store.get("U1").await;
+        assert_eq!(chat.requests, 2);
+        assert_eq!(chat.total_tokens(), 30);
+        // 20 prompt and 10 completion tokens of gpt-4
+        assert!((chat.cost_usd - 0.0012).abs() < 1e-9);
+        assert_eq!(store.get("C1").await, ChatUsage::default());
+    }
+
+    #[test]
+    fn test_prices_default_to_list_prices() {
+        let mut config = UsageConfig::default();
+        config.prices.insert(
+            "gpt-4".to_string(),
+            PriceConfig {
+                prompt: Some(0.01),
+                completion: None,
+            },
+        );
+        let prices = Prices::from_config(&config);
+        assert_eq!(prices.per_1k_tokens(Model::Gpt_4), (0.01, 0.06));
+        assert_eq!(prices.per_1k_tokens(Model::Gpt_4_32k), (0.06, 0.12));
+        // 1,000 prompt and 500 completion tokens
+        let cost = cost_usd(&prices, Model::Gpt_4, &usage(1000, 500));
+        assert!((cost - 0.04).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_query_and_report() {
+        let store = UsageStore::new();
+        store
+            .record(entry("C1", 1_000, Some("teacher")))
+            .await
+            .unwrap();
+        store.record(entry("C1", 2_000, None)).await.unwrap();
+        store.record(entry("U2", 3_000, None)).await.unwrap();
+
+        let query = LedgerQuery {
+            from: Some(2_000),
+            to: Some(3_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            store.query(&query).await.unwrap(),
+            vec![entry("C1", 2_000, None)]
+        );
+        let query = LedgerQuery {
+            chat_id: Some("C1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).await.unwrap().len(), 2);
+
+        let report = UsageReport::new(&store.query(&LedgerQuery::default()).await.unwrap());
+        assert_eq!(report.total.requests, 3);
+        assert_eq!(report.by_model["gpt-4"].requests, 3);
+        assert_eq!(report.by_persona["teacher"].requests, 1);
+        assert_eq!(report.by_persona["none"].requests, 2);
+        assert_eq!(report.by_chat["U2"].total_tokens(), 15);
+    }
+
+    #[tokio::test]
+    async fn test_ledger_file_survives_restart() {
+        let path =
+            std::env::temp_dir().join(format!("line_botx_ledger_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let store = UsageStore::load(Arc::new(MemoryStorage::new()), path, None)
+            .await
+            .unwrap();
+        store
+            .record(entry("C1", 1_000, Some("teacher")))
+            .await
+            .unwrap();
+        store.record(entry("U2", 2_000, None)).await.unwrap();
+
+        let restarted = UsageStore::load(Arc::new(MemoryStorage::new()), path, None)
+            .await
+            .unwrap();
+        assert_eq!(restarted.get("C1").await.requests, 1);
+        assert_eq!(
+            restarted
+                .query(&LedgerQuery::default())
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // A storage that keeps its own ledger is not given the file twice
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        storage
+            .record_usage(&entry("C1", 3_000, None))
+            .await
+            .unwrap();
+        let persistent = UsageStore::load(storage, path, None).await.unwrap();
+        assert_eq!(
+            persistent
+                .query(&LedgerQuery::default())
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ledger_file_is_pruned() {
+        let path = std::env::temp_dir().join(format!(
+            "line_botx_ledger_pruned_{}.jsonl",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let store = UsageStore::load(Arc::new(MemoryStorage::new()), path, None)
+            .await
+            .unwrap();
+        for timestamp in [1_000, 2_000, 3_000] {
+            store.record(entry("C1", timestamp, None)).await.unwrap();
+        }
+        assert_eq!(store.prune_file(2_000).await.unwrap(), 1);
+        assert_eq!(store.prune_file(2_000).await.unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(path).unwrap().lines().count(), 2);
+
+        // Nor are expired entries read back, though the file was not pruned yet
+        let restarted = UsageStore::load(Arc::new(MemoryStorage::new()), path, Some(3_000))
+            .await
+            .unwrap();
+        let entries = restarted.query(&LedgerQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].timestamp, 3_000);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let mut quoted = entry("C1", 951_782_400_000, Some("pirate, arr"));
+        quoted.cost_usd = 0.00075;
+        let csv = to_csv(&[quoted]);
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("timestamp,chat_id,user_id,model,persona,prompt_tokens,completion_tokens,total_tokens,cost_usd")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("2000-02-29T00:00:00Z,C1,U1,gpt-4,\"pirate, arr\",10,5,15,0.000750")
+        );
+        assert_eq!(lines.next(), None);
+    }
+}
@@ -1,124 +1,1260 @@
-use std::fmt::Display;
-use std::sync::Mutex;
+use std::time::Instant;
 
 use actix_web::{post, web, web::Data, HttpResponse};
-use opentelemetry::propagation::TextMapPropagator;
-use opentelemetry::trace::Tracer;
-use serde_derive::{Deserialize, Serialize};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use opentelemetry::trace::{FutureExt, TraceContextExt};
+use opentelemetry::Context;
+use sha2::Sha256;
 use tracing::{error, info};
 use tracing_attributes::instrument;
 
 use crate::bot::LineBot;
-use crate::events::messages::MessageType;
-use crate::events::{EventType, Events};
-use crate::messages::{SendMessageType, TextMessage};
-use crate::openai::client::{ChatGPTClient, ChatInput, Message};
-use crate::openai::models::{Model, Role};
+use crate::commands::{execute, Command, CommandContext};
+use crate::conversation::{ConversationStore, Turn};
+use crate::documents::{load_document, DocumentError, DocumentStore};
+use crate::events;
+use crate::events::messages::{FileMessage, MessageType};
+use crate::events::source::SouceType;
+use crate::events::{EventType, Events, MessageEvent, PostBackEvent, Source};
+use crate::knowledge;
+use crate::knowledge::KnowledgeBase;
+use crate::limits::Limiter;
+use crate::loading::{expected_latency, with_loading_animation};
+use crate::messages::text_message_v2::escape_text;
+use crate::messages::{SendMessageType, TextMessage, TextMessageV2};
+use crate::metrics::BotMetrics;
+use crate::moderation::Stage;
+use crate::objects::{Profile, SentMessage, SentMessages};
+use crate::openai::client::{ChatGPTClient, Message};
+use crate::openai::models::Role;
+use crate::openai::moderations::ModerationsClient;
+use crate::openai::tokenizer::count_tokens;
+use crate::personas::PromptVars;
+use crate::privacy::Stores;
+use crate::profiles::ProfileCache;
+use crate::redaction::Redactions;
+use crate::reload::RuntimeConfig;
+use crate::rich_menus::{parse_mode_postback, RichMenuRegistry};
+use crate::settings::{SettingsStore, DEFAULT_MODEL};
+use crate::state::AppState;
 use crate::support::signature::Signature;
+use crate::traces;
+use crate::usage::{LedgerEntry, UsageStore};
 
 /// Signature validator
 /// # Note
 /// The signature in the `x-line-signature` request header must be verified to confirm that the request was sent from the LINE Platform. [\[detail\]](https://developers.line.biz/en/reference/messaging-api/#signature-validation)
 /// # Example
-/// ```
-/// if webhook::validate_signature(channel_secret, signature, body) {
+/// ```ignore
+/// if validate_signature(channel_secret, signature, body) {
 ///     // OK
 /// } else {
 ///     // NG
 /// }
 /// ```
+fn validate_signature(channel_secret: &str, signature: &str, body: &[u8]) -> bool {
+    let Ok(signature) = STANDARD.decode(signature) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(channel_secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Tokens kept free for the answer when fitting context into the model window,
+/// unless the generation parameters cap the answer length.
+const RESERVED_ANSWER_TOKENS: usize = 1024;
 
-/*
-fn validate_signature(channel_secret: &str, signature: &str, body: &str) -> bool {
-    type HmacSha256 = Hmac<Sha256>;
+#[instrument(skip(signature, body, state))]
+#[post("/v1/line/webhook")]
+pub async fn callback(
+    signature: Signature,
+    body: web::Bytes,
+    state: Data<AppState>,
+) -> HttpResponse {
+    if !validate_signature(&state.bot.channel_secret, &signature.key, &body) {
+        info!("invalid signature");
+        return HttpResponse::Unauthorized().finish();
+    }
+    let data: Events = match serde_json::from_slice(&body) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Invalid webhook body: {}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    // One configuration for the whole batch, even if it is reloaded meanwhile
+    let runtime = state.live.get();
 
-    let mut mac = HmacSha256::new(channel_secret.as_bytes());
-    mac.input(body.as_bytes());
+    let ctx = EventContext {
+        bot: &state.bot,
+        chat_gpt: &state.chat_gpt,
+        moderations: &state.moderations,
+        bot_user_id: state.bot_user_id.as_deref(),
+        documents: &state.documents,
+        knowledge: state.knowledge.as_ref(),
+        rich_menus: state.rich_menus.as_ref(),
+        runtime: &runtime,
+        settings: &state.settings,
+        conversations: &state.conversations,
+        profiles: &state.profiles,
+        usage: &state.usage,
+        limiter: &state.limiter,
+        metrics: &state.metrics,
+    };
+    // A redelivered event was handled already, by this replica or another one
+    let mut events = Vec::with_capacity(data.events.len());
+    for event in &data.events {
+        match &event.webhook_event_id {
+            Some(id) if !state.dedup.first_delivery(id).await => info!("redelivered : {}", id),
+            _ => events.push(event),
+        }
+    }
+    ctx.metrics
+        .events_received(events.iter().map(|event| &event.r#type));
 
-    encode(&mac.result().code().to_vec()) == signature
+    // Chats are handled concurrently, the events of one chat in order
+    let mut chats: Vec<(Option<&str>, Vec<&EventType>)> = Vec::new();
+    for event in events {
+        let chat = source_of(&event.r#type).map(Source::id);
+        match chats.iter_mut().find(|(id, _)| id.is_some() && *id == chat) {
+            Some((_, events)) => events.push(&event.r#type),
+            None => chats.push((chat, vec![&event.r#type])),
+        }
+    }
+    let ctx = &ctx;
+    join_all(chats.iter().map(|(_, events)| async move {
+        for event in events {
+            // One trace per event
+            let cx = traces::event_context(event);
+            handle_event(ctx, event).with_context(cx.clone()).await;
+            cx.span().end();
+            ctx.metrics.event_handled();
+        }
+    }))
+    .await;
+    HttpResponse::Ok().finish()
 }
-*/
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct LineKeys {
-    pub channel_secret: String,
-    pub access_token: String,
-    pub chat_gpt_api_key: String,
-    pub chat_gpt_max_tokens: Option<i32>,
-    pub chat_gpt_temperature: Option<f32>,
-    pub line_chat_prompt: String,
+/// Services shared by the handlers of one webhook call.
+struct EventContext<'a> {
+    bot: &'a LineBot,
+    chat_gpt: &'a ChatGPTClient,
+    moderations: &'a ModerationsClient,
+    bot_user_id: Option<&'a str>,
+    documents: &'a DocumentStore,
+    knowledge: Option<&'a KnowledgeBase>,
+    rich_menus: Option<&'a RichMenuRegistry>,
+    runtime: &'a RuntimeConfig,
+    settings: &'a SettingsStore,
+    conversations: &'a ConversationStore,
+    profiles: &'a ProfileCache,
+    usage: &'a UsageStore,
+    limiter: &'a Limiter,
+    metrics: &'a BotMetrics,
 }
 
-#[instrument(skip(config, _signature, _bytes))]
-#[post("/v1/line/webhook")]
-pub async fn callback(
-    _signature: Signature,
-    data: web::Json<Events>,
-    config: Data<Mutex<LineKeys>>,
-    _bytes: web::Bytes,
-) -> HttpResponse {
-    let config = config.lock().unwrap();
+impl<'a> EventContext<'a> {
+    fn stores(&self) -> Stores<'a> {
+        Stores {
+            conversations: self.conversations,
+            settings: self.settings,
+            documents: self.documents,
+            profiles: self.profiles,
+            usage: self.usage,
+        }
+    }
+}
 
-    // LineBot
-    let bot = LineBot::new(
-        &config.channel_secret.as_str(),
-        config.access_token.as_str(),
-    );
+/// The chat an event comes from, for the events the bot handles.
+fn source_of(event: &EventType) -> Option<&Source> {
+    match event {
+        EventType::MessageEvent(message_event) => Some(&message_event.source),
+        EventType::PostBackEvent(postback_event) => Some(&postback_event.source),
+        EventType::FollowEvent(follow_event) => Some(&follow_event.source),
+        EventType::JoinEvent(join_event) => Some(&join_event.source),
+        _ => None,
+    }
+}
 
-    //let body: &str = &String::from_utf8(bytes.to_vec()).unwrap();
-    //validate_signature(&bot.channel_secret, &signature.key, &body);
+async fn handle_event(ctx: &EventContext<'_>, event: &EventType) {
+    if let Some(source) = source_of(event) {
+        if !ctx.runtime.access.allows(source) {
+            info!("not allowed : {}", source.id());
+            return;
+        }
+    }
+    match event {
+        EventType::MessageEvent(message_event) => match &message_event.message.r#type {
+            MessageType::TextMessage(text_message) => {
+                handle_text_message(ctx, message_event, text_message).await
+            }
+            MessageType::FileMessage(file_message) => {
+                handle_file_message(ctx.bot, ctx.documents, message_event, file_message).await
+            }
+            _ => {}
+        },
+        EventType::PostBackEvent(postback_event) => {
+            if let Some(rich_menus) = ctx.rich_menus {
+                handle_postback(ctx.bot, rich_menus, postback_event).await
+            }
+        }
+        // Added as a friend, or unblocked
+        EventType::FollowEvent(follow_event) => {
+            info!("followed : {}", follow_event.source.id());
+            match &follow_event.reply_token {
+                Some(reply_token) => greet_friend(ctx, &follow_event.source, reply_token).await,
+                None => info!("no reply token : {}", follow_event.source.id()),
+            }
+        }
+        EventType::JoinEvent(join_event) => {
+            info!("joined : {}", join_event.source.id());
+            if let Some(welcome) = &ctx.runtime.welcome {
+                let greeting = welcome.for_language(None);
+                let message = greeting.join_message(&ctx.runtime.chat_prompt);
+                reply_returning_sent(ctx.bot, &join_event.reply_token, message).await;
+            }
+        }
+        // Blocked by a user, or removed from a group or room
+        EventType::UnFollowEvent(unfollow_event) => {
+            info!("unfollowed : {}", unfollow_event.source.id());
+            ctx.stores().forget(unfollow_event.source.id()).await;
+        }
+        EventType::LeaveEvent(leave_event) => {
+            info!("left : {}", leave_event.source.id());
+            ctx.stores().forget(leave_event.source.id()).await;
+        }
+        _ => {}
+    }
+}
 
-    for event in &data.events {
-        // MessageEvent only
-        if let EventType::MessageEvent(message_event) = &event.r#type {
-            // TextMessageEvent only
-            if let MessageType::TextMessage(text_message) = &message_event.message.r#type {
-                // Create TextMessage
-                info!("message : {}", text_message.text);
-                // Reply message with reply_token
-                let prompt = &config.line_chat_prompt;
-                if text_message.text.contains(/*"Nick:>"*/ prompt) {
-                    let message = text_message.text.clone();
-                    let message = message.replace(&prompt.as_str(), ""); //remove prompt
-                    let api_key = &config.chat_gpt_api_key;
-                    let client = ChatGPTClient::new(&api_key, "https://api.openai.com");
-                    let messages = vec![Message {
-                        role: Role::User,
-                        content: message.trim().to_string(),
-                    }];
-                    // Define the input for the ChatGPTClient
-                    let input = ChatInput {
-                        model: Model::Gpt3_5Turbo,  // Set the GPT-3.5 Turbo model
-                        messages: messages.clone(), // Pass in the messages vector
-                        ..Default::default()
-                    };
-                    let response = client.chat(input).await;
-                    match response {
-                        Ok(response) => {
-                            for message in response.choices {
-                                let content = message.message.content;
-                                let message = SendMessageType::TextMessage(TextMessage {
-                                    text: content.trim().to_string(),
-                                    emojis: None,
-                                });
-                                //reply message to Line
-                                let res = bot
-                                    .reply_message(&message_event.reply_token, vec![message])
-                                    .await;
-                                if let Err(e) = res {
-                                    error!("Error: {}", e);
-                                    HttpResponse::InternalServerError().finish();
-                                }
-                            }
+async fn handle_text_message(
+    ctx: &EventContext<'_>,
+    message_event: &MessageEvent,
+    text_message: &events::messages::TextMessage,
+) {
+    let EventContext {
+        bot,
+        chat_gpt,
+        moderations,
+        bot_user_id,
+        documents,
+        knowledge,
+        runtime,
+        settings,
+        conversations,
+        profiles,
+        usage,
+        limiter,
+        metrics,
+        ..
+    } = *ctx;
+    let personas = runtime.personas.as_ref();
+    // Create TextMessage, whose text may hold personal data and is never logged
+    info!("message : {} chars", text_message.text.chars().count());
+    let source = &message_event.source;
+    let in_group = !matches!(source.r#type, SouceType::User(_));
+
+    // Reply message with reply_token
+    let prompt = &runtime.chat_prompt;
+    let mentioned = in_group && bot_user_id.is_some_and(|id| text_message.mentions(id));
+    let question = match bot_user_id {
+        Some(bot_user_id) if mentioned => text_message.text_without_mentions_of(bot_user_id),
+        _ if text_message.text.contains(/*"Nick:>"*/ prompt) => {
+            text_message.text.replace(prompt.as_str(), "") //remove prompt
+        }
+        // Commands need no prompt in 1:1 chats
+        _ if !in_group && text_message.text.trim_start().starts_with('/') => {
+            text_message.text.clone()
+        }
+        _ => return,
+    };
+    let question = question.trim().to_string();
+
+    if in_group {
+        // "@bot on" / "@bot off" switches the bot for the whole group
+        if mentioned {
+            let enabled = match question.to_lowercase().as_str() {
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => None,
+            };
+            if let Some(enabled) = enabled {
+                settings.update(source.id(), |s| s.enabled = enabled).await;
+                let text = if enabled {
+                    "I'm back! Mention me to ask a question."
+                } else {
+                    "OK, I'll stay quiet here. Mention me with \"on\" to wake me up."
+                };
+                reply_text(bot, &message_event.reply_token, text).await;
+                return;
+            }
+        }
+        if !settings.get(source.id()).await.enabled {
+            return;
+        }
+    }
+
+    if let Some(command) = Command::parse(&question) {
+        info!("command : {:?}", command);
+        if command == Command::Export {
+            if let Err(text) = push_export(ctx, source).await {
+                reply_text(bot, &message_event.reply_token, text).await;
+                return;
+            }
+        }
+        let names = personas.map(|p| p.names()).unwrap_or_default();
+        let ctx = CommandContext {
+            chat_id: source.id(),
+            settings,
+            conversations,
+            usage,
+            personas: &names,
+        };
+        let reply = execute(&command, &ctx).await;
+        reply_returning_sent(bot, &message_event.reply_token, reply).await;
+        return;
+    }
+
+    // Both the chat and, in groups, the asker are limited. Admins never are
+    let mut limit_keys = vec![source.id()];
+    if let Some(user_id) = source.user_id().filter(|id| *id != source.id()) {
+        limit_keys.push(user_id);
+    }
+    let is_admin = source
+        .user_id()
+        .is_some_and(|id| runtime.admins.iter().any(|admin| admin == id));
+    if !is_admin {
+        if let Err(exceeded) = limiter.check(&limit_keys, &runtime.limits).await {
+            info!("limited : {} {:?}", source.id(), exceeded);
+            reply_text(bot, &message_event.reply_token, &exceeded.to_string()).await;
+            return;
+        }
+    }
+
+    // What the chat chose wins over its persona
+    let chat_settings = settings.get(source.id()).await;
+    let persona = personas.and_then(|p| p.for_chat(&chat_settings));
+    let model = chat_settings
+        .model
+        .or(persona.and_then(|p| p.model))
+        .unwrap_or(DEFAULT_MODEL);
+    let mut params = match persona {
+        Some(persona) => runtime.generation.merge(&persona.params),
+        None => runtime.generation.clone(),
+    };
+    if chat_settings.temperature.is_some() {
+        params.temperature = chat_settings.temperature;
+    }
+
+    // Personal data never reaches OpenAI, the stored turns keep it
+    let redactor = &runtime.redaction;
+    let mut redactions = Redactions::default();
+    let redacted_question = redactor.redact(&question, &mut redactions);
+
+    let moderator = &runtime.moderation;
+    let flags = moderator.check(moderations, &redacted_question).await;
+    if !flags.is_empty() {
+        let (chat_id, user_id) = (source.id(), source.user_id());
+        moderator.review(chat_id, user_id, Stage::Input, &flags, &redacted_question);
+        metrics.flagged(Stage::Input, &flags);
+        let text = moderator.response(Stage::Input);
+        reply_text(bot, &message_event.reply_token, text).await;
+        return;
+    }
+
+    let mut messages = Vec::new();
+    let reserved = params.max_tokens.unwrap_or(RESERVED_ANSWER_TOKENS);
+    let mut used_tokens = reserved + count_tokens(&redacted_question);
+    // The persona leads every conversation
+    if let Some(persona) = persona {
+        let mut vars = PromptVars::default();
+        if persona.uses_profile() {
+            if let Some(profile) = sender_profile(bot, profiles, source).await {
+                vars.display_name = profile.display_name;
+                vars.language = profile.language;
+            }
+        }
+        let content = persona.system_prompt(&vars);
+        used_tokens += count_tokens(&content);
+        messages.push(Message {
+            role: Role::System,
+            content,
+        });
+    }
+    // Passages from the internal knowledge base, cited in the reply
+    let mut passages = Vec::new();
+    if let Some(knowledge) = knowledge {
+        match knowledge.retrieve(&redacted_question).await {
+            Ok(retrieved) => passages = retrieved,
+            Err(e) => error!("Error: {}", e),
+        }
+    }
+    let knowledge_prompt = (!passages.is_empty()).then(|| knowledge::system_prompt(&passages));
+    used_tokens += knowledge_prompt.as_deref().map_or(0, count_tokens);
+    // The earlier message the user is replying to, if we still have it
+    let quoted_message_id = text_message.quoted_message_id.as_deref();
+    let mut quoted = None;
+    if let Some(quoted_message_id) = quoted_message_id {
+        if let Some(turn) = conversations.find(source.id(), quoted_message_id).await {
+            let mut message = turn.to_message();
+            message.content = redactor.redact(&message.content, &mut redactions);
+            used_tokens += count_tokens(&message.content);
+            quoted = Some(message);
+        }
+    }
+    // Ground the answer in the document uploaded to this chat, if any, in what is left
+    if let Some(document) = documents.get(message_event.source.id()) {
+        let instructions = count_tokens(&document.system_prompt(""));
+        let budget = model
+            .max_tokens()
+            .saturating_sub(used_tokens + instructions);
+        let excerpt = document.excerpt(&question, budget);
+        let content = redactor.redact(&document.system_prompt(&excerpt), &mut redactions);
+        used_tokens += count_tokens(&content);
+        messages.push(Message {
+            role: Role::System,
+            content,
+        });
+    }
+    if let Some(content) = knowledge_prompt {
+        messages.push(Message {
+            role: Role::System,
+            content,
+        });
+    }
+    // The conversation so far, as much of its end as fits
+    let budget = model.max_tokens().saturating_sub(used_tokens);
+    for turn in conversations.recent(source.id(), budget).await {
+        if quoted.is_some() && turn.message_id.as_deref() == quoted_message_id {
+            continue;
+        }
+        let mut message = turn.to_message();
+        message.content = redactor.redact(&message.content, &mut redactions);
+        messages.push(message);
+    }
+    messages.extend(quoted);
+    messages.push(Message {
+        role: Role::User,
+        content: redacted_question,
+    });
+    if !redactions.is_empty() {
+        info!("redacted : {} {:?}", source.id(), redactions.counts());
+        metrics.redactions(&redactions);
+    }
+    conversations
+        .push(
+            source.id(),
+            Turn::new(Role::User, &question, Some(&text_message.id)),
+        )
+        .await;
+
+    // Define the input for the ChatGPTClient
+    let input = params.chat_input(model, messages, source.user_id());
+    let expected = expected_latency(input.model, input.max_tokens);
+    let completion = async {
+        let started = Instant::now();
+        let response = chat_gpt.chat(input).await;
+        let usage = response.as_ref().ok().map(|r| &r.usage);
+        metrics.completion(model, started.elapsed(), usage);
+        response
+    };
+    let response = with_loading_animation(bot, &message_event.source, expected, completion).await;
+    match response {
+        Ok(response) => {
+            let entry = LedgerEntry::new(
+                source.id(),
+                source.user_id(),
+                model,
+                persona.map(|p| p.name.as_str()),
+                &response.usage,
+                &runtime.prices,
+            );
+            if let Err(e) = usage.record(entry).await {
+                error!("Error: {}", e);
+            }
+            if let Err(e) = limiter
+                .record(&limit_keys, model, &response.usage, &runtime.prices)
+                .await
+            {
+                error!("Error: {}", e);
+            }
+            for message in response.choices {
+                let answer = message.message.content.trim();
+                let flags = moderator.check(moderations, answer).await;
+                let mut content = if flags.is_empty() {
+                    redactor.restore(answer, &redactions)
+                } else {
+                    let (chat_id, user_id) = (source.id(), source.user_id());
+                    moderator.review(chat_id, user_id, Stage::Output, &flags, answer);
+                    metrics.flagged(Stage::Output, &flags);
+                    moderator.response(Stage::Output).to_string()
+                };
+                if !passages.is_empty() && flags.is_empty() {
+                    content = format!("{}\n\n{}", content, knowledge::citations(&passages));
+                }
+                // Groups get the question quoted, natively when LINE gave a quote token,
+                // and the asker @mentioned
+                let quote_token = text_message.quote_token.as_deref();
+                let sent = if in_group {
+                    if quote_token.is_none() {
+                        content = quote_asker(&question, &content);
+                    }
+                    match source.user_id() {
+                        Some(user_id) => {
+                            reply_mentioning(
+                                bot,
+                                &message_event.reply_token,
+                                user_id,
+                                &content,
+                                quote_token,
+                            )
+                            .await
                         }
-                        Err(e) => {
-                            error!("Error: {}", e);
-                            HttpResponse::InternalServerError().finish();
+                        None => {
+                            reply_text_quoting(
+                                bot,
+                                &message_event.reply_token,
+                                &content,
+                                quote_token,
+                            )
+                            .await
                         }
                     }
-                }
+                } else {
+                    reply_text(bot, &message_event.reply_token, &content).await
+                };
+                conversations
+                    .push(
+                        source.id(),
+                        Turn::new(
+                            Role::Assistant,
+                            &content,
+                            sent.as_ref().map(|m| m.id.as_str()),
+                        ),
+                    )
+                    .await;
             }
         }
+        Err(e) => {
+            error!("Error: {}", e);
+        }
+    }
+}
+
+async fn handle_file_message(
+    bot: &LineBot,
+    documents: &DocumentStore,
+    message_event: &MessageEvent,
+    file_message: &FileMessage,
+) {
+    info!(
+        "file : {} ({} bytes)",
+        file_message.file_name, file_message.file_size
+    );
+    match load_document(bot, file_message).await {
+        Ok(document) => {
+            let text = format!(
+                "I've read \"{}\". Ask me about it during the next {} minutes.",
+                document.file_name,
+                documents.ttl().as_secs() / 60
+            );
+            documents.insert(message_event.source.id(), document);
+            reply_text(bot, &message_event.reply_token, &text).await;
+        }
+        // Not a document, e.g. a zip file shared in a group
+        Err(DocumentError::Unsupported(_)) => {}
+        Err(e) => {
+            error!("Error: {}", e);
+            let text = format!("Sorry, I couldn't read \"{}\".", file_message.file_name);
+            reply_text(bot, &message_event.reply_token, &text).await;
+        }
+    }
+}
+
+async fn handle_postback(
+    bot: &LineBot,
+    rich_menus: &RichMenuRegistry,
+    postback_event: &PostBackEvent,
+) {
+    // Mode switch from the rich menu, e.g. `mode=translate`
+    let Some(mode) = parse_mode_postback(&postback_event.postback.data) else {
+        return;
+    };
+    let Some(user_id) = postback_event.source.user_id() else {
+        return;
+    };
+    match rich_menus.switch_mode(bot, user_id, mode).await {
+        Ok(true) => {
+            let text = format!("Switched to {} mode.", mode);
+            reply_text(bot, &postback_event.reply_token, &text).await;
+        }
+        Ok(false) => info!("unknown mode : {}", mode),
+        Err(e) => error!("Error: {}", e),
+    }
+}
+
+/// Greets a new friend by name, in the language of their profile.
+async fn greet_friend(ctx: &EventContext<'_>, source: &Source, reply_token: &str) {
+    let Some(welcome) = &ctx.runtime.welcome else {
+        return;
+    };
+    let profile = sender_profile(ctx.bot, ctx.profiles, source).await;
+    let profile = profile.as_ref();
+    let greeting = welcome.for_language(profile.and_then(|p| p.language.as_deref()));
+    let display_name = profile.and_then(|p| p.display_name.as_deref());
+    let message = greeting.follow_message(display_name, &ctx.runtime.chat_prompt);
+    reply_returning_sent(ctx.bot, reply_token, message).await;
+}
+
+/// Pushes to the asker everything stored about them. The error is the reply to send.
+async fn push_export(ctx: &EventContext<'_>, source: &Source) -> Result<(), &'static str> {
+    let Some(user_id) = source.user_id() else {
+        return Err("I can't tell who you are here. Send /export to me in a private chat.");
+    };
+    let export = ctx.stores().export(user_id).await.map_err(|e| {
+        error!("Error: {}", e);
+        "Sorry, I couldn't gather your data. Please try again later."
+    })?;
+    let mut messages = export.to_messages();
+    while !messages.is_empty() {
+        // LINE takes up to 5 messages per push
+        let batch = messages.drain(..messages.len().min(5)).collect();
+        let res = ctx.bot.push_message(user_id, batch).await;
+        if let Err(e) = res.and_then(|res| res.error_for_status()) {
+            error!("Error: {}", e);
+            return Err("Sorry, I couldn't send you your data. Add me as a friend and try again.");
+        }
+    }
+    Ok(())
+}
+
+/// Profile of the user who sent an event, looked up in the group or room it came from.
+async fn sender_profile(
+    bot: &LineBot,
+    profiles: &ProfileCache,
+    source: &Source,
+) -> Option<Profile> {
+    let user_id = source.user_id()?;
+    if let Some(profile) = profiles.get(source.id(), user_id) {
+        return Some(profile);
+    }
+    let res = match &source.r#type {
+        SouceType::User(user) => bot.get_profile(&user.user_id).await,
+        SouceType::Group(group) => {
+            bot.get_group_member_profile(&group.group_id, group.user_id.as_deref()?)
+                .await
+        }
+        SouceType::Room(room) => {
+            bot.get_room_member_profile(&room.room_id, room.user_id.as_deref()?)
+                .await
+        }
+    };
+    let profile = res.map_err(|e| error!("Error: {}", e)).ok()?;
+    profiles.insert(source.id(), user_id, profile.clone());
+    Some(profile)
+}
+
+/// Prefixes a group answer with the question it answers, so busy groups can follow.
+fn quote_asker(question: &str, answer: &str) -> String {
+    const QUOTE_CHARS: usize = 60;
+    let mut quote: String = question.chars().take(QUOTE_CHARS).collect();
+    if question.chars().count() > QUOTE_CHARS {
+        quote.push('…');
+    }
+    format!("“{quote}”\n\n{answer}")
+}
+
+async fn reply_text(bot: &LineBot, reply_token: &str, text: &str) -> Option<SentMessage> {
+    reply_text_quoting(bot, reply_token, text, None).await
+}
+
+/// Replies with `text`, quoting the message of `quote_token` if given.
+/// Returns the message LINE created, whose id later replies can quote.
+async fn reply_text_quoting(
+    bot: &LineBot,
+    reply_token: &str,
+    text: &str,
+    quote_token: Option<&str>,
+) -> Option<SentMessage> {
+    let message = SendMessageType::TextMessage(TextMessage {
+        text: text.trim().to_string(),
+        emojis: None,
+        quote_token: quote_token.map(str::to_string),
+        quick_reply: None,
+    });
+    reply_returning_sent(bot, reply_token, message).await
+}
+
+/// Replies with `text` preceded by an @mention of `user_id`.
+async fn reply_mentioning(
+    bot: &LineBot,
+    reply_token: &str,
+    user_id: &str,
+    text: &str,
+    quote_token: Option<&str>,
+) -> Option<SentMessage> {
+    let message = TextMessageV2::new(&format!("{{asker}} {}", escape_text(text.trim())))
+        .with_mention("asker", user_id)
+        .with_quote_token(quote_token);
+    if let Err(e) = message.validate() {
+        error!("Error: {}", e);
+        return reply_text_quoting(bot, reply_token, text, quote_token).await;
+    }
+    reply_returning_sent(bot, reply_token, SendMessageType::TextMessageV2(message)).await
+}
+
+async fn reply_returning_sent(
+    bot: &LineBot,
+    reply_token: &str,
+    message: SendMessageType,
+) -> Option<SentMessage> {
+    //reply message to Line, under the trace of the event being handled
+    let res = match bot
+        .reply_message_with_context(reply_token, vec![message], Context::current())
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => {
+            error!("Error: {}", e);
+            return None;
+        }
+    };
+    let status = res.status();
+    let res = match res.error_for_status() {
+        Ok(res) => res,
+        Err(e) => {
+            error!("Reply refused with {}: {}", status, e);
+            return None;
+        }
+    };
+    match res.json::<SentMessages>().await {
+        Ok(sent) => sent.sent_messages.into_iter().next(),
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use actix_web::http::header::ContentType;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{App, HttpRequest, HttpServer};
+    use opentelemetry::trace::SpanKind;
+    use opentelemetry::Value;
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::{AdminConfig, Config};
+    use crate::dedup::Deduplicator;
+    use crate::metrics::HTTP_STATUS;
+    use crate::reload::LiveConfig;
+    use crate::traces::testing::InMemorySpans;
+    use crate::usage::UsageStore;
+
+    #[test]
+    fn test_quote_asker() {
+        assert_eq!(
+            quote_asker("What time is it?", "Noon."),
+            "“What time is it?”\n\nNoon."
+        );
+        let long = "x".repeat(70);
+        assert_eq!(
+            quote_asker(&long, "OK"),
+            format!("“{}…”\n\nOK", "x".repeat(60))
+        );
+    }
+
+    /// Latency of the mocked completions endpoint.
+    const COMPLETION_LATENCY: Duration = Duration::from_millis(50);
+
+    /// `traceparent` headers received by the mocked completions endpoint.
+    static TRACEPARENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Last user messages received by the mocked completions endpoint.
+    static QUESTIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Contents of all the messages of every request to the mocked completions endpoint.
+    static PROMPTS: Mutex<Vec<Vec<String>>> = Mutex::new(Vec::new());
+
+    /// Bodies received by the mocked reply endpoint.
+    static REPLIED: Mutex<Vec<serde_json::Value>> = Mutex::new(Vec::new());
+
+    /// Bodies received by the mocked push endpoint.
+    static PUSHED: Mutex<Vec<serde_json::Value>> = Mutex::new(Vec::new());
+
+    /// Starts mocks of the LINE and OpenAI APIs and returns their base URL.
+    pub(super) fn mock_apis() -> String {
+        async fn push(body: web::Json<serde_json::Value>) -> HttpResponse {
+            PUSHED.lock().unwrap().push(body.into_inner());
+            HttpResponse::Ok().json(json!({"sentMessages": [{"id": "2"}]}))
+        }
+        async fn reply(body: web::Json<serde_json::Value>) -> HttpResponse {
+            REPLIED.lock().unwrap().push(body.into_inner());
+            HttpResponse::Ok().json(json!({"sentMessages": [{"id": "1", "quoteToken": "q"}]}))
+        }
+        async fn completion(
+            request: HttpRequest,
+            body: web::Json<serde_json::Value>,
+        ) -> HttpResponse {
+            if let Some(traceparent) = request.headers().get("traceparent") {
+                let traceparent = traceparent.to_str().unwrap().to_string();
+                TRACEPARENTS.lock().unwrap().push(traceparent);
+            }
+            let prompt: Vec<String> = body["messages"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|message| message["content"].as_str().unwrap_or_default().to_string())
+                .collect();
+            let question = prompt.last().cloned().unwrap_or_default();
+            PROMPTS.lock().unwrap().push(prompt);
+            QUESTIONS.lock().unwrap().push(question.clone());
+            // "echo <text>" is answered with the text, "reverse <text>" with it reversed
+            let answer = match (
+                question.strip_prefix("echo "),
+                question.strip_prefix("reverse "),
+            ) {
+                (Some(text), _) => text.to_string(),
+                (_, Some(text)) => text.chars().rev().collect(),
+                _ => "Pong".to_string(),
+            };
+            actix_web::rt::time::sleep(COMPLETION_LATENCY).await;
+            HttpResponse::Ok().json(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "gpt-3.5-turbo",
+                "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15},
+                "choices": [{
+                    "message": {"role": "assistant", "content": answer},
+                    "finish_reason": "stop"
+                }]
+            }))
+        }
+        async fn profile(user_id: web::Path<String>) -> HttpResponse {
+            HttpResponse::Ok().json(json!({
+                "userId": user_id.into_inner(),
+                "displayName": "Somchai",
+                "language": "th"
+            }))
+        }
+        // Flags "violence" in anything mentioning a weapon
+        async fn moderation(body: web::Json<serde_json::Value>) -> HttpResponse {
+            let input = body["input"].as_str().unwrap_or_default();
+            let score = if input.contains("weapon") { 0.9 } else { 0.01 };
+            HttpResponse::Ok().json(json!({
+                "id": "modr-1",
+                "model": "omni-moderation-latest",
+                "results": [{
+                    "flagged": score > 0.5,
+                    "categories": {"violence": score > 0.5},
+                    "category_scores": {"violence": score}
+                }]
+            }))
+        }
+        let server = HttpServer::new(|| {
+            App::new()
+                .route("/v2/bot/message/reply", web::post().to(reply))
+                .route("/v1/moderations", web::post().to(moderation))
+                .route("/v2/bot/profile/{user_id}", web::get().to(profile))
+                .route("/v2/bot/message/push", web::post().to(push))
+                .route("/v1/chat/completions", web::post().to(completion))
+                .default_service(web::to(HttpResponse::Ok))
+        })
+        .workers(4)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{address}")
+    }
+
+    pub(super) fn mock_state(base_url: &str) -> Data<AppState> {
+        let mut config = Config::default();
+        config.line.chat_prompt = "Nick:>".to_string();
+        let runtime = RuntimeConfig::from_config(&config).unwrap();
+        let live = LiveConfig::new(runtime, move || Ok(config.clone()));
+        let line_url = format!("{base_url}/v2/bot");
+        Data::new(AppState {
+            bot: LineBot::with_base_urls("secret", "token", &line_url, &line_url),
+            chat_gpt: ChatGPTClient::new("key", base_url),
+            moderations: ModerationsClient::new("key", base_url, "omni-moderation-latest"),
+            bot_user_id: None,
+            live: Arc::new(live),
+            admin: AdminConfig::default(),
+            documents: DocumentStore::new(Duration::from_secs(60)),
+            knowledge: None,
+            rich_menus: None,
+            settings: SettingsStore::new(),
+            conversations: ConversationStore::default(),
+            usage: Arc::new(UsageStore::new()),
+            limiter: Limiter::new(),
+            dedup: Deduplicator::default(),
+            profiles: ProfileCache::new(),
+            metrics: BotMetrics::global(),
+        })
+    }
+
+    /// A webhook call with `body`, signed with the channel secret of `mock_state`.
+    pub(super) fn webhook_request(body: serde_json::Value) -> TestRequest {
+        let body = body.to_string();
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(body.as_bytes());
+        let signature = STANDARD.encode(mac.finalize().into_bytes());
+        TestRequest::post()
+            .uri("/v1/line/webhook")
+            .insert_header(("x-line-signature", signature))
+            .insert_header(ContentType::json())
+            .set_payload(body)
+    }
+
+    pub(super) fn text_event(user_id: &str) -> serde_json::Value {
+        json!({
+            "type": "message",
+            "replyToken": format!("reply-{user_id}"),
+            "mode": "active",
+            "timestamp": 0,
+            "source": {"type": "user", "userId": user_id},
+            "message": {"type": "text", "id": format!("m-{user_id}"), "text": "Nick:> ping"}
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_event_trace() {
+        let spans = InMemorySpans::global();
+        let state = mock_state(&mock_apis());
+        let app = init_service(App::new().app_data(Data::clone(&state)).service(callback)).await;
+        let request =
+            webhook_request(json!({"destination": "Ubot", "events": [text_event("Utrace")]}))
+                .to_request();
+        assert!(call_service(&app, request).await.status().is_success());
+
+        let spans = spans.spans();
+        let event = spans
+            .iter()
+            .find(|s| s.name == "line.event message")
+            .expect("event span");
+        assert_eq!(event.span_kind, SpanKind::Consumer);
+        let trace_id = event.span_context.trace_id();
+        let children: Vec<_> = spans
+            .iter()
+            .filter(|s| s.span_context.trace_id() == trace_id)
+            .collect();
+        let attribute = |name: &str, key: &str| -> Option<Value> {
+            let span = children.iter().find(|s| s.name == name)?;
+            assert_eq!(span.parent_span_id, event.span_context.span_id());
+            span.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.clone())
+        };
+
+        assert_eq!(
+            attribute("chat gpt-3.5-turbo", traces::INPUT_TOKENS),
+            Some(Value::I64(10))
+        );
+        assert_eq!(
+            attribute("chat gpt-3.5-turbo", traces::FINISH_REASONS).map(|v| v.to_string()),
+            Some(r#"["stop"]"#.to_string())
+        );
+        assert_eq!(attribute("LINE POST", HTTP_STATUS), Some(Value::I64(200)));
+        let traceparents = TRACEPARENTS.lock().unwrap();
+        assert!(traceparents
+            .iter()
+            .any(|t| t.contains(&trace_id.to_string())));
+    }
+
+    #[actix_web::test]
+    async fn test_redelivered_event_is_answered_once() {
+        let state = mock_state(&mock_apis());
+        let app = init_service(App::new().app_data(Data::clone(&state)).service(callback)).await;
+        let mut event = text_event("Uredelivered");
+        event["webhookEventId"] = json!("01HREDELIVERED");
+        for is_redelivery in [false, true] {
+            event["deliveryContext"] = json!({"isRedelivery": is_redelivery});
+            let request =
+                webhook_request(json!({"destination": "Ubot", "events": [event]})).to_request();
+            assert!(call_service(&app, request).await.status().is_success());
+        }
+        assert_eq!(state.usage.get("Uredelivered").await.requests, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_bad_signature_is_refused() {
+        let state = mock_state(&mock_apis());
+        let app = init_service(App::new().app_data(Data::clone(&state)).service(callback)).await;
+        let body = json!({"destination": "Ubot", "events": [text_event("Uforged")]});
+        let request = webhook_request(body)
+            .insert_header(("x-line-signature", "c2lnbmF0dXJl"))
+            .to_request();
+        assert_eq!(
+            call_service(&app, request).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(state.usage.get("Uforged").await.requests, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_follow_up_carries_the_conversation() {
+        let state = mock_state(&mock_apis());
+        let app = init_service(App::new().app_data(Data::clone(&state)).service(callback)).await;
+        let ask = |text: &str| {
+            let mut event = text_event("Umemory");
+            event["message"]["text"] = json!(text);
+            webhook_request(json!({"destination": "Ubot", "events": [event]})).to_request()
+        };
+        let prompt_of = |question: &str| {
+            PROMPTS
+                .lock()
+                .unwrap()
+                .iter()
+                .rev()
+                .find(|prompt| prompt.last().is_some_and(|q| q == question))
+                .cloned()
+                .expect("prompt sent")
+        };
+
+        assert!(call_service(&app, ask("Nick:> echo my name is Nok"))
+            .await
+            .status()
+            .is_success());
+        assert!(call_service(&app, ask("Nick:> echo what is my name?"))
+            .await
+            .status()
+            .is_success());
+        let prompt = prompt_of("echo what is my name?");
+        assert_eq!(
+            prompt,
+            vec![
+                "echo my name is Nok",
+                "my name is Nok",
+                "echo what is my name?"
+            ]
+        );
+
+        assert!(call_service(&app, ask("/reset"))
+            .await
+            .status()
+            .is_success());
+        assert!(call_service(&app, ask("Nick:> echo who am I?"))
+            .await
+            .status()
+            .is_success());
+        assert_eq!(prompt_of("echo who am I?"), vec!["echo who am I?"]);
+    }
+
+    #[actix_web::test]
+    async fn test_export_and_forget_on_unfollow() {
+        let state = mock_state(&mock_apis());
+        let app = init_service(App::new().app_data(Data::clone(&state)).service(callback)).await;
+        let post = |events: serde_json::Value| {
+            webhook_request(json!({"destination": "Ubot", "events": events})).to_request()
+        };
+        let request = post(json!([text_event("Uprivacy")]));
+        assert!(call_service(&app, request).await.status().is_success());
+        assert_eq!(state.conversations.turns("Uprivacy").await.len(), 2);
+
+        let mut export = text_event("Uprivacy");
+        export["message"]["text"] = json!("/export");
+        assert!(call_service(&app, post(json!([export])))
+            .await
+            .status()
+            .is_success());
+        let pushed = PUSHED.lock().unwrap().clone();
+        let body = pushed
+            .iter()
+            .find(|body| body["to"] == "Uprivacy")
+            .expect("export pushed");
+        let text = body["messages"][0]["text"].as_str().unwrap();
+        let exported: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!(exported["conversation"][1]["content"], "Pong");
+        assert_eq!(exported["usage"][0]["prompt_tokens"], 10);
+
+        let unfollow = json!({
+            "type": "unfollow",
+            "mode": "active",
+            "timestamp": 0,
+            "source": {"type": "user", "userId": "Uprivacy"}
+        });
+        assert!(call_service(&app, post(json!([unfollow])))
+            .await
+            .status()
+            .is_success());
+        assert!(state.conversations.turns("Uprivacy").await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_personal_data_is_redacted_for_openai() {
+        let logs = LogCapture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(logs.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let state = mock_state(&mock_apis());
+        let app = init_service(App::new().app_data(Data::clone(&state)).service(callback)).await;
+        let mut event = text_event("Uredacted");
+        event["message"]["text"] = json!("Nick:> echo mail somchai@example.com");
+        let request =
+            webhook_request(json!({"destination": "Ubot", "events": [event]})).to_request();
+        assert!(call_service(&app, request).await.status().is_success());
+
+        let questions = QUESTIONS.lock().unwrap().clone();
+        assert!(questions.contains(&"echo mail [EMAIL_1]".to_string()));
+        assert!(!questions.iter().any(|q| q.contains("somchai@example.com")));
+        // The answer got the address back, and the chat history keeps it
+        let turns = state.conversations.turns("Uredacted").await;
+        assert_eq!(turns[0].content, "echo mail somchai@example.com");
+        assert_eq!(turns[1].content, "mail somchai@example.com");
+        // Nor does it reach the logs
+        let logs = logs.contents();
+        assert!(logs.contains("message : 36 chars"));
+        assert!(!logs.contains("somchai@example.com"));
+    }
+
+    /// Collects what a test logs.
+    #[derive(Clone, Default)]
+    struct LogCapture(Arc<Mutex<Vec<u8>>>);
+
+    impl LogCapture {
+        fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl std::io::Write for LogCapture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for LogCapture {
+        type Writer = LogCapture;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_flagged_question_and_answer_are_blocked() {
+        let state = mock_state(&mock_apis());
+        let app = init_service(App::new().app_data(Data::clone(&state)).service(callback)).await;
+        let post = |user_id: &str, text: &str| {
+            let mut event = text_event(user_id);
+            event["message"]["text"] = json!(format!("Nick:> {text}"));
+            webhook_request(json!({"destination": "Ubot", "events": [event]})).to_request()
+        };
+
+        let request = post("Uflagged-input", "where do I buy a weapon");
+        assert!(call_service(&app, request).await.status().is_success());
+        // Never sent to the model
+        assert_eq!(state.usage.get("Uflagged-input").await.requests, 0);
+        assert!(state.conversations.turns("Uflagged-input").await.is_empty());
+
+        // The question passes, its answer "a weapon" does not
+        let request = post("Uflagged-output", "reverse nopaew a");
+        assert!(call_service(&app, request).await.status().is_success());
+        assert_eq!(state.usage.get("Uflagged-output").await.requests, 1);
+        let turns = state.conversations.turns("Uflagged-output").await;
+        assert_eq!(turns[1].content, "Sorry, I can't share that answer.");
+    }
+
+    #[actix_web::test]
+    async fn test_welcome_on_follow_and_join() {
+        let state = mock_state(&mock_apis());
+        let app = init_service(App::new().app_data(Data::clone(&state)).service(callback)).await;
+        let follow = json!({
+            "type": "follow",
+            "replyToken": "reply-follow",
+            "mode": "active",
+            "timestamp": 0,
+            "source": {"type": "user", "userId": "Uwelcome"}
+        });
+        let join = json!({
+            "type": "join",
+            "replyToken": "reply-join",
+            "mode": "active",
+            "timestamp": 0,
+            "source": {"type": "group", "groupId": "Cwelcome"}
+        });
+        let request =
+            webhook_request(json!({"destination": "Ubot", "events": [follow, join]})).to_request();
+        assert!(call_service(&app, request).await.status().is_success());
+
+        let replied = REPLIED.lock().unwrap().clone();
+        let reply = |token: &str| {
+            replied
+                .iter()
+                .find(|body| body["replyToken"] == token)
+                .map(|body| body["messages"][0].clone())
+                .expect("reply")
+        };
+        // Greeted by name, in Thai as the profile says
+        let greeting = reply("reply-follow");
+        assert_eq!(greeting["template"]["title"], "สวัสดี Somchai!");
+        assert_eq!(
+            greeting["quickReply"]["items"][0]["action"]["text"],
+            "Nick:> คุณทำอะไรได้บ้าง"
+        );
+        let join = reply("reply-join");
+        assert!(join["text"].as_str().unwrap().contains("type @"));
+    }
+}
+
+/// Throughput against local mocks, run with `cargo +nightly bench --features bench`.
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use actix_web::test::{call_service, init_service};
+    use actix_web::App;
+    use futures_util::future::join_all;
+    use serde_json::json;
+    use test::Bencher;
+
+    use super::tests::{mock_apis, mock_state, text_event, webhook_request};
+    use super::*;
+
+    /// Concurrent webhook calls per iteration, and events per call.
+    const CALLS: usize = 10;
+    const EVENTS_PER_CALL: usize = 10;
+
+    #[bench]
+    fn bench_webhook_throughput(b: &mut Bencher) {
+        let system = actix_web::rt::System::new();
+        let app = system.block_on(async {
+            let state = mock_state(&mock_apis());
+            init_service(App::new().app_data(state).service(callback)).await
+        });
+        let mut iteration = 0;
+        b.iter(|| {
+            iteration += 1;
+            let calls = (0..CALLS).map(|call| {
+                let events: Vec<_> = (0..EVENTS_PER_CALL)
+                    .map(|event| text_event(&format!("U{iteration}-{call}-{event}")))
+                    .collect();
+                let body = json!({"destination": "Ubot", "events": events});
+                call_service(&app, webhook_request(body).to_request())
+            });
+            for response in system.block_on(join_all(calls)) {
+                assert!(response.status().is_success());
+            }
+        });
     }
-    HttpResponse::Ok().finish()
 }
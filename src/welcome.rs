@@ -0,0 +1,300 @@
+//! Welcome
+//! # Note
+//! A user who adds the bot as a friend is greeted by name with a buttons template, whose
+//! buttons and quick replies start a conversation. When the bot joins a group or room it
+//! explains how to @mention it.
+//!
+//! Greetings are picked by the language of the user's profile, e.g. `th` for `th-TH`, and
+//! can be replaced with the JSON file of `welcome.file`:
+//! ```json
+//! {
+//!   "default_language": "en",
+//!   "greetings": [
+//!     {
+//!       "language": "en",
+//!       "title": "Hi {display_name}!",
+//!       "text": "Ask me anything: start your message with {chat_prompt}",
+//!       "starters": ["What can you do?", "/help"],
+//!       "join": "Hi everyone! Mention me with your question."
+//!     }
+//!   ]
+//! }
+//! ```
+//! Starters are sent with the chat prompt in front, unless they are commands.
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::messages::template_message::{Template, TemplateType};
+use crate::messages::{SendMessageType, TemplateMessage, TextMessage};
+use crate::objects::action::ActionType;
+use crate::objects::{Action, QuickReply};
+
+/// Characters of a buttons template title.
+const MAX_TITLE_CHARS: usize = 40;
+/// Characters of the text of a buttons template that has a title.
+const MAX_TEXT_CHARS: usize = 60;
+/// Buttons of a buttons template.
+const MAX_BUTTONS: usize = 4;
+/// Characters of an action label.
+const MAX_LABEL_CHARS: usize = 20;
+
+/// The welcome messages in one language.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Greeting {
+    /// Language tag, e.g. `en` or `th`.
+    pub language: String,
+    /// May contain `{display_name}`.
+    pub title: String,
+    /// May contain `{chat_prompt}`.
+    pub text: String,
+    /// Questions or commands offered as buttons and quick replies.
+    #[serde(default)]
+    pub starters: Vec<String>,
+    /// Sent when the bot joins a group or room, may contain `{chat_prompt}`.
+    pub join: String,
+}
+
+impl Greeting {
+    /// The greeting of a new friend, by name if known.
+    pub fn follow_message(&self, display_name: Option<&str>, chat_prompt: &str) -> SendMessageType {
+        let title = match display_name {
+            Some(name) => self.title.replace("{display_name}", name),
+            // "Hi {display_name}!" becomes "Hi!"
+            None => self
+                .title
+                .replace(" {display_name}", "")
+                .replace("{display_name}", ""),
+        };
+        let text = self.text.replace("{chat_prompt}", chat_prompt);
+        let starters: Vec<(String, String)> = self
+            .starters
+            .iter()
+            .map(|starter| {
+                let label: String = starter.chars().take(MAX_LABEL_CHARS).collect();
+                let text = if starter.starts_with('/') {
+                    starter.clone()
+                } else {
+                    format!("{chat_prompt} {starter}")
+                };
+                (label, text)
+            })
+            .collect();
+        let actions = starters
+            .iter()
+            .take(MAX_BUTTONS)
+            .map(|(label, text)| Action {
+                r#type: ActionType::Message { text: text.clone() },
+                label: Some(label.clone()),
+            })
+            .collect();
+        let quick_reply = (!starters.is_empty())
+            .then(|| QuickReply::messages(starters.iter().map(|(_, text)| text.clone())));
+        SendMessageType::TemplateMessage(TemplateMessage {
+            alt_text: format!("{title} {text}"),
+            template: Template {
+                r#type: TemplateType::Buttons {
+                    thumbnail_image_url: None,
+                    image_aspect_ratio: None,
+                    image_size: None,
+                    image_background_color: None,
+                    title: Some(title.chars().take(MAX_TITLE_CHARS).collect()),
+                    text: text.chars().take(MAX_TEXT_CHARS).collect(),
+                    default_action: None,
+                    actions,
+                },
+            },
+            quick_reply,
+        })
+    }
+
+    /// The explanation sent to a group or room the bot joined.
+    pub fn join_message(&self, chat_prompt: &str) -> SendMessageType {
+        SendMessageType::TextMessage(TextMessage {
+            text: self.join.replace("{chat_prompt}", chat_prompt),
+            emojis: None,
+            quote_token: None,
+            quick_reply: None,
+        })
+    }
+}
+
+/// Contents of the welcome file.
+#[derive(Debug, Deserialize)]
+struct WelcomeFile {
+    default_language: Option<String>,
+    greetings: Vec<Greeting>,
+}
+
+/// Greetings by language, and the language of users whose language has none.
+#[derive(Debug)]
+pub struct WelcomeRegistry {
+    greetings: Vec<Greeting>,
+    default_language: String,
+}
+
+impl WelcomeRegistry {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<WelcomeRegistry> {
+        let json = std::fs::read_to_string(path)?;
+        WelcomeRegistry::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> io::Result<WelcomeRegistry> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let file: WelcomeFile = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        for (i, greeting) in file.greetings.iter().enumerate() {
+            if greeting.language.trim().is_empty() {
+                return Err(invalid("greeting without a language".to_string()));
+            }
+            if file.greetings[..i]
+                .iter()
+                .any(|g| g.language.eq_ignore_ascii_case(&greeting.language))
+            {
+                return Err(invalid(format!(
+                    "duplicate greeting: {}",
+                    greeting.language
+                )));
+            }
+            if greeting.title.trim().is_empty() || greeting.text.trim().is_empty() {
+                return Err(invalid(format!(
+                    "empty title or text of {}",
+                    greeting.language
+                )));
+            }
+        }
+        let default_language = match file.default_language {
+            Some(language) => language,
+            None => file
+                .greetings
+                .first()
+                .map(|g| g.language.clone())
+                .ok_or_else(|| invalid("no greetings".to_string()))?,
+        };
+        let registry = WelcomeRegistry {
+            greetings: file.greetings,
+            default_language,
+        };
+        if registry.get(&registry.default_language).is_none() {
+            return Err(invalid(format!(
+                "unknown default language: {}",
+                registry.default_language
+            )));
+        }
+        Ok(registry)
+    }
+
+    /// The greeting in `language`, e.g. `zh-TW`, or else in its primary language `zh`.
+    pub fn get(&self, language: &str) -> Option<&Greeting> {
+        let primary = language.split(['-', '_']).next().unwrap_or(language);
+        let find = |language: &str| {
+            self.greetings
+                .iter()
+                .find(|g| g.language.eq_ignore_ascii_case(language))
+        };
+        find(language).or_else(|| find(primary))
+    }
+
+    /// The greeting for a profile language, the default one without a match.
+    pub fn for_language(&self, language: Option<&str>) -> &Greeting {
+        language
+            .and_then(|language| self.get(language))
+            .or_else(|| self.get(&self.default_language))
+            .unwrap_or(&self.greetings[0])
+    }
+}
+
+impl Default for WelcomeRegistry {
+    /// Greetings in English and Thai.
+    fn default() -> Self {
+        let greeting =
+            |language: &str, title: &str, text: &str, starters: &[&str], join: &str| Greeting {
+                language: language.to_string(),
+                title: title.to_string(),
+                text: text.to_string(),
+                starters: starters.iter().map(|s| s.to_string()).collect(),
+                join: join.to_string(),
+            };
+        WelcomeRegistry {
+            greetings: vec![
+                greeting(
+                    "en",
+                    "Hi {display_name}!",
+                    "Ask me anything, starting with {chat_prompt}",
+                    &["What can you do?", "Tell me a fun fact", "/help"],
+                    "Hi everyone! To ask me something, type @, pick me from the list and \
+                     add your question. Starting a message with {chat_prompt} works too. \
+                     Mention me with \"off\" to keep me quiet, and \"on\" to wake me up.",
+                ),
+                greeting(
+                    "th",
+                    "สวัสดี {display_name}!",
+                    "ถามอะไรก็ได้ โดยขึ้นต้นข้อความด้วย {chat_prompt}",
+                    &["คุณทำอะไรได้บ้าง", "เล่าเรื่องสนุกๆ", "/help"],
+                    "สวัสดีทุกคน! ถามฉันได้โดยพิมพ์ @ เลือกชื่อฉัน แล้วตามด้วยคำถาม \
+                     หรือขึ้นต้นข้อความด้วย {chat_prompt} ก็ได้ \
+                     แท็กฉันพร้อมคำว่า \"off\" ให้ฉันเงียบ และ \"on\" ให้ฉันกลับมา",
+                ),
+            ],
+            default_language: "en".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_greeting_by_language() {
+        let welcome = WelcomeRegistry::default();
+        assert_eq!(welcome.for_language(Some("th")).language, "th");
+        assert_eq!(welcome.for_language(Some("TH-th")).language, "th");
+        assert_eq!(welcome.for_language(Some("ja")).language, "en");
+        assert_eq!(welcome.for_language(None).language, "en");
+    }
+
+    #[test]
+    fn test_follow_message() {
+        let greeting = WelcomeRegistry::default().for_language(Some("en")).clone();
+        let message = greeting.follow_message(Some("Somchai"), "Nick:>");
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["type"], "template");
+        assert_eq!(value["template"]["type"], "buttons");
+        assert_eq!(value["template"]["title"], "Hi Somchai!");
+        assert_eq!(
+            value["template"]["actions"][0]["text"],
+            "Nick:> What can you do?"
+        );
+        assert_eq!(value["template"]["actions"][0]["label"], "What can you do?");
+        assert_eq!(value["template"]["actions"][2]["text"], "/help");
+        assert_eq!(value["quickReply"]["items"][1]["action"]["type"], "message");
+
+        let value = serde_json::to_value(greeting.follow_message(None, "Nick:>")).unwrap();
+        assert_eq!(value["template"]["title"], "Hi!");
+        let text = value["template"]["text"].as_str().unwrap();
+        assert!(text.chars().count() <= MAX_TEXT_CHARS);
+    }
+
+    #[test]
+    fn test_welcome_file() {
+        let json = r#"{
+            "greetings": [
+                {"language": "ja", "title": "こんにちは {display_name}", "text": "質問をどうぞ", "join": "@で呼んでね"}
+            ]
+        }"#;
+        let welcome = WelcomeRegistry::from_json(json).unwrap();
+        assert_eq!(welcome.for_language(Some("en")).language, "ja");
+        let message = welcome.for_language(None).join_message("Nick:>");
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["text"], "@で呼んでね");
+
+        let unknown_default = r#"{"default_language": "en", "greetings": []}"#;
+        assert!(WelcomeRegistry::from_json(unknown_default).is_err());
+        let duplicate = r#"{"greetings": [
+            {"language": "en", "title": "Hi", "text": "Ask", "join": "Hi"},
+            {"language": "EN", "title": "Hi", "text": "Ask", "join": "Hi"}
+        ]}"#;
+        assert!(WelcomeRegistry::from_json(duplicate).is_err());
+    }
+}